@@ -0,0 +1,37 @@
+use std::time::Instant;
+use bitcask::data::log_record_mod::decode_log_record_pos;
+use bitcask::index::btree::BTree;
+use bitcask::index::hash::HashIndex;
+use bitcask::index::indexer::Indexer;
+
+// 默认写入的 key 数量，可以通过环境变量 BITCASK_BENCH_KEYS 修改
+const DEFAULT_KEYS: u32 = 10_000_000;
+
+fn main() {
+    let n = std::env::var("BITCASK_BENCH_KEYS")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_KEYS);
+    let indexers: Vec<(&str, Box<dyn Indexer>)> = vec![
+        ("btree", Box::new(BTree::new())),
+        ("hash", Box::new(HashIndex::new())),
+    ];
+    // 位置信息的字段只在 crate 内可见，通过解码得到 file_id 为 1、offset 为 10、size 为 11 的位置信息
+    let pos = decode_log_record_pos(vec![1, 10, 11]).unwrap();
+    for (name, indexer) in indexers {
+        let now = Instant::now();
+        for i in 0..n {
+            indexer.put(format!("bitcask-rs-key-{:09}", i).into_bytes(), pos).unwrap();
+        }
+        let put_cost = now.elapsed();
+
+        // 以固定的步长打乱点查的顺序
+        let now = Instant::now();
+        for i in 0..n {
+            let key = format!("bitcask-rs-key-{:09}", (i as u64 * 7919 % n as u64) as u32);
+            assert!(indexer.get(key.into_bytes()).unwrap().is_some());
+        }
+        let get_cost = now.elapsed();
+        println!("{}: put {} keys cost {:?}, get cost {:?}", name, n, put_cost, get_cost);
+    }
+}
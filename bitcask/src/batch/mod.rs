@@ -5,17 +5,19 @@ mod utils;
 
 use crate::db::engine::Engine;
 use std::sync::Arc;
-use std::sync::Mutex;
+use parking_lot::Mutex;
 use std::collections::HashMap;
 use crate::errors::{AppErrors, AppResult};
 use crate::options::index_type::IndexType;
-use self::write_batch::WriteBatch;
+use self::write_batch::{SpillState, WriteBatch};
+pub(crate) use self::utils::{log_record_key_with_seq, parse_log_record_key};
+pub(crate) use self::write_batch::NON_TRANSACTION_SEQ_NO;
 use crate::options::write_batch_options::WriteBatchOptions;
 
 // 给Engine附加额外方法: batch系列
 impl Engine {
     /// 初始化 WriteBatch
-    pub fn new_write_batch(&self, options: WriteBatchOptions) -> AppResult<WriteBatch> {
+    pub fn new_write_batch(&self, options: WriteBatchOptions) -> AppResult<WriteBatch<'_>> {
         // 如果是 B+树 类型, 需要额外判断
         if self.options.index_type == IndexType::BPlusTree && !self.seq_file_exists && !self.is_initial {
             return Err(AppErrors::UnableToUseWriteBatch);
@@ -24,6 +26,7 @@ impl Engine {
             pending_writes: Arc::new(Mutex::new(HashMap::new())),
             engine: self,
             options,
+            spill_state: Mutex::new(SpillState::default()),
         })
     }
 }
\ No newline at end of file
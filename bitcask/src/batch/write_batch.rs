@@ -1,27 +1,48 @@
 use std::{
     collections::HashMap,
 };
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::Bytes;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use parking_lot::Mutex;
 use crate::data::log_record_mod::log_record::LogRecord;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
 use crate::db::engine::Engine;
 use crate::options::write_batch_options::WriteBatchOptions;
 use crate::errors::{AppResult, AppErrors};
 // use crate::index::bptree::BPlusTree; // B+树索引
-use crate::options::index_type::IndexType;
+use crate::index::indexer::IndexOp;
 use crate::data::log_record_mod::log_record_type::LogRecordType;
 use super::utils::{log_record_key_with_seq, parse_log_record_key};
 
 const TXN_FIN_KEY: &[u8] = "txn-fin".as_bytes();
 pub(crate) const NON_TRANSACTION_SEQ_NO: usize = 0usize;
+// 提交时每次从溢写的数据中读取并更新索引的数据条数
+const SPILL_APPLY_BATCH_SIZE: usize = 1024;
 
 /// 批量写操作，保证原子性
 pub struct WriteBatch<'a> {
     pub pending_writes: Arc<Mutex<HashMap<Vec<u8>, LogRecord>>>, // 暂存用户写入的数据
     pub engine: &'a Engine, // 涉及引用, 需要生命周期'a
     pub options: WriteBatchOptions,
+    pub(crate) spill_state: Mutex<SpillState>, // 暂存数据的大小，以及已经提前写入数据文件的数据
+}
+
+/// 批次中暂存数据的大小，以及溢写到数据文件中但尚未提交的数据
+/// 溢写的数据只记录每次溢写的数据文件区间，不在内存中保留 key，提交时再从数据文件中读取
+#[derive(Default)]
+pub(crate) struct SpillState {
+    pending_size: usize,           // 内存中暂存的 key 和 value 的总字节数
+    seq_no: Option<usize>,         // 第一次溢写时分配的事务序列号
+    spills: Vec<SpilledRange>,     // 每次溢写写入的数据文件区间，按写入顺序排列
+    spilled_size: usize,           // 已经溢写的数据的总字节数
+}
+
+/// 一次溢写连续写入同一个数据文件的区间
+struct SpilledRange {
+    file_id: u32,
+    offset: u64,
+    len: u64,
 }
 
 impl WriteBatch<'_> {
@@ -39,8 +60,7 @@ impl WriteBatch<'_> {
         };
 
         let mut pending_writes = self.pending_writes.lock();
-        pending_writes.insert(key.to_vec(), record);
-        Ok(())
+        self.stage(&mut pending_writes, record)
     }
 
     /// 批量操作删除数据
@@ -50,11 +70,12 @@ impl WriteBatch<'_> {
         }
 
         let mut pending_writes = self.pending_writes.lock();
-        // 如果数据不存在则直接返回
-        let index_pos = self.engine.index.get(key.to_vec());
-        if index_pos.is_none() {
-            if pending_writes.contains_key(&key.to_vec()) {
-                pending_writes.remove(&key.to_vec());
+        // 如果数据不存在则直接返回，批次已经溢写过时该 key 可能在溢写的数据中，仍然需要写入删除标识
+        let index_pos = self.engine.index_get(&key)?;
+        let spilled = self.spill_state.lock().seq_no.is_some();
+        if index_pos.is_none() && !spilled {
+            if let Some(old) = pending_writes.remove(&key.to_vec()) {
+                self.spill_state.lock().pending_size -= old.key.len() + old.value.len();
             }
             return Ok(());
         }
@@ -65,74 +86,288 @@ impl WriteBatch<'_> {
             value: Default::default(),
             rec_type: LogRecordType::DELETED,
         };
-        pending_writes.insert(key.to_vec(), record);
+        self.stage(&mut pending_writes, record)
+    }
+
+    // 暂存一条数据，开启溢写且暂存数据超过阈值时，将暂存的数据提前写入数据文件
+    fn stage(&self, pending_writes: &mut HashMap<Vec<u8>, LogRecord>, record: LogRecord) -> AppResult<()> {
+        let mut spill_state = self.spill_state.lock();
+        spill_state.pending_size += record.key.len() + record.value.len();
+        if let Some(old) = pending_writes.insert(record.key.clone(), record) {
+            spill_state.pending_size -= old.key.len() + old.value.len();
+        }
+
+        if self.options.spill_threshold > 0 && spill_state.pending_size >= self.options.spill_threshold {
+            self.spill(pending_writes, &mut spill_state)?;
+        }
+        Ok(())
+    }
+
+    // 将暂存的数据以事务序列号写入数据文件，内存中只保留其位置信息，
+    // 提交时再写入事务完成的标识，未提交的数据在重启加载索引时会被忽略
+    fn spill(&self, pending_writes: &mut HashMap<Vec<u8>, LogRecord>, spill_state: &mut SpillState) -> AppResult<()> {
+        // 登记溢写的批次和写入数据期间持有读锁，merge 在检查溢写的批次和切换活跃文件期间持有写锁，
+        // 溢写的数据要么被 merge 拒绝执行，要么只会写入切换之后的活跃文件
+        let _write_guard = self.engine.write_lock.read();
+        let seq_no = match spill_state.seq_no {
+            Some(seq_no) => seq_no,
+            None => {
+                // 第一次溢写时分配事务序列号，并阻止 merge 丢弃这些尚未被索引的数据
+                self.engine.spilled_batches.fetch_add(1, Ordering::SeqCst);
+                let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
+                spill_state.seq_no = Some(seq_no);
+                seq_no
+            }
+        };
+
+        let records: Vec<LogRecord> = pending_writes
+            .drain()
+            .map(|(key, item)| LogRecord {
+                key: log_record_key_with_seq(key, seq_no),
+                value: item.value,
+                rec_type: item.rec_type,
+            })
+            .collect();
+        spill_state.pending_size = 0;
+
+        // 一个批次的数据连续写入同一个数据文件，只需要记录起始位置和总长度
+        let positions = self.engine.append_log_records(&records)?;
+        if let Some(first) = positions.first() {
+            let len: u64 = positions.iter().map(|pos| pos.size as u64).sum();
+            spill_state.spills.push(SpilledRange {
+                file_id: first.file_id,
+                offset: first.offset,
+                len,
+            });
+            spill_state.spilled_size += len as usize;
+        }
+        Ok(())
+    }
+
+    // 按写入顺序重新读取溢写的数据，每读取 SPILL_APPLY_BATCH_SIZE 条更新一次索引，
+    // 同一个 key 之后的溢写覆盖之前的位置，被覆盖的数据由 update_index 计入可以回收的空间
+    fn apply_spilled(&self, spill_state: &SpillState) -> AppResult<()> {
+        for range in spill_state.spills.iter() {
+            let end = range.offset + range.len;
+            let mut offset = range.offset;
+            while offset < end {
+                let mut ops = Vec::with_capacity(SPILL_APPLY_BATCH_SIZE);
+                let mut deleted_size = 0;
+                {
+                    // 只在读取期间持有数据文件的读锁
+                    let active_file = self.engine.active_file.read();
+                    let older_files = self.engine.older_files.read();
+                    let data_file = match active_file.get_file_id() == range.file_id {
+                        true => &*active_file,
+                        false => match older_files.get(&range.file_id) {
+                            Some(data_file) => data_file,
+                            None => return Err(AppErrors::DataFileNotFound),
+                        },
+                    };
+                    while offset < end && ops.len() < SPILL_APPLY_BATCH_SIZE {
+                        let read_record = data_file.read_log_record(offset)?;
+                        let (key, _) = parse_log_record_key(read_record.record.key);
+                        let pos = LogRecordPos {
+                            file_id: range.file_id,
+                            offset,
+                            size: read_record.size as u32,
+                        };
+                        match read_record.record.rec_type {
+                            LogRecordType::NORMAL => ops.push(IndexOp::Put(key, pos)),
+                            LogRecordType::DELETED => {
+                                deleted_size += pos.size as usize;
+                                ops.push(IndexOp::Delete(key));
+                            }
+                            _ => {}
+                        }
+                        offset += read_record.size as u64;
+                    }
+                }
+                self.update_index(ops, deleted_size)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 批量更新内存索引，删除标识本身以及被覆盖的旧数据都计入可以回收的空间
+    fn update_index(&self, ops: Vec<IndexOp>, deleted_size: usize) -> AppResult<()> {
+        self.engine.reclaim_size.fetch_add(deleted_size, Ordering::SeqCst);
+        for old_pos in self.engine.index.batch_update(ops)?.into_iter().flatten() {
+            self.engine
+                .reclaim_size
+                .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        }
         Ok(())
     }
 
     /// 提交数据，将数据写到文件当中，并更新内存索引
     pub fn commit(&self) -> AppResult<()> {
         let mut pending_writes = self.pending_writes.lock();
-        if pending_writes.len() == 0 {
+        let mut spill_state = self.spill_state.lock();
+        if pending_writes.is_empty() && spill_state.seq_no.is_none() {
             return Ok(());
         }
         if pending_writes.len() > self.options.max_batch_num {
             return Err(AppErrors::ExceedMaxBatchNum);
         }
+        if spill_state.pending_size > self.options.max_batch_size {
+            return Err(AppErrors::ExceedMaxBatchSize);
+        }
 
         // 加锁保证事务提交串行化
         let _lock = self.engine.batch_commit_lock.lock();
+        // 追加数据和更新索引期间不能有 merge 的压缩过滤器修改索引
+        let _write_guard = self.engine.write_lock.read();
 
-        // 获取全局事务序列号
-        let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
+        // 获取全局事务序列号，已经溢写过的批次沿用溢写时的序列号
+        let seq_no = match spill_state.seq_no {
+            Some(seq_no) => seq_no,
+            None => self.engine.seq_no.fetch_add(1, Ordering::SeqCst),
+        };
 
-        let mut positions = HashMap::new();
-        // 开始写数据到数据文件当中
+        // 编码事务中的所有数据
+        let mut records = Vec::with_capacity(pending_writes.len() + 1);
         for (_, item) in pending_writes.iter() {
-            let mut record = LogRecord {
+            records.push(LogRecord {
                 key: log_record_key_with_seq(item.key.clone(), seq_no),
                 value: item.value.clone(),
                 rec_type: item.rec_type,
-            };
-
-            let pos = self.engine.append_log_record(&mut record)?;
-            positions.insert(item.key.clone(), pos);
+            });
         }
 
-        // 写最后一条标识事务完成的数据
-        let mut finish_record = LogRecord {
+        // 最后一条标识事务完成的数据
+        records.push(LogRecord {
             key: log_record_key_with_seq(TXN_FIN_KEY.to_vec(), seq_no),
             value: Default::default(),
             rec_type: LogRecordType::TXNFINISHED,
-        };
-        self.engine.append_log_record(&mut finish_record)?;
+        });
+
+        // 整个批次一次写入到数据文件当中
+        let record_positions = self.engine.append_log_records(&records)?;
 
         // 如果配置了持久化，则 sync
         if self.options.sync_writes {
             self.engine.sync()?;
         }
 
-        // 数据全部写完之后更新内存索引
-        for (_, item) in pending_writes.iter() {
-            if item.rec_type == LogRecordType::NORMAL {
-                let record_pos = positions.get(&item.key).unwrap();
-                if let Some(old_pos) = self.engine.index.put(item.key.clone(), *record_pos) {
-                    self.engine
-                        .reclaim_size
-                        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
-                }
-            }
-            if item.rec_type == LogRecordType::DELETED {
-                if let Some(old_pos) = self.engine.index.delete(item.key.clone()) {
-                    self.engine
-                        .reclaim_size
-                        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        // 数据全部写完之后更新内存索引，先分批应用溢写的数据，再用内存中暂存的较新的数据覆盖
+        self.apply_spilled(&spill_state)?;
+        let mut ops = Vec::with_capacity(pending_writes.len());
+        let mut deleted_size = 0;
+        for ((_, item), pos) in pending_writes.iter().zip(record_positions.iter()) {
+            match item.rec_type {
+                LogRecordType::NORMAL => ops.push(IndexOp::Put(item.key.clone(), *pos)),
+                LogRecordType::DELETED => {
+                    deleted_size += pos.size as usize;
+                    ops.push(IndexOp::Delete(item.key.clone()));
                 }
+                _ => {}
             }
         }
+        self.update_index(ops, deleted_size)?;
 
         // 清空暂存数据
         pending_writes.clear();
+        spill_state.pending_size = 0;
+        spill_state.spills.clear();
+        spill_state.spilled_size = 0;
+        if spill_state.seq_no.take().is_some() {
+            self.engine.spilled_batches.fetch_sub(1, Ordering::SeqCst);
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl Drop for WriteBatch<'_> {
+    // 溢写之后没有提交的数据永远不会生效，作为可以回收的空间，并允许 merge 继续执行
+    fn drop(&mut self) {
+        let mut spill_state = self.spill_state.lock();
+        if spill_state.seq_no.take().is_some() {
+            self.engine
+                .reclaim_size
+                .fetch_add(spill_state.spilled_size, Ordering::SeqCst);
+            self.engine.spilled_batches.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::options::options::Options;
+    use crate::utils::rand_kv::{get_test_key, get_test_value};
+    use super::*;
+
+    #[test]
+    fn test_write_batch_max_size() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-batch-size");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let mut wb_opts = WriteBatchOptions::default();
+        wb_opts.max_batch_size = 1024;
+        let wb = engine.new_write_batch(wb_opts).expect("failed to create write batch");
+        for i in 0..100 {
+            assert!(wb.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert_eq!(AppErrors::ExceedMaxBatchSize, wb.commit().err().unwrap());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_spill() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-batch-spill");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let mut wb_opts = WriteBatchOptions::default();
+        wb_opts.spill_threshold = 4 * 1024;
+        let wb = engine.new_write_batch(wb_opts).expect("failed to create write batch");
+        for i in 0..10000 {
+            assert!(wb.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        assert!(wb.delete(get_test_key(0)).is_ok());
+
+        // 溢写的数据在提交之前不可见，且不能 merge
+        assert!(wb.pending_writes.lock().len() < 100);
+        assert_eq!(AppErrors::KeyNotFound, engine.get(get_test_key(1)).err().unwrap());
+        let active_file_id = engine.active_file.read().get_file_id();
+        assert_eq!(AppErrors::WriteBatchSpilling, engine.merge().err().unwrap());
+        // 被拒绝的 merge 不会切换活跃文件
+        assert_eq!(engine.active_file.read().get_file_id(), active_file_id);
+
+        // 已经溢写的 key 再次写入，提交之后以最后一次写入为准
+        assert!(wb.put(get_test_key(2), Bytes::from("new value")).is_ok());
+
+        assert!(wb.commit().is_ok());
+        assert_eq!(AppErrors::KeyNotFound, engine.get(get_test_key(0)).err().unwrap());
+        assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+        assert_eq!(Bytes::from("new value"), engine.get(get_test_key(2)).unwrap());
+        assert_eq!(engine.list_keys().unwrap().len(), 9999);
+        assert!(engine.merge().is_ok());
+
+        // 溢写之后没有提交的批次，重启之后不可见
+        let mut wb_opts2 = WriteBatchOptions::default();
+        wb_opts2.spill_threshold = 4 * 1024;
+        let wb2 = engine.new_write_batch(wb_opts2).expect("failed to create write batch");
+        for i in 10000..20000 {
+            assert!(wb2.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        std::mem::drop(wb2);
+        std::mem::drop(wb);
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().unwrap().len(), 9999);
+        assert_eq!(get_test_value(9999), engine2.get(get_test_key(9999)).unwrap());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
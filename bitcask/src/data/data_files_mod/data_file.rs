@@ -1,10 +1,17 @@
-use std::{sync::Arc, path::PathBuf};
+use std::{os::unix::io::RawFd, sync::Arc, path::PathBuf};
+use bytes::{Buf, Bytes, BytesMut};
 use parking_lot::RwLock;
+use log::warn;
+use prost::{decode_length_delimiter, length_delimiter_len};
 use crate::fio::io_manager::{IOManager, new_io_manager};
 use super::utils::get_data_file_name;
-use crate::errors::AppResult;
+use crate::errors::{AppErrors, AppResult};
 use crate::options::io_type::IOType;
-use super::utils::{HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME};
+use crate::data::log_record_mod::{max_log_record_header_size, ReadLogRecord};
+use crate::data::log_record_mod::log_record::LogRecord;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::data::log_record_mod::log_record_type::LogRecordType;
+use super::utils::{HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME};
 
 // 数据文件
 pub struct DataFile {
@@ -19,7 +26,7 @@ impl DataFile {
         // 根据 path 和 id 构造出完整的文件名称
         let file_name: PathBuf = get_data_file_name(dir_path, file_id);
         // 初始化 io manager (理解 动态分发, 静态分发)
-        let io_manager: Box<dyn IOManager> = new_io_manager(file_name, io_type);
+        let io_manager: Box<dyn IOManager> = new_io_manager(file_name, io_type)?;
 
         Ok(Self {
             file_id: Arc::new(RwLock::new(file_id)),
@@ -31,7 +38,7 @@ impl DataFile {
     /// 新建或打开 hint 索引文件
     pub fn new_hint_file(dir_path: PathBuf) -> AppResult<Self> {
         let file_name: PathBuf = dir_path.join(HINT_FILE_NAME);
-        let io_manager: Box<dyn IOManager> = new_io_manager(file_name, IOType::StandardFIO);
+        let io_manager: Box<dyn IOManager> = new_io_manager(file_name, IOType::StandardFIO)?;
 
         Ok(Self {
             file_id: Arc::new(RwLock::new(0)),
@@ -43,7 +50,7 @@ impl DataFile {
     /// 新建或打开标识 merge 完成的文件
     pub fn new_merge_fin_file(dir_path: PathBuf) -> AppResult<DataFile> {
         let file_name = dir_path.join(MERGE_FINISHED_FILE_NAME);
-        let io_manager = new_io_manager(file_name, IOType::StandardFIO);
+        let io_manager = new_io_manager(file_name, IOType::StandardFIO)?;
 
         Ok(DataFile {
             file_id: Arc::new(RwLock::new(0)),
@@ -51,117 +58,177 @@ impl DataFile {
             io_manager,
         })
     }
-}
 
-// impl DataFile {
-
-
-//     /// 新建或打开存储事务序列号的文件
-//     pub fn new_seq_no_file(dir_path: PathBuf) -> Result<DataFile> {
-//         let file_name = dir_path.join(SEQ_NO_FILE_NAME);
-//         let io_manager = new_io_manager(file_name, IOType::StandardFIO);
-
-//         Ok(DataFile {
-//             file_id: Arc::new(RwLock::new(0)),
-//             write_off: Arc::new(RwLock::new(0)),
-//             io_manager,
-//         })
-//     }
-
-//     pub fn file_size(&self) -> u64 {
-//         self.io_manager.size()
-//     }
-
-//     pub fn get_write_off(&self) -> u64 {
-//         let read_guard = self.write_off.read();
-//         *read_guard
-//     }
-
-//     pub fn set_write_off(&self, offset: u64) {
-//         let mut write_guard = self.write_off.write();
-//         *write_guard = offset;
-//     }
-
-//     pub fn get_file_id(&self) -> u32 {
-//         let read_guard = self.file_id.read();
-//         *read_guard
-//     }
-
-//     /// 根据 offset 从数据文件中读取 LogRecord
-//     pub fn read_log_record(&self, offset: u64) -> Result<ReadLogRecord> {
-//         // 先读取出 header 部分的数据
-//         let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
-
-//         self.io_manager.read(&mut header_buf, offset)?;
-
-//         // 取出 type，在第一个字节
-//         let rec_type = header_buf.get_u8();
-
-//         // 取出 key 和 value 的长度
-//         let key_size = decode_length_delimiter(&mut header_buf).unwrap();
-//         let value_size = decode_length_delimiter(&mut header_buf).unwrap();
-
-//         // 如果 key 和 value 均为空，则说明读取到了文件的末尾，直接返回
-//         if key_size == 0 && value_size == 0 {
-//             return Err(Errors::ReadDataFileEOF);
-//         }
-
-//         // 获取实际的 header 大小
-//         let actual_header_size =
-//             length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
-
-//         // 读取实际的 key 和 value，最后的 4 个字节是 crc 校验值
-//         let mut kv_buf = BytesMut::zeroed(key_size + value_size + 4);
-//         self.io_manager
-//             .read(&mut kv_buf, offset + actual_header_size as u64)?;
-
-//         // 构造 LogRecord
-//         let log_record = LogRecord {
-//             key: kv_buf.get(..key_size).unwrap().to_vec(),
-//             value: kv_buf.get(key_size..kv_buf.len() - 4).unwrap().to_vec(),
-//             rec_type: LogRecordType::from_u8(rec_type),
-//         };
-
-//         // 向前移动到最后的 4 个字节，就是 crc 的值
-//         kv_buf.advance(key_size + value_size);
-
-//         if kv_buf.get_u32() != log_record.get_crc() {
-//             return Err(Errors::InvalidLogRecordCrc);
-//         }
-
-//         // 构造结果并返回
-//         Ok(ReadLogRecord {
-//             record: log_record,
-//             size: actual_header_size + key_size + value_size + 4,
-//         })
-//     }
-
-//     pub fn write(&self, buf: &[u8]) -> Result<usize> {
-//         let n_bytes = self.io_manager.write(buf)?;
-//         // 更新 write_off 字段
-//         let mut write_off = self.write_off.write();
-//         *write_off += n_bytes as u64;
-
-//         Ok(n_bytes)
-//     }
-
-//     /// 写 hint 索引到文件当中
-//     pub fn write_hint_record(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<()> {
-//         let hint_record = LogRecord {
-//             key,
-//             value: pos.encode(),
-//             rec_type: LogRecordType::NORMAL,
-//         };
-//         let enc_record = hint_record.encode();
-//         self.write(&enc_record)?;
-//         Ok(())
-//     }
-
-//     pub fn sync(&self) -> Result<()> {
-//         self.io_manager.sync()
-//     }
-
-//     pub fn set_io_manager(&mut self, dir_path: PathBuf, io_type: IOType) {
-//         self.io_manager = new_io_manager(get_data_file_name(dir_path, self.get_file_id()), io_type);
-//     }
-// }
+    /// 新建或打开存储事务序列号的文件
+    pub fn new_seq_no_file(dir_path: PathBuf) -> AppResult<DataFile> {
+        let file_name = dir_path.join(SEQ_NO_FILE_NAME);
+        let io_manager = new_io_manager(file_name, IOType::StandardFIO)?;
+
+        Ok(DataFile {
+            file_id: Arc::new(RwLock::new(0)),
+            write_off: Arc::new(RwLock::new(0)),
+            io_manager,
+        })
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.io_manager.size()
+    }
+
+    pub fn get_write_off(&self) -> u64 {
+        let read_guard = self.write_off.read();
+        *read_guard
+    }
+
+    pub fn set_write_off(&self, offset: u64) {
+        let mut write_guard = self.write_off.write();
+        *write_guard = offset;
+    }
+
+    pub fn get_file_id(&self) -> u32 {
+        let read_guard = self.file_id.read();
+        *read_guard
+    }
+
+    /// 根据 offset 从数据文件中读取 LogRecord
+    /// 读到文件末尾或者预分配的空间时返回 ReadDataFileEOF，数据不完整或者损坏时返回 InvalidLogRecordCrc
+    pub fn read_log_record(&self, offset: u64) -> AppResult<ReadLogRecord> {
+        // 先读取出 header 部分的数据，文件末尾不足一个 header 时只读取剩余的部分
+        let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
+        let n = self.io_manager.read(&mut header_buf, offset)?;
+        if n == 0 {
+            return Err(AppErrors::ReadDataFileEOF);
+        }
+        header_buf.truncate(n);
+
+        // 取出 type，在第一个字节
+        let rec_type = header_buf.get_u8();
+
+        // 取出 key 和 value 的长度
+        let key_size = decode_length_delimiter(&mut header_buf).map_err(|_| AppErrors::InvalidLogRecordCrc)?;
+        let value_size = decode_length_delimiter(&mut header_buf).map_err(|_| AppErrors::InvalidLogRecordCrc)?;
+
+        // 如果 key 和 value 均为空，则说明读取到了文件的末尾，直接返回
+        if key_size == 0 && value_size == 0 {
+            return Err(AppErrors::ReadDataFileEOF);
+        }
+
+        // 获取实际的 header 大小
+        let actual_header_size = length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
+
+        // 读取实际的 key 和 value，最后的 4 个字节是 crc 校验值，读取的数据不完整说明写入时发生了中断
+        let mut kv_buf = BytesMut::zeroed(key_size + value_size + 4);
+        let n = self.io_manager.read(&mut kv_buf, offset + actual_header_size as u64)?;
+        if n < kv_buf.len() {
+            return Err(AppErrors::InvalidLogRecordCrc);
+        }
+
+        // 构造 LogRecord
+        let log_record = LogRecord {
+            key: kv_buf.get(..key_size).unwrap().to_vec(),
+            value: kv_buf.get(key_size..kv_buf.len() - 4).unwrap().to_vec(),
+            rec_type: LogRecordType::from_u8(rec_type),
+        };
+
+        // 向前移动到最后的 4 个字节，就是 crc 的值
+        kv_buf.advance(key_size + value_size);
+
+        if kv_buf.get_u32() != log_record.get_crc() {
+            return Err(AppErrors::InvalidLogRecordCrc);
+        }
+
+        // 构造结果并返回
+        Ok(ReadLogRecord {
+            record: log_record,
+            size: actual_header_size + key_size + value_size + 4,
+        })
+    }
+
+    pub fn write(&self, buf: &[u8]) -> AppResult<usize> {
+        let n_bytes = self.io_manager.write(buf)?;
+        // 更新 write_off 字段
+        let mut write_off = self.write_off.write();
+        *write_off += n_bytes as u64;
+
+        Ok(n_bytes)
+    }
+
+    /// 写 hint 索引到文件当中
+    pub fn write_hint_record(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<()> {
+        let hint_record = LogRecord {
+            key,
+            value: pos.encode(),
+            rec_type: LogRecordType::NORMAL,
+        };
+        let enc_record = hint_record.encode();
+        self.write(&enc_record)?;
+        Ok(())
+    }
+
+    pub fn sync(&self) -> AppResult<()> {
+        self.io_manager.sync()
+    }
+
+    /// 一次写入多个字节数组，并更新写偏移
+    pub fn write_vectored(&self, bufs: &[&[u8]]) -> AppResult<usize> {
+        let n_bytes = self.io_manager.write_vectored(bufs)?;
+        // 更新 write_off 字段
+        let mut write_off = self.write_off.write();
+        *write_off += n_bytes as u64;
+
+        Ok(n_bytes)
+    }
+
+    /// 从数据文件的给定位置读取数据
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> AppResult<usize> {
+        self.io_manager.read(buf, offset)
+    }
+
+    /// 预分配数据文件的磁盘空间，避免追加写导致文件碎片化
+    pub fn preallocate(&self, size: u64) -> AppResult<()> {
+        self.io_manager.preallocate(size)
+    }
+
+    /// 将数据文件截断到当前写偏移，去掉预分配但没有写入数据的空间
+    pub fn truncate_to_write_off(&self) -> AppResult<()> {
+        let write_off = *self.write_off.read();
+        if self.io_manager.size() == write_off {
+            return Ok(());
+        }
+        self.io_manager.truncate(write_off)
+    }
+
+    /// 从头扫描数据文件，返回最后一条完整数据的末尾，预分配的空间中填充的 0 会被识别为文件末尾
+    /// 崩溃时末尾的数据可能只写入了一部分，遇到第一条无法解码的数据就停止，之后的内容由调用方截断
+    pub fn scan_write_off(&self) -> AppResult<u64> {
+        let mut offset = 0;
+        loop {
+            match self.read_log_record(offset) {
+                Ok(log_record) => offset += log_record.size as u64,
+                Err(AppErrors::ReadDataFileEOF) => break,
+                Err(AppErrors::InvalidLogRecordCrc) => {
+                    warn!("data file {} has an incomplete record at offset {}, truncate it", self.get_file_id(), offset);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(offset)
+    }
+
+    /// 零拷贝读取数据文件给定位置的数据，只有通过 mmap 打开的数据文件支持
+    pub fn read_view(&self, offset: u64, len: usize) -> Option<Bytes> {
+        self.io_manager.read_view(offset, len)
+    }
+
+    /// 切换数据文件的 IO 类型，打开失败时保留原来的 IO 类型
+    pub fn set_io_manager(&mut self, dir_path: PathBuf, io_type: IOType) -> AppResult<()> {
+        self.io_manager = new_io_manager(get_data_file_name(dir_path, self.get_file_id()), io_type)?;
+        Ok(())
+    }
+
+    /// 获取可以提交给 io_uring 批量读取的文件描述符
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        self.io_manager.raw_fd()
+    }
+}
@@ -2,7 +2,7 @@ use bytes::BytesMut;
 use prost::encoding::encode_varint;
 
 /// 数据位置索引信息，描述数据存储到了哪个位置
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct LogRecordPos {
     pub(crate) file_id: u32, // 文件 id，表示将数据存储到了哪个文件当中
     pub(crate) offset: u64,  // 偏移，表示将数据存储到了数据文件中的哪个位置
@@ -10,6 +10,17 @@ pub struct LogRecordPos {
 }
 
 impl LogRecordPos {
+    /// 不指向任何数据的位置信息，只需要 key 的迭代器返回这个位置，不能用来读取数据
+    pub(crate) const UNRESOLVED: LogRecordPos = LogRecordPos {
+        file_id: u32::MAX,
+        offset: u64::MAX,
+        size: 0,
+    };
+
+    pub(crate) fn is_unresolved(&self) -> bool {
+        self.file_id == u32::MAX && self.offset == u64::MAX
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let mut buf: BytesMut = BytesMut::new();
         encode_varint(self.file_id as u64, &mut buf);
@@ -2,16 +2,14 @@ pub mod log_record_type;
 pub mod log_record_pos;
 pub mod log_record;
 
-use bytes::{BufMut, BytesMut};
-use prost::{length_delimiter_len, encoding::{decode_varint}};
+use std::ops::Range;
+use bytes::{BufMut, Bytes, BytesMut};
+use prost::{decode_length_delimiter, length_delimiter_len, encoding::{decode_varint}};
+use crate::errors::{AppErrors, AppResult};
+use log::error;
 use self::log_record_pos::LogRecordPos;
 use self::log_record::LogRecord;
-
-/// 暂存事务数据信息
-pub struct TransactionRecord {
-    pub(crate) record: LogRecord,
-    pub(crate) pos: LogRecordPos,
-}
+use self::log_record_type::LogRecordType;
 
 /// 从数据文件中读取的 log_record 信息，包含其 size
 #[derive(Debug)]
@@ -22,29 +20,79 @@ pub struct ReadLogRecord {
 
 /// 获取 LogRecord header 部分的最大长度
 pub fn max_log_record_header_size() -> usize {
-    std::mem::size_of::<u8>() + length_delimiter_len(std::u32::MAX as usize) * 2
+    std::mem::size_of::<u8>() + length_delimiter_len(u32::MAX as usize) * 2
 }
 
-/// 解码 LogRecordPos
-pub fn decode_log_record_pos(pos: Vec<u8>) -> LogRecordPos {
+/// 解码 LogRecordPos，数据不完整或者损坏时返回错误
+pub fn decode_log_record_pos(pos: Vec<u8>) -> AppResult<LogRecordPos> {
     let mut buf = BytesMut::new();
     buf.put_slice(&pos);
 
-    let fid: u64 = match decode_varint(&mut buf) {
-        Ok(fid) => fid,
-        Err(e) => panic!("decode log record pos err: {}", e),
-    };
-    let offset: u64 = match decode_varint(&mut buf) {
-        Ok(offset) => offset,
-        Err(e) => panic!("decode log record pos err: {}", e),
-    };
-    let size: u64 = match decode_varint(&mut buf) {
-        Ok(size) => size,
-        Err(e) => panic!("decode log record pos err: {}", e),
+    let mut decode = || match decode_varint(&mut buf) {
+        Ok(v) => Ok(v),
+        Err(e) => {
+            error!("decode log record pos err: {}", e);
+            Err(AppErrors::InvalidLogRecordCrc)
+        }
     };
-    LogRecordPos {
+    let fid = decode()?;
+    let offset = decode()?;
+    let size = decode()?;
+    Ok(LogRecordPos {
         file_id: fid as u32,
         offset,
         size: size as u32,
+    })
+}
+
+/// 从一条完整编码的数据中解码 LogRecord，并校验 crc
+pub fn decode_log_record(buf: &[u8]) -> AppResult<LogRecord> {
+    let (rec_type, key_range, value_range) = decode_log_record_layout(buf)?;
+    Ok(LogRecord {
+        key: buf[key_range].to_vec(),
+        value: buf[value_range].to_vec(),
+        rec_type,
+    })
+}
+
+/// 从一条完整编码的数据中解码类型和 value，并校验 crc
+/// 返回的 value 和 buf 共享内存，不拷贝数据
+pub fn decode_log_record_value(buf: Bytes) -> AppResult<(LogRecordType, Bytes)> {
+    let (rec_type, _, value_range) = decode_log_record_layout(&buf)?;
+    Ok((rec_type, buf.slice(value_range)))
+}
+
+// 解析编码数据中 key 和 value 的位置，并校验 crc
+fn decode_log_record_layout(buf: &[u8]) -> AppResult<(LogRecordType, Range<usize>, Range<usize>)> {
+    if buf.is_empty() {
+        return Err(AppErrors::ReadDataFileEOF);
+    }
+
+    // 取出 type，以及 key 和 value 的长度
+    let rec_type = buf[0];
+    let mut header_buf = &buf[1..];
+    let key_size = decode_length_delimiter(&mut header_buf).map_err(|_| AppErrors::InvalidLogRecordCrc)?;
+    let value_size = decode_length_delimiter(&mut header_buf).map_err(|_| AppErrors::InvalidLogRecordCrc)?;
+
+    // 如果 key 和 value 均为空，则说明读取到了文件的末尾
+    if key_size == 0 && value_size == 0 {
+        return Err(AppErrors::ReadDataFileEOF);
+    }
+
+    let header_size = 1 + length_delimiter_len(key_size) + length_delimiter_len(value_size);
+    let kv_end = header_size + key_size + value_size;
+    if buf.len() < kv_end + 4 {
+        return Err(AppErrors::InvalidLogRecordCrc);
+    }
+
+    // 最后的 4 个字节是 crc 的值，由 header、key 和 value 计算得到
+    let crc = u32::from_be_bytes(buf[kv_end..kv_end + 4].try_into().unwrap());
+    if crc != crc32fast::hash(&buf[..kv_end]) {
+        return Err(AppErrors::InvalidLogRecordCrc);
     }
+    Ok((
+        LogRecordType::from_u8(rec_type),
+        header_size..header_size + key_size,
+        header_size + key_size..kv_end,
+    ))
 }
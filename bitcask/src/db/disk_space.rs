@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use log::error;
+use parking_lot::Mutex;
+use crate::errors::{AppErrors, AppResult};
+use crate::utils::file::available_disk_size;
+
+// 上次获取剩余空间之后累计写入超过该字节数时重新获取
+const DISK_SPACE_REFRESH_BYTES: u64 = 4 * 1024 * 1024;
+// 上次获取剩余空间之后超过该时间时重新获取，其他进程也会占用磁盘空间
+const DISK_SPACE_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 数据目录所在磁盘的剩余空间
+/// 缓存上次获取的结果并累计之后写入的字节数，不需要每次写入都调用 statvfs
+pub(crate) struct DiskSpace {
+    dir_path: PathBuf,
+    reserve_size: u64, // 需要保留的空间，为 0 时不检查
+    state: Mutex<Option<DiskSpaceState>>,
+}
+
+struct DiskSpaceState {
+    available: u64,        // 上次获取的剩余空间
+    written: u64,          // 上次获取之后累计写入的字节数
+    refreshed_at: Instant, // 上次获取的时间
+}
+
+impl DiskSpace {
+    pub(crate) fn new(dir_path: PathBuf, reserve_size: u64) -> Self {
+        Self {
+            dir_path,
+            reserve_size,
+            state: Mutex::new(None),
+        }
+    }
+
+    /// 判断写入 size 个字节之后，剩余空间是否仍然不低于保留值
+    pub(crate) fn check(&self, size: u64) -> AppResult<()> {
+        if self.reserve_size == 0 {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock();
+        // 估算的剩余空间不足时也重新获取一次，避免估算的误差导致拒绝写入
+        let stale = match state.as_ref() {
+            None => true,
+            Some(s) => {
+                s.written >= DISK_SPACE_REFRESH_BYTES
+                    || s.refreshed_at.elapsed() >= DISK_SPACE_REFRESH_INTERVAL
+                    || s.available < s.written + size + self.reserve_size
+            }
+        };
+        if stale {
+            let available = match available_disk_size(self.dir_path.clone()) {
+                Ok(available) => available,
+                Err(e) => {
+                    error!("failed to get available disk size: {}", e);
+                    return Err(AppErrors::FailedToGetDiskSpace);
+                }
+            };
+            *state = Some(DiskSpaceState {
+                available,
+                written: 0,
+                refreshed_at: Instant::now(),
+            });
+        }
+
+        let s = state.as_mut().unwrap();
+        if s.available < s.written + size + self.reserve_size {
+            return Err(AppErrors::DiskSpaceLow);
+        }
+        s.written += size;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_space_check() {
+        let dir_path = std::env::temp_dir();
+        let available = available_disk_size(dir_path.clone()).unwrap();
+
+        // 不保留空间时不检查
+        let space1 = DiskSpace::new(dir_path.clone(), 0);
+        assert!(space1.check(u64::MAX / 2).is_ok());
+
+        // 保留空间大于剩余空间
+        let space2 = DiskSpace::new(dir_path.clone(), available + 1);
+        assert_eq!(AppErrors::DiskSpaceLow, space2.check(1).err().unwrap());
+
+        // 累计写入的字节数计入估算的剩余空间，估算不足时重新获取之后仍然不足则拒绝
+        let space3 = DiskSpace::new(dir_path.clone(), 1);
+        assert!(space3.check(1024).is_ok());
+        assert_eq!(space3.state.lock().as_ref().unwrap().written, 1024);
+        assert_eq!(AppErrors::DiskSpaceLow, space3.check(u64::MAX / 2).err().unwrap());
+        assert!(space3.check(1024).is_ok());
+    }
+}
@@ -1,17 +1,43 @@
 use std::collections::HashMap;
 use std::fs;
 use std::fs::{File, create_dir_all};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
-use parking_lot::{Mutex, RwLock};
-use crate::index;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use bytes::Bytes;
+use fs2::FileExt;
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use crate::index::new_indexer;
 use crate::options::options::Options;
+use crate::options::index_type::IndexType;
 use crate::data::data_files_mod::data_file::DataFile;
+use crate::data::data_files_mod::utils::SEQ_NO_FILE_NAME;
+use crate::batch::{log_record_key_with_seq, NON_TRANSACTION_SEQ_NO};
 use crate::index::indexer::Indexer;
 use crate::errors::{AppResult, AppErrors};
-use super::utils::{check_options, load_data_files};
+use super::utils::{check_options, data_file_io_type, load_data_files, prepare_active_file, sealed_file_io_type};
+use super::group_commit::GroupCommit;
+use super::flusher::Flusher;
+use super::stat::Stat;
+use super::value_cache::ValueCache;
+use super::index_loader::{IndexLoader, IndexReadiness};
+use super::disk_space::DiskSpace;
+use crate::options::sync_policy::SyncPolicy;
 use crate::merge::load_merge_files;
-use crate::options::io_type::IOType;
+use crate::utils::file::{available_disk_size, copy_dir, dir_disk_size};
+use crate::data::log_record_mod::log_record::LogRecord;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::data::log_record_mod::log_record_type::LogRecordType;
+use crate::data::log_record_mod::{decode_log_record, decode_log_record_value};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::fio::uring_io::{UringRead, UringReader};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use std::os::fd::{AsRawFd, BorrowedFd, OwnedFd};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use std::collections::hash_map::Entry;
+use crate::utils::rate_limiter::RateLimiter;
+use log::{error, warn};
 
 // TODO 补充 engine 方法!
 
@@ -24,16 +50,25 @@ pub struct Engine {
     pub(crate) options: Arc<Options>,
     pub(crate) active_file: Arc<RwLock<DataFile>>, // 当前活跃数据文件
     pub(crate) older_files: Arc<RwLock<HashMap<u32, DataFile>>>, // 旧的数据文件
-    pub(crate) index: Box<dyn Indexer>,     // 数据内存索引
-    file_ids: Vec<u32>, // 数据库启动时的文件 id，只用于加载索引时使用，不能在其他的地方更新或使用
+    pub(crate) index: Arc<dyn Indexer>,     // 数据内存索引
+    pub(crate) index_ready: Arc<IndexReadiness>, // 索引是否已经加载完成
+    index_loader_handle: Mutex<Option<JoinHandle<()>>>, // 后台加载索引的线程，关闭之前等待退出
+    pub(crate) file_ids: Vec<u32>, // 数据库启动时的文件 id，只用于加载索引时使用，不能在其他的地方更新或使用
     pub(crate) batch_commit_lock: Mutex<()>, // 事务提交保证串行化
+    pub(crate) write_lock: RwLock<()>, // 写入数据和更新索引期间持有读锁，需要和所有写入互斥的操作持有写锁
+    pub(crate) spilled_batches: AtomicUsize, // 已经溢写但尚未提交的批次数量，期间不能 merge
     pub(crate) seq_no: Arc<AtomicUsize>, // 事务序列号，全局递增
     pub(crate) merging_lock: Mutex<()>, // 防止多个线程同时 merge
     pub(crate) seq_file_exists: bool, // 事务序列号文件是否存在
     pub(crate) is_initial: bool, // 是否是第一次初始化该目录
     lock_file: File,    // 文件锁，保证只能在数据目录上打开一个实例
-    bytes_write: Arc<AtomicUsize>, // 累计写入了多少字节
+    bytes_write: Arc<AtomicUsize>, // 距离上一次持久化累计写入了多少字节
     pub(crate) reclaim_size: Arc<AtomicUsize>, // 累计有多少空间可以 merge
+    pub(crate) io_limiter: Arc<RateLimiter>, // 后台任务（merge、备份）的 IO 限速器
+    group_commit: GroupCommit, // 组提交，并发的同步写共享一次 sync
+    flusher: Option<Flusher>, // 按时间间隔持久化的后台线程
+    pub(crate) value_cache: Option<ValueCache>, // 数据文件读取的 value 缓存
+    disk_space: DiskSpace, // 数据目录所在磁盘的剩余空间
 }
 
 impl Engine {
@@ -61,9 +96,10 @@ impl Engine {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(dir_path.join(FILE_LOCK_NAME))
             .unwrap();
-        if let Err(_) = lock_file.try_lock_exclusive() {
+        if lock_file.try_lock_exclusive().is_err() {
             return Err(AppErrors::DatabaseIsUsing);
         }
 
@@ -76,7 +112,7 @@ impl Engine {
         load_merge_files(dir_path.clone())?;
 
         // 加载数据文件
-        let mut data_files = load_data_files(dir_path.clone(), options.mmap_at_startup)?;
+        let mut data_files = load_data_files(dir_path.clone(), &options)?;
 
         // 设置 file id 信息
         let mut file_ids = Vec::new();
@@ -98,7 +134,7 @@ impl Engine {
         // 拿到当前活跃文件，即列表中最后一个文件
         let active_file = match data_files.pop() {
             Some(v) => v,
-            None => DataFile::new(dir_path.clone(), INITIAL_FILE_ID, IOType::StandardFIO)?,
+            None => DataFile::new(dir_path.clone(), INITIAL_FILE_ID, data_file_io_type(&options))?,
         };
 
         // 构造存储引擎实例
@@ -106,9 +142,13 @@ impl Engine {
             options: Arc::new(opts),
             active_file: Arc::new(RwLock::new(active_file)),
             older_files: Arc::new(RwLock::new(older_files)),
-            index: new_indexer(options.index_type, options.dir_path),
+            index: new_indexer(&options)?.into(),
+            index_ready: Arc::new(IndexReadiness::ready()),
+            index_loader_handle: Mutex::new(None),
             file_ids,
             batch_commit_lock: Mutex::new(()),
+            write_lock: RwLock::new(()),
+            spilled_batches: AtomicUsize::new(0),
             seq_no: Arc::new(AtomicUsize::new(1)),
             merging_lock: Mutex::new(()),
             seq_file_exists: false,
@@ -116,40 +156,1339 @@ impl Engine {
             lock_file,
             bytes_write: Arc::new(AtomicUsize::new(0)),
             reclaim_size: Arc::new(AtomicUsize::new(0)),
+            io_limiter: Arc::new(RateLimiter::new(options.background_io_rate_limit)),
+            group_commit: GroupCommit::new(options.group_commit_max_wait),
+            flusher: None,
+            value_cache: match options.value_cache_size {
+                0 => None,
+                size => Some(ValueCache::new(size)),
+            },
+            disk_space: DiskSpace::new(dir_path.clone(), options.disk_reserve_size),
         };
 
-        // B+ 树则不需要从数据文件中加载索引
-        if engine.options.index_type != IndexType::BPlusTree {
-            // 从 hint 文件中加载索引
-            engine.load_index_from_hint_file()?;
+        if engine.index.is_persisted() {
+            // B+ 树和正常关闭的混合索引不需要从数据文件中加载索引，加载事务序列号
+            let (exists, seq_no) = engine.load_seq_no()?;
+            if exists {
+                engine.seq_no.store(seq_no, Ordering::SeqCst);
+                engine.seq_file_exists = exists;
+            }
 
-            // 从数据文件中加载索引
-            let current_seq_no = engine.load_index_from_data_files()?;
+            // 数据文件通过 mmap 打开时切换为配置的 IO 类型，只读的 mmap 不能写入和截断活跃文件
+            if engine.options.mmap_at_startup {
+                engine.index_loader().reset_io_type()?;
+            }
 
-            // 更新当前事务序列号
-            if current_seq_no > 0 {
-                engine.seq_no.store(current_seq_no + 1, Ordering::SeqCst);
+            engine.recover_active_file()?;
+        } else if engine.options.lazy_open {
+            // 截断活跃文件在打开时完成，后台线程只读取数据文件，不会截断已经通过 mmap 返回给用户的数据
+            if engine.options.mmap_at_startup {
+                let mut active_file = engine.active_file.write();
+                active_file.set_io_manager(dir_path.clone(), data_file_io_type(&engine.options))?;
             }
+            engine.recover_active_file()?;
 
-            // 重置 IO 类型
+            // 在后台线程中加载索引，已经加载到的 key 可以直接读取，其余的读取和所有的写入等待加载完成
+            engine.index_ready = Arc::new(IndexReadiness::loading());
+            let loader = engine.index_loader();
+            let index_ready = engine.index_ready.clone();
+            let handle = thread::spawn(move || index_ready.finish(loader.load()));
+            *engine.index_loader_handle.lock() = Some(handle);
+        } else {
+            // 先截断活跃文件末尾不完整的数据，再从索引快照、hint 文件和数据文件中加载索引
             if engine.options.mmap_at_startup {
-                engine.reset_io_type();
+                let mut active_file = engine.active_file.write();
+                active_file.set_io_manager(dir_path.clone(), data_file_io_type(&engine.options))?;
             }
+            engine.recover_active_file()?;
+            engine.index_loader().load()?;
         }
 
-        if engine.options.index_type == IndexType::BPlusTree {
-            // 加载事务序列号
-            let (exists, seq_no) = engine.load_seq_no();
-            if exists {
-                engine.seq_no.store(seq_no, Ordering::SeqCst);
-                engine.seq_file_exists = exists;
+        // 按时间间隔持久化时启动后台刷盘线程
+        if let SyncPolicy::EveryInterval(interval) = engine.options.sync_policy {
+            engine.flusher = Some(Flusher::start(
+                interval,
+                engine.active_file.clone(),
+                engine.bytes_write.clone(),
+            ));
+        }
+
+        Ok(engine)
+    }
+
+    /// 备份数据目录，拷贝过程受后台 IO 限速控制
+    pub fn backup(&self, dir_path: PathBuf) -> AppResult<()> {
+        // 判断备份目录所在磁盘能否容纳数据目录，并保留一定的空间
+        let data_size = dir_disk_size(self.options.dir_path.clone());
+        let available_size = match available_disk_size(dir_path.clone()) {
+            Ok(size) => size,
+            Err(e) => {
+                error!("failed to get available disk size: {}", e);
+                return Err(AppErrors::FailedToGetDiskSpace);
             }
+        };
+        if data_size + self.options.disk_reserve_size >= available_size {
+            return Err(AppErrors::DiskSpaceLow);
+        }
 
-            // 设置当前活跃文件的偏移
-            let active_file = engine.active_file.write();
-            active_file.set_write_off(active_file.file_size());
+        let exclude = &[FILE_LOCK_NAME];
+        if let Err(e) = copy_dir(
+            self.options.dir_path.clone(),
+            dir_path,
+            exclude,
+            &self.io_limiter,
+        ) {
+            error!("failed to copy data directory error: {}", e);
+            return Err(AppErrors::FailedToCopyDirectory);
         }
+        Ok(())
+    }
 
-        Ok(engine)
+    /// 持久化当前活跃文件
+    pub fn sync(&self) -> AppResult<()> {
+        let active_file = self.active_file.read();
+        active_file.sync()
+    }
+
+    /// 关闭数据库，释放相关资源
+    /// 开启 index_snapshot 时写入索引快照，写入快照期间阻塞其他的写入
+    pub fn close(&self) -> AppResult<()> {
+        // 如果数据目录不存在则返回
+        if !self.options.dir_path.is_dir() {
+            return Ok(());
+        }
+
+        // 记录当前事务序列号，覆盖之前没有被读取的序列号文件
+        let seq_no_path = self.options.dir_path.join(SEQ_NO_FILE_NAME);
+        if seq_no_path.is_file() && let Err(e) = fs::remove_file(&seq_no_path) {
+            error!("failed to remove seq no file: {}", e);
+            return Err(AppErrors::FailedWriteToDataFile);
+        }
+        let seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone())?;
+        let seq_no = self.seq_no.load(Ordering::SeqCst);
+        let record = LogRecord {
+            key: SEQ_NO_KEY.as_bytes().to_vec(),
+            value: seq_no.to_string().into_bytes(),
+            rec_type: LogRecordType::NORMAL,
+        };
+        seq_no_file.write(&record.encode())?;
+        seq_no_file.sync()?;
+
+        let active_file = self.active_file.read();
+        active_file.sync()?;
+        drop(active_file);
+
+        // 索引没有完整加载时不写入磁盘
+        self.join_index_loader();
+        let index_loaded = self.index_ready.wait().is_ok();
+        if index_loaded {
+            // 将混合索引内存中更新过的位置信息写入磁盘，下次打开时不需要重建
+            let _commit_guard = self.batch_commit_lock.lock();
+            let _write_guard = self.write_lock.write();
+            self.index.flush()?;
+        }
+
+        // 写入索引快照，B+ 树和混合索引本身就存储在磁盘上
+        let on_disk = matches!(self.options.index_type, IndexType::BPlusTree | IndexType::Hybrid);
+        if self.options.index_snapshot && !on_disk && index_loaded {
+            self.write_index_snapshot()?;
+        }
+
+        // 释放文件锁
+        if let Err(e) = self.lock_file.unlock() {
+            error!("failed to unlock database directory: {}", e);
+            return Err(AppErrors::FailedToUnlockDatabase);
+        }
+        Ok(())
+    }
+
+    /// 存储 key/value 数据，key 不能为空
+    pub fn put(&self, key: Bytes, value: Bytes) -> AppResult<()> {
+        // 判断 key 的有效性
+        if key.is_empty() {
+            return Err(AppErrors::KeyIsEmpty);
+        }
+
+        // 构造 LogRecord
+        let mut record = LogRecord {
+            key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
+            value: value.to_vec(),
+            rec_type: LogRecordType::NORMAL,
+        };
+
+        // 追加数据和更新索引期间不能有 merge 的压缩过滤器修改索引
+        let _write_guard = self.write_lock.read();
+
+        // 追加写到活跃数据文件中
+        let log_record_pos = self.append_log_record(&mut record)?;
+
+        // 更新内存索引，旧的数据成为可以回收的空间
+        if let Some(old_pos) = self.index.put(key.to_vec(), log_record_pos)? {
+            self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// 根据 key 删除对应的数据
+    pub fn delete(&self, key: Bytes) -> AppResult<()> {
+        // 判断 key 的有效性
+        if key.is_empty() {
+            return Err(AppErrors::KeyIsEmpty);
+        }
+
+        let _write_guard = self.write_lock.read();
+
+        // 从内存索引当中取出对应的数据，不存在的话直接返回
+        if self.index_get(&key)?.is_none() {
+            return Ok(());
+        }
+
+        // 构造 LogRecord，标识其是被删除的
+        let mut record = LogRecord {
+            key: log_record_key_with_seq(key.to_vec(), NON_TRANSACTION_SEQ_NO),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+        };
+
+        // 写入到数据文件当中，删除标记本身也是可以回收的空间
+        let pos = self.append_log_record(&mut record)?;
+        self.reclaim_size.fetch_add(pos.size as usize, Ordering::SeqCst);
+
+        // 从内存索引中将对应的 key 删除
+        if let Some(old_pos) = self.index.delete(key.to_vec())? {
+            self.reclaim_size.fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// 根据 key 获取对应的数据
+    pub fn get(&self, key: Bytes) -> AppResult<Bytes> {
+        // 判断 key 的有效性
+        if key.is_empty() {
+            return Err(AppErrors::KeyIsEmpty);
+        }
+
+        // 从内存索引中拿到 key 对应的数据信息
+        let pos = self.index_get(&key)?;
+        // 如果 key 不存在则直接返回
+        if pos.is_none() {
+            return Err(AppErrors::KeyNotFound);
+        }
+
+        // 从对应的数据文件中获取对应的 LogRecord
+        let log_record_pos = pos.unwrap();
+        self.get_value_by_position(&log_record_pos)
+    }
+
+    /// 根据索引信息获取 value，开启缓存时先从缓存中读取
+    pub(crate) fn get_value_by_position(&self, log_record_pos: &LogRecordPos) -> AppResult<Bytes> {
+        // 只需要 key 的迭代器返回的位置信息不指向任何数据
+        if log_record_pos.is_unresolved() {
+            return Err(AppErrors::DataFileNotFound);
+        }
+        if let Some(value_cache) = self.value_cache.as_ref()
+            && let Some(value) = value_cache.get(log_record_pos.file_id, log_record_pos.offset)
+        {
+            return Ok(value);
+        }
+
+        // 从对应的数据文件中获取对应的 LogRecord
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        let log_record = match active_file.get_file_id() == log_record_pos.file_id {
+            true => active_file.read_log_record(log_record_pos.offset)?.record,
+            false => {
+                let data_file = older_files.get(&log_record_pos.file_id);
+                if data_file.is_none() {
+                    // 找不到对应的数据文件，返回错误
+                    return Err(AppErrors::DataFileNotFound);
+                }
+                data_file.unwrap().read_log_record(log_record_pos.offset)?.record
+            }
+        };
+
+        // 判断 LogRecord 的类型
+        if log_record.rec_type == LogRecordType::DELETED {
+            return Err(AppErrors::KeyNotFound);
+        }
+
+        let value: Bytes = log_record.value.into();
+        if let Some(value_cache) = self.value_cache.as_ref() {
+            value_cache.insert(log_record_pos.file_id, log_record_pos.offset, value.clone());
+        }
+        Ok(value)
+    }
+
+    /// 根据 key 获取数据，数据位于通过 mmap 打开的数据文件中时，返回直接引用映射内存的 Bytes，不拷贝 value
+    /// 返回的 Bytes 持有映射的引用计数，之后数据文件被释放映射或者被 merge 删除，已经返回的数据仍然有效
+    pub fn get_pinned(&self, key: Bytes) -> AppResult<Bytes> {
+        if key.is_empty() {
+            return Err(AppErrors::KeyIsEmpty);
+        }
+        let log_record_pos = match self.index_get(&key)? {
+            Some(pos) => pos,
+            None => return Err(AppErrors::KeyNotFound),
+        };
+
+        let view = {
+            let active_file = self.active_file.read();
+            let older_files = self.older_files.read();
+            let data_file = if active_file.get_file_id() == log_record_pos.file_id {
+                &*active_file
+            } else {
+                match older_files.get(&log_record_pos.file_id) {
+                    Some(data_file) => data_file,
+                    None => return Err(AppErrors::DataFileNotFound),
+                }
+            };
+            data_file.read_view(log_record_pos.offset, log_record_pos.size as usize)
+        };
+
+        // 数据文件不支持零拷贝读取，退化为普通的读取
+        let view = match view {
+            Some(view) => view,
+            None => return self.get_value_by_position(&log_record_pos),
+        };
+        let (rec_type, value) = decode_log_record_value(view)?;
+        if rec_type == LogRecordType::DELETED {
+            return Err(AppErrors::KeyNotFound);
+        }
+        Ok(value)
+    }
+
+    /// 获取数据库中所有的 key
+    pub fn list_keys(&self) -> AppResult<Vec<Bytes>> {
+        self.index_ready.wait()?;
+        self.index.list_keys()
     }
-}
\ No newline at end of file
+
+    /// 获取数据库的统计信息，索引还在后台加载时只统计已经加载到的 key
+    pub fn stat(&self) -> AppResult<Stat> {
+        let keys = self.index.list_keys()?;
+        let older_files = self.older_files.read();
+        let (cache_hits, cache_misses) = match self.value_cache.as_ref() {
+            Some(value_cache) => (value_cache.hits(), value_cache.misses()),
+            None => (0, 0),
+        };
+        Ok(Stat {
+            key_num: keys.len(),
+            data_file_num: older_files.len() + 1,
+            reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
+            disk_size: dir_disk_size(self.options.dir_path.clone()),
+            cache_hits,
+            cache_misses,
+            index_memory_usage: self.index.memory_usage(),
+        })
+    }
+
+    /// 批量读取多个 key 对应的 value，key 不存在时返回 None
+    /// 数据文件通过 io_uring 读取时，所有的读请求在一次提交中发出
+    pub fn multi_get(&self, keys: &[Bytes]) -> AppResult<Vec<Option<Bytes>>> {
+        if keys.iter().any(|key| key.is_empty()) {
+            return Err(AppErrors::KeyIsEmpty);
+        }
+
+        // 从内存索引中取出所有 key 的位置信息，缓存中已经存在的 value 不需要再读取
+        let mut positions: Vec<Option<LogRecordPos>> =
+            keys.iter().map(|key| self.index_get(key)).collect::<AppResult<_>>()?;
+        let mut values: Vec<Option<Bytes>> = vec![None; keys.len()];
+        if let Some(value_cache) = self.value_cache.as_ref() {
+            for (pos, value) in positions.iter_mut().zip(values.iter_mut()) {
+                if let Some(p) = pos
+                    && let Some(cached) = value_cache.get(p.file_id, p.offset)
+                {
+                    *value = Some(cached);
+                    *pos = None;
+                }
+            }
+        }
+        let mut bufs: Vec<Vec<u8>> = positions
+            .iter()
+            .map(|pos| vec![0u8; pos.map_or(0, |pos| pos.size as usize)])
+            .collect();
+
+        // 提交给 io_uring 的读请求使用复制的文件描述符，数据文件被切换或者被 merge 删除之后仍然可以读取
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let mut uring_fds: HashMap<u32, OwnedFd> = HashMap::new();
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let mut uring_bufs = Vec::new();
+        {
+            // 查找数据文件期间持有数据文件的读锁，不支持 io_uring 的数据文件在持有锁期间直接读取
+            let active_file = self.active_file.read();
+            let older_files = self.older_files.read();
+
+            for (pos, buf) in positions.iter().zip(bufs.iter_mut()) {
+                let pos = match pos {
+                    Some(pos) => pos,
+                    None => continue,
+                };
+                let data_file = if active_file.get_file_id() == pos.file_id {
+                    &*active_file
+                } else {
+                    match older_files.get(&pos.file_id) {
+                        Some(data_file) => data_file,
+                        None => return Err(AppErrors::DataFileNotFound),
+                    }
+                };
+
+                #[cfg(all(feature = "io-uring", target_os = "linux"))]
+                if let Some(fd) = data_file.raw_fd() {
+                    if let Entry::Vacant(entry) = uring_fds.entry(pos.file_id) {
+                        let owned_fd = match unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned() {
+                            Ok(owned_fd) => owned_fd,
+                            Err(e) => {
+                                error!("failed to duplicate data file fd: {}", e);
+                                return Err(AppErrors::FailedReadFromDataFile);
+                            }
+                        };
+                        entry.insert(owned_fd);
+                    }
+                    uring_bufs.push((pos.file_id, pos.offset, buf));
+                    continue;
+                }
+                if data_file.read_at(buf, pos.offset)? < buf.len() {
+                    error!("short read from data file {} at offset {}", pos.file_id, pos.offset);
+                    return Err(AppErrors::ReadDataFileEOF);
+                }
+            }
+        }
+
+        // 释放数据文件的锁之后再批量提交，不阻塞写入和切换活跃文件
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(reader) = UringReader::global() {
+            let mut uring_reqs: Vec<UringRead> = uring_bufs
+                .into_iter()
+                .map(|(file_id, offset, buf)| UringRead {
+                    fd: uring_fds[&file_id].as_raw_fd(),
+                    offset,
+                    buf: buf.as_mut_slice(),
+                })
+                .collect();
+            let read_sizes = reader.read_batch(&mut uring_reqs)?;
+            for (req, read_size) in uring_reqs.iter().zip(read_sizes) {
+                if read_size < req.buf.len() {
+                    error!("short io_uring read from fd {} at offset {}", req.fd, req.offset);
+                    return Err(AppErrors::ReadDataFileEOF);
+                }
+            }
+        }
+
+        // 解码读取到的数据
+        for ((pos, buf), value) in positions.iter().zip(bufs.iter()).zip(values.iter_mut()) {
+            let pos = match pos {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let log_record = decode_log_record(buf)?;
+            if log_record.rec_type == LogRecordType::DELETED {
+                continue;
+            }
+            let read_value: Bytes = log_record.value.into();
+            if let Some(value_cache) = self.value_cache.as_ref() {
+                value_cache.insert(pos.file_id, pos.offset, read_value.clone());
+            }
+            *value = Some(read_value);
+        }
+        Ok(values)
+    }
+
+    /// 追加写数据到当前活跃文件中
+    pub(crate) fn append_log_record(&self, log_record: &mut LogRecord) -> AppResult<LogRecordPos> {
+        // 索引加载完成之前不能写入，活跃文件的写偏移在加载完成时才确定
+        self.index_ready.wait()?;
+
+        // 输入数据进行编码
+        let enc_record = log_record.encode();
+        let record_len = enc_record.len() as u64;
+
+        // 磁盘剩余空间低于保留值时拒绝写入，避免把磁盘写满
+        self.disk_space.check(record_len)?;
+
+        // 获取到当前活跃文件
+        let mut active_file = self.active_file.write();
+
+        // 判断当前活跃文件是否达到了阈值
+        if active_file.get_write_off() + record_len > self.options.data_file_size {
+            self.rotate_active_file(&mut active_file)?;
+        }
+
+        // 追加写数据到当前文件中
+        let write_off = active_file.get_write_off();
+        active_file.write(&enc_record)?;
+
+        // 构造数据内存索引信息
+        let pos = LogRecordPos {
+            file_id: active_file.get_file_id(),
+            offset: write_off,
+            size: enc_record.len() as u32,
+        };
+
+        self.sync_after_write(active_file, enc_record.len())?;
+        Ok(pos)
+    }
+
+    /// 批量追加写数据到当前活跃文件中，所有数据编码之后一次写入，保证在同一个数据文件中连续存放
+    pub(crate) fn append_log_records(&self, log_records: &[LogRecord]) -> AppResult<Vec<LogRecordPos>> {
+        // 索引加载完成之前不能写入，活跃文件的写偏移在加载完成时才确定
+        self.index_ready.wait()?;
+
+        let enc_records: Vec<Vec<u8>> = log_records.iter().map(|r| r.encode()).collect();
+        let total_len: usize = enc_records.iter().map(|r| r.len()).sum();
+
+        // 磁盘剩余空间低于保留值时拒绝写入，避免把磁盘写满
+        self.disk_space.check(total_len as u64)?;
+
+        let mut active_file = self.active_file.write();
+
+        // 当前活跃文件放不下整个批次则切换到新的数据文件，批次本身超过文件阈值时整体写入新文件
+        let write_off = active_file.get_write_off();
+        if write_off > 0 && write_off + total_len as u64 > self.options.data_file_size {
+            self.rotate_active_file(&mut active_file)?;
+        }
+
+        // 计算每条数据的位置信息
+        let file_id = active_file.get_file_id();
+        let mut offset = active_file.get_write_off();
+        let mut positions = Vec::with_capacity(enc_records.len());
+        for enc_record in enc_records.iter() {
+            positions.push(LogRecordPos {
+                file_id,
+                offset,
+                size: enc_record.len() as u32,
+            });
+            offset += enc_record.len() as u64;
+        }
+
+        // 一次系统调用写入所有数据
+        let bufs: Vec<&[u8]> = enc_records.iter().map(|r| r.as_slice()).collect();
+        active_file.write_vectored(&bufs)?;
+
+        self.sync_after_write(active_file, total_len)?;
+        Ok(positions)
+    }
+
+    // 将当前活跃文件转为旧的数据文件，并打开新的活跃文件
+    fn rotate_active_file(&self, active_file: &mut DataFile) -> AppResult<()> {
+        let dir_path = self.options.dir_path.clone();
+
+        // 截断预分配但没有写入数据的空间，然后将当前活跃文件进行持久化
+        active_file.truncate_to_write_off()?;
+        active_file.sync()?;
+        self.bytes_write.store(0, Ordering::SeqCst);
+
+        let current_fid = active_file.get_file_id();
+        // 旧的数据文件存储到 map 中
+        let mut older_files = self.older_files.write();
+        // 旧的数据文件不会再写入，按照配置切换为 mmap 读取
+        let old_file = DataFile::new(dir_path.clone(), current_fid, sealed_file_io_type(&self.options))?;
+        older_files.insert(current_fid, old_file);
+
+        // 打开新的数据文件
+        let new_file = DataFile::new(dir_path.clone(), current_fid + 1, data_file_io_type(&self.options))?;
+        if self.options.preallocate {
+            new_file.preallocate(self.options.data_file_size)?;
+        }
+        *active_file = new_file;
+        Ok(())
+    }
+
+    // 扫描活跃文件找到数据真实的末尾，崩溃时末尾可能有不完整的数据，然后截断预分配的空间
+    fn recover_active_file(&self) -> AppResult<()> {
+        let active_file = self.active_file.write();
+        active_file.set_write_off(active_file.scan_write_off()?);
+        prepare_active_file(&active_file, &self.options)
+    }
+
+    // 加载关闭时记录的事务序列号，读取之后删除文件，下次关闭时重新写入
+    fn load_seq_no(&self) -> AppResult<(bool, usize)> {
+        let file_name = self.options.dir_path.join(SEQ_NO_FILE_NAME);
+        if !file_name.is_file() {
+            return Ok((false, 0));
+        }
+
+        let seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone())?;
+        let record = seq_no_file.read_log_record(0)?.record;
+        let seq_no = match String::from_utf8(record.value).ok().and_then(|v| v.parse::<usize>().ok()) {
+            Some(seq_no) => seq_no,
+            None => {
+                error!("invalid seq no in {:?}", file_name);
+                return Err(AppErrors::DataDirectoryCorrupted);
+            }
+        };
+        if let Err(e) = fs::remove_file(&file_name) {
+            error!("failed to remove seq no file: {}", e);
+            return Err(AppErrors::DataDirectoryCorrupted);
+        }
+        Ok((true, seq_no))
+    }
+
+    // 等待后台加载索引的线程退出
+    fn join_index_loader(&self) {
+        if let Some(handle) = self.index_loader_handle.lock().take()
+            && handle.join().is_err()
+        {
+            error!("index loader thread panicked");
+        }
+    }
+
+    // 构造加载索引需要的状态
+    fn index_loader(&self) -> IndexLoader {
+        IndexLoader {
+            options: self.options.clone(),
+            index: self.index.clone(),
+            ready: self.index_ready.clone(),
+            active_file: self.active_file.clone(),
+            older_files: self.older_files.clone(),
+            file_ids: self.file_ids.clone(),
+            seq_no: self.seq_no.clone(),
+            reclaim_size: self.reclaim_size.clone(),
+        }
+    }
+
+    /// 索引是否已经加载完成，后台加载索引时可以用于健康检查
+    pub fn is_ready(&self) -> bool {
+        self.index_ready.is_ready()
+    }
+
+    /// 等待后台加载索引完成，加载失败时返回错误
+    pub fn wait_ready(&self) -> AppResult<()> {
+        self.index_ready.wait()
+    }
+
+    /// 从内存索引中拿到 key 对应的数据信息
+    /// 索引还在后台加载时，数据文件从新到旧加载，已经加载到的 key 和已经确定被删除的 key 不会再变化，直接返回，
+    /// 其余的 key 可能还在没有加载的旧数据文件中，需要等待加载完成
+    pub(crate) fn index_get(&self, key: &[u8]) -> AppResult<Option<LogRecordPos>> {
+        if !self.index_ready.is_ready() {
+            if let Some(pos) = self.index.get(key.to_vec())? {
+                return Ok(Some(pos));
+            }
+            if self.index_ready.is_deleted(key) {
+                return Ok(None);
+            }
+            self.index_ready.wait()?;
+        }
+        self.index.get(key.to_vec())
+    }
+
+    /// 将通过 mmap 打开的旧数据文件切换为标准文件 IO，释放映射占用的内存，用于内存紧张时
+    /// 之后切换出来的旧数据文件仍然按照配置通过 mmap 打开
+    pub fn unmap_sealed_files(&self) -> AppResult<()> {
+        let mut older_files = self.older_files.write();
+        for (_, file) in older_files.iter_mut() {
+            file.set_io_manager(self.options.dir_path.clone(), data_file_io_type(&self.options))?;
+        }
+        Ok(())
+    }
+
+    // 写入 written 个字节之后，根据持久化策略决定是否 sync，按时间间隔持久化由后台线程负责
+    fn sync_after_write(
+        &self,
+        active_file: RwLockWriteGuard<'_, DataFile>,
+        written: usize,
+    ) -> AppResult<()> {
+        let bytes_write = self.bytes_write.fetch_add(written, Ordering::SeqCst) + written;
+
+        match self.options.sync_policy {
+            SyncPolicy::Always if self.options.group_commit => {
+                // 释放活跃文件的锁，让其他写入进来，然后等待共享的 sync 完成
+                let seq = self.group_commit.next_seq();
+                drop(active_file);
+                self.group_commit.wait_synced(seq, || self.sync())?;
+            }
+            SyncPolicy::Always => {
+                active_file.sync()?;
+            }
+            SyncPolicy::EveryBytes(bytes_per_sync) if bytes_write >= bytes_per_sync => {
+                active_file.sync()?;
+                self.bytes_write.store(0, Ordering::SeqCst);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 运行时调整后台任务的 IO 限速，单位为字节/秒，0 表示不限速
+    pub fn set_background_io_rate_limit(&self, bytes_per_sec: u64) {
+        self.io_limiter.set_rate(bytes_per_sec);
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        // 没有调用 close 时也需要等待后台加载索引的线程退出，之后才能释放文件锁
+        self.join_index_loader();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::rand_kv::{get_test_key, get_test_value};
+    use crate::data::data_files_mod::utils::get_data_file_name;
+    use crate::db::index_snapshot::INDEX_SNAPSHOT_FILE_NAME;
+    use crate::options::index_load_progress::IndexLoadProgress;
+    use crate::options::write_batch_options::WriteBatchOptions;
+    use std::os::unix::fs::MetadataExt;
+    use std::io::Write;
+
+    #[test]
+    fn test_engine_disk_reserve() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-disk-reserve");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let put_res1 = engine.put(get_test_key(1), get_test_value(1));
+        assert!(put_res1.is_ok());
+        std::mem::drop(engine);
+
+        // 保留空间大于磁盘剩余空间，写入和备份都被拒绝
+        opts.disk_reserve_size = available_disk_size(opts.dir_path.clone()).unwrap() + 1;
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let put_res2 = engine2.put(get_test_key(2), get_test_value(2));
+        assert_eq!(AppErrors::DiskSpaceLow, put_res2.err().unwrap());
+        let backup_res = engine2.backup(PathBuf::from("/tmp/bitcask-rs-disk-reserve-backup"));
+        assert_eq!(AppErrors::DiskSpaceLow, backup_res.err().unwrap());
+
+        // 已经写入的数据仍然可以读取
+        let get_res = engine2.get(get_test_key(1));
+        assert!(get_res.is_ok());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_group_commit() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-group-commit");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.sync_policy = SyncPolicy::Always;
+        opts.group_commit = true;
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+
+        let mut handles = vec![];
+        for t in 0..8 {
+            let eng = engine.clone();
+            handles.push(std::thread::spawn(move || {
+                for i in t * 1000..(t + 1) * 1000 {
+                    let put_res = eng.put(get_test_key(i), get_test_value(i));
+                    assert!(put_res.is_ok());
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 重启校验
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine2.list_keys().unwrap();
+        assert_eq!(keys.len(), 8000);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_sync_policy() {
+        // 非法的持久化策略
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-sync-policy");
+        opts.sync_policy = SyncPolicy::EveryBytes(0);
+        assert_eq!(AppErrors::InvalidSyncPolicy, Engine::open(opts.clone()).err().unwrap());
+        opts.sync_policy = SyncPolicy::EveryInterval(std::time::Duration::ZERO);
+        assert_eq!(AppErrors::InvalidSyncPolicy, Engine::open(opts.clone()).err().unwrap());
+
+        // 累计写入字节数达到阈值后持久化
+        opts.sync_policy = SyncPolicy::EveryBytes(4 * 1024);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        assert!(engine.bytes_write.load(Ordering::SeqCst) < 4 * 1024);
+        std::mem::drop(engine);
+
+        // 后台线程按时间间隔持久化
+        opts.sync_policy = SyncPolicy::EveryInterval(std::time::Duration::from_millis(10));
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 1000..2000 {
+            let put_res = engine2.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(engine2.bytes_write.load(Ordering::SeqCst), 0);
+        std::mem::drop(engine2);
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine3.list_keys().unwrap().len(), 2000);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_append_log_records() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-append-records");
+        opts.data_file_size = 4 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let records: Vec<LogRecord> = (0..20)
+            .map(|i| LogRecord {
+                key: get_test_key(i).to_vec(),
+                value: get_test_value(i).to_vec(),
+                rec_type: LogRecordType::NORMAL,
+            })
+            .collect();
+
+        // 同一批次的数据在同一个文件中连续存放
+        let positions1 = engine.append_log_records(&records).unwrap();
+        assert_eq!(positions1.len(), 20);
+        for i in 1..positions1.len() {
+            assert_eq!(positions1[i].file_id, positions1[0].file_id);
+            assert_eq!(positions1[i].offset, positions1[i - 1].offset + positions1[i - 1].size as u64);
+        }
+
+        // 当前文件放不下整个批次时，切换到新的文件
+        let positions2 = engine.append_log_records(&records).unwrap();
+        assert_eq!(positions2[0].file_id, positions1[0].file_id + 1);
+        assert_eq!(positions2[0].offset, 0);
+
+        let record = engine.active_file.read().read_log_record(positions2[19].offset).unwrap();
+        assert_eq!(record.record.value, get_test_value(19).to_vec());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_multi_get() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-multi-get");
+        opts.data_file_size = 64 * 1024;
+        opts.io_uring = true;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..1000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        let del_res = engine.delete(get_test_key(10));
+        assert!(del_res.is_ok());
+
+        // 数据分布在多个数据文件中，一次读取
+        let keys: Vec<Bytes> = (0..1000).map(get_test_key).chain(std::iter::once(get_test_key(2000))).collect();
+        let values = engine.multi_get(&keys).unwrap();
+        assert_eq!(values.len(), 1001);
+        for (i, value) in values.iter().take(1000).enumerate() {
+            if i == 10 {
+                assert!(value.is_none());
+                continue;
+            }
+            assert_eq!(value.clone().unwrap(), get_test_value(i));
+        }
+        assert!(values[1000].is_none());
+
+        // key 为空
+        let empty_res = engine.multi_get(&[Bytes::new()]);
+        assert_eq!(AppErrors::KeyIsEmpty, empty_res.err().unwrap());
+
+        // 数据文件被截断，读取不完整时返回错误
+        let pos = engine.index_get(&get_test_key(999)).unwrap().unwrap();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(get_data_file_name(opts.dir_path.clone(), pos.file_id))
+            .unwrap()
+            .set_len(pos.offset + 1)
+            .unwrap();
+        let short_res = engine.multi_get(&[get_test_key(999)]);
+        assert_eq!(AppErrors::ReadDataFileEOF, short_res.err().unwrap());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_conflicting_io_type() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-conflicting-io-type");
+        opts.direct_io = true;
+        opts.io_uring = true;
+        let res = Engine::open(opts.clone());
+        assert_eq!(AppErrors::ConflictingIOType, res.err().unwrap());
+    }
+
+    #[test]
+    fn test_engine_preallocate() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-preallocate");
+        opts.data_file_size = 64 * 1024;
+        opts.preallocate = true;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..1000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+
+        // 活跃文件预分配了磁盘空间但文件长度不变，切换之后的旧文件截断到数据真实的末尾
+        let active_fid = engine.active_file.read().get_file_id();
+        assert!(active_fid > 0);
+        let active_meta = fs::metadata(get_data_file_name(opts.dir_path.clone(), active_fid)).unwrap();
+        assert_eq!(active_meta.len(), engine.active_file.read().get_write_off());
+        assert!(active_meta.blocks() * 512 >= opts.data_file_size);
+        let older_len = fs::metadata(get_data_file_name(opts.dir_path.clone(), 0)).unwrap().len();
+        assert!(older_len < opts.data_file_size);
+        assert_eq!(older_len, engine.older_files.read().get(&0).unwrap().file_size());
+
+        // 重启之后从数据真实的末尾继续追加写
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 1000..1100 {
+            let put_res = engine2.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        std::mem::drop(engine2);
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine3.list_keys().unwrap().len(), 1100);
+        let get_res = engine3.get(get_test_key(1050));
+        assert_eq!(get_res.unwrap(), get_test_value(1050));
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_torn_write() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-torn-write");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        let write_off = engine.active_file.read().get_write_off();
+        std::mem::drop(engine);
+
+        // 模拟崩溃时只写入了一部分的数据，以及 crc 不匹配的数据
+        let mut record = LogRecord {
+            key: log_record_key_with_seq(get_test_key(100).to_vec(), NON_TRANSACTION_SEQ_NO),
+            value: get_test_value(100).to_vec(),
+            rec_type: LogRecordType::NORMAL,
+        };
+        let mut enc_record = record.encode();
+        enc_record.truncate(enc_record.len() / 2);
+        let data_file_name = get_data_file_name(opts.dir_path.clone(), 0);
+        let mut file = fs::OpenOptions::new().append(true).open(&data_file_name).unwrap();
+        file.write_all(&enc_record).unwrap();
+        drop(file);
+
+        // 打开时截断不完整的数据，之后的写入从最后一条完整数据的末尾追加
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.active_file.read().get_write_off(), write_off);
+        assert_eq!(fs::metadata(&data_file_name).unwrap().len(), write_off);
+        assert_eq!(engine2.list_keys().unwrap().len(), 100);
+        assert!(engine2.put(get_test_key(100), get_test_value(100)).is_ok());
+        std::mem::drop(engine2);
+
+        record.value = get_test_value(101).to_vec();
+        let mut enc_record = record.encode();
+        let last = enc_record.len() - 1;
+        enc_record[last] ^= 0xff;
+        let mut file = fs::OpenOptions::new().append(true).open(&data_file_name).unwrap();
+        file.write_all(&enc_record).unwrap();
+        drop(file);
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine3.list_keys().unwrap().len(), 101);
+        assert_eq!(engine3.get(get_test_key(100)).unwrap(), get_test_value(100));
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_value_cache() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-cache");
+        opts.data_file_size = 64 * 1024;
+        opts.value_cache_size = 1024 * 1024;
+        opts.data_file_merge_ratio = 0f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..100 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+
+        // 第一次读取未命中，之后命中缓存
+        for _ in 0..3 {
+            for i in 0..100 {
+                assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+            }
+        }
+        let stat1 = engine.stat().unwrap();
+        assert_eq!(stat1.cache_misses, 100);
+        assert_eq!(stat1.cache_hits, 200);
+
+        // 更新和删除之后读取到新的数据
+        let put_res = engine.put(get_test_key(1), Bytes::from("new-value"));
+        assert!(put_res.is_ok());
+        assert_eq!(engine.get(get_test_key(1)).unwrap(), Bytes::from("new-value"));
+        let del_res = engine.delete(get_test_key(2));
+        assert!(del_res.is_ok());
+        assert_eq!(AppErrors::KeyNotFound, engine.get(get_test_key(2)).err().unwrap());
+
+        // 批量读取同样使用缓存
+        let values = engine.multi_get(&[get_test_key(3), get_test_key(1)]).unwrap();
+        assert_eq!(values[0].clone().unwrap(), get_test_value(3));
+        assert_eq!(values[1].clone().unwrap(), Bytes::from("new-value"));
+        let stat2 = engine.stat().unwrap();
+        assert_eq!(stat2.cache_hits, 202);
+        assert_eq!(stat2.cache_misses, 101);
+
+        // merge 生成的数据文件在重启之后才生效，merge 之后继续命中原来的缓存
+        let merge_res = engine.merge();
+        assert!(merge_res.is_ok());
+        let stat3 = engine.stat().unwrap();
+        for i in 3..100 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+        let stat4 = engine.stat().unwrap();
+        assert_eq!(stat4.cache_hits - stat3.cache_hits, 97);
+        assert_eq!(stat4.cache_misses, stat3.cache_misses);
+
+        // merge 之后重启，读取到的仍然是正确的数据
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 3..100 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+        assert_eq!(engine2.get(get_test_key(1)).unwrap(), Bytes::from("new-value"));
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_mmap_sealed_files() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-mmap-sealed");
+        opts.data_file_size = 64 * 1024;
+        opts.mmap_sealed_files = true;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        assert!(engine.older_files.read().len() > 1);
+
+        // 旧的数据文件通过 mmap 读取
+        for i in 0..2000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        // 释放映射之后仍然可以读取
+        engine.unmap_sealed_files().unwrap();
+        for i in 0..2000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+        for i in 2000..3000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        std::mem::drop(engine);
+
+        // 重启之后旧的数据文件同样通过 mmap 读取
+        opts.mmap_at_startup = false;
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3000 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_get_pinned() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-get-pinned");
+        opts.data_file_size = 64 * 1024;
+        opts.mmap_sealed_files = true;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+
+        // 旧的数据文件零拷贝读取，活跃文件退化为普通读取
+        let pinned: Vec<Bytes> = (0..2000).map(|i| engine.get_pinned(get_test_key(i)).unwrap()).collect();
+        for (i, value) in pinned.iter().enumerate() {
+            assert_eq!(*value, get_test_value(i));
+        }
+        assert_eq!(AppErrors::KeyNotFound, engine.get_pinned(get_test_key(3000)).err().unwrap());
+        let del_res = engine.delete(get_test_key(1));
+        assert!(del_res.is_ok());
+        assert_eq!(AppErrors::KeyNotFound, engine.get_pinned(get_test_key(1)).err().unwrap());
+
+        // 释放映射并且 merge 删除旧的数据文件之后，已经返回的数据仍然有效
+        engine.unmap_sealed_files().unwrap();
+        opts.data_file_merge_ratio = 0f32;
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine2.merge().is_ok());
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        for (i, value) in pinned.iter().enumerate() {
+            assert_eq!(*value, get_test_value(i));
+        }
+        assert_eq!(engine3.get_pinned(get_test_key(10)).unwrap(), get_test_value(10));
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_index_snapshot() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-index-snapshot");
+        opts.data_file_size = 64 * 1024;
+        opts.index_snapshot = true;
+        opts.data_file_merge_ratio = 0f32;
+        let snapshot_path = opts.dir_path.join(INDEX_SNAPSHOT_FILE_NAME);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..100 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+
+        // 关闭时写入快照，重启时从快照加载索引，并删除快照
+        assert!(engine.close().is_ok());
+        std::mem::drop(engine);
+        assert!(snapshot_path.is_file());
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(!snapshot_path.is_file());
+        assert_eq!(engine2.stat().unwrap().key_num, 900);
+        assert_eq!(AppErrors::KeyNotFound, engine2.get(get_test_key(10)).err().unwrap());
+        assert_eq!(engine2.get(get_test_key(500)).unwrap(), get_test_value(500));
+
+        // 没有关闭直接重启，重放所有的数据文件
+        for i in 1000..1200 {
+            assert!(engine2.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine3.stat().unwrap().key_num, 1100);
+
+        // 快照之后写入的数据在重启时重放
+        assert!(engine3.close().is_ok());
+        assert!(engine3.put(get_test_key(1), get_test_value(1)).is_ok());
+        assert!(engine3.delete(get_test_key(500)).is_ok());
+        std::mem::drop(engine3);
+        let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine4.stat().unwrap().key_num, 1100);
+        assert_eq!(engine4.get(get_test_key(1)).unwrap(), get_test_value(1));
+        assert_eq!(AppErrors::KeyNotFound, engine4.get(get_test_key(500)).err().unwrap());
+
+        // 快照损坏时重放所有的数据文件
+        assert!(engine4.close().is_ok());
+        std::mem::drop(engine4);
+        let mut data = fs::read(&snapshot_path).unwrap();
+        let mid = data.len() / 2;
+        data[mid] ^= 0xff;
+        fs::write(&snapshot_path, data).unwrap();
+        let engine5 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine5.stat().unwrap().key_num, 1100);
+        assert_eq!(engine5.get(get_test_key(600)).unwrap(), get_test_value(600));
+
+        // merge 之后快照失效
+        assert!(engine5.merge().is_ok());
+        assert!(engine5.close().is_ok());
+        std::mem::drop(engine5);
+        let engine6 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine6.stat().unwrap().key_num, 1100);
+        for i in 100..1200 {
+            if i == 500 {
+                continue;
+            }
+            assert_eq!(engine6.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_parallel_index_load() {
+        struct Progress(Mutex<Vec<(usize, usize)>>);
+        impl IndexLoadProgress for Progress {
+            fn on_file_loaded(&self, files_loaded: usize, total_files: usize) {
+                self.0.lock().push((files_loaded, total_files));
+            }
+        }
+
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-parallel-load");
+        opts.data_file_size = 64 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        // 后面的数据文件中的更新和删除覆盖前面的数据
+        for i in 0..500 {
+            assert!(engine.put(get_test_key(i), Bytes::from("new-value")).is_ok());
+        }
+        for i in 500..1000 {
+            assert!(engine.delete(get_test_key(i)).is_ok());
+        }
+        // 事务中的数据提交之后才生效
+        let wb = engine.new_write_batch(WriteBatchOptions::default()).unwrap();
+        for i in 1000..1100 {
+            assert!(wb.put(get_test_key(i), Bytes::from("batch-value")).is_ok());
+        }
+        assert!(wb.commit().is_ok());
+        let data_file_num = engine.stat().unwrap().data_file_num;
+        assert!(data_file_num > 4);
+        let reclaim_size = engine.reclaim_size.load(Ordering::SeqCst);
+        std::mem::drop(wb);
+        std::mem::drop(engine);
+
+        let progress = Arc::new(Progress(Mutex::new(Vec::new())));
+        opts.index_load_threads = 4;
+        opts.index_load_progress = Some(progress.clone());
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.stat().unwrap().key_num, 2500);
+        // 从新到旧加载时被覆盖的旧数据同样计入可以回收的空间
+        assert_eq!(engine2.reclaim_size.load(Ordering::SeqCst), reclaim_size);
+        for i in 0..500 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), Bytes::from("new-value"));
+        }
+        for i in 500..1000 {
+            assert_eq!(AppErrors::KeyNotFound, engine2.get(get_test_key(i)).err().unwrap());
+        }
+        for i in 1000..1100 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), Bytes::from("batch-value"));
+        }
+        assert_eq!(engine2.get(get_test_key(2000)).unwrap(), get_test_value(2000));
+        assert!(engine2.seq_no.load(Ordering::SeqCst) > 1);
+
+        // 每加载完一个数据文件按顺序回调一次
+        let calls = progress.0.lock().clone();
+        assert_eq!(calls.len(), data_file_num);
+        for (i, (files_loaded, total_files)) in calls.iter().enumerate() {
+            assert_eq!(*files_loaded, i + 1);
+            assert_eq!(*total_files, data_file_num);
+        }
+
+        // 加载之后继续追加写入
+        assert!(engine2.put(get_test_key(1), get_test_value(1)).is_ok());
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine3.get(get_test_key(1)).unwrap(), get_test_value(1));
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_lazy_open() {
+        // 加载完第一个数据文件之后阻塞，直到测试放行
+        #[derive(Default)]
+        struct Gate {
+            state: Mutex<(bool, bool)>, // 是否已经加载完第一个数据文件，是否放行
+            cond: parking_lot::Condvar,
+        }
+        impl IndexLoadProgress for Gate {
+            fn on_file_loaded(&self, _files_loaded: usize, _total_files: usize) {
+                let mut state = self.state.lock();
+                state.0 = true;
+                self.cond.notify_all();
+                while !state.1 {
+                    self.cond.wait(&mut state);
+                }
+            }
+        }
+
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-lazy-open");
+        opts.data_file_size = 64 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3000 {
+            assert!(engine.put(get_test_key(i), get_test_value(i)).is_ok());
+        }
+        for i in 0..100 {
+            assert!(engine.put(get_test_key(i), Bytes::from("new-value")).is_ok());
+        }
+        assert!(engine.delete(get_test_key(2999)).is_ok());
+        // 更新的数据都在最新的数据文件中
+        let active_fid = engine.active_file.read().get_file_id();
+        assert!(active_fid > 0);
+        for i in 0..100 {
+            assert_eq!(engine.index.get(get_test_key(i).to_vec()).unwrap().unwrap().file_id, active_fid);
+        }
+        std::mem::drop(engine);
+
+        let gate = Arc::new(Gate::default());
+        opts.lazy_open = true;
+        opts.index_load_progress = Some(gate.clone());
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        {
+            let mut state = gate.state.lock();
+            while !state.0 {
+                gate.cond.wait(&mut state);
+            }
+        }
+
+        // 最新的数据文件最先加载，其中的 key 不需要等待加载完成，读到的是最新的数据
+        for i in 0..100 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), Bytes::from("new-value"));
+        }
+        assert_eq!(AppErrors::KeyNotFound, engine2.get(get_test_key(2999)).err().unwrap());
+        assert!(engine2.stat().unwrap().key_num < 2999);
+        assert!(!engine2.is_ready());
+
+        // 还没有加载到的 key 等待加载完成
+        let gate2 = gate.clone();
+        let opener = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            gate2.state.lock().1 = true;
+            gate2.cond.notify_all();
+        });
+        assert_eq!(engine2.get(get_test_key(1000)).unwrap(), get_test_value(1000));
+        assert!(engine2.is_ready());
+        opener.join().unwrap();
+        assert!(engine2.wait_ready().is_ok());
+
+        assert_eq!(AppErrors::KeyNotFound, engine2.get(get_test_key(2999)).err().unwrap());
+        assert_eq!(engine2.stat().unwrap().key_num, 2999);
+        for i in 1..100 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), Bytes::from("new-value"));
+        }
+        for i in 100..2999 {
+            assert_eq!(engine2.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        // 加载完成之后继续追加写入
+        assert!(engine2.put(get_test_key(2999), get_test_value(2999)).is_ok());
+        std::mem::drop(engine2);
+        opts.lazy_open = false;
+        opts.index_load_progress = None;
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine3.is_ready());
+        assert_eq!(engine3.get(get_test_key(2999)).unwrap(), get_test_value(2999));
+        assert_eq!(engine3.get(get_test_key(0)).unwrap(), Bytes::from("new-value"));
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
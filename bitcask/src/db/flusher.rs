@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use log::error;
+use parking_lot::{Condvar, Mutex, RwLock};
+use crate::data::data_files_mod::data_file::DataFile;
+
+/// 后台刷盘线程，按照固定的时间间隔持久化活跃文件
+pub(crate) struct Flusher {
+    stop: Arc<(Mutex<bool>, Condvar)>, // 停止标识，关闭时唤醒后台线程
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Flusher {
+    pub(crate) fn start(
+        interval: Duration,
+        active_file: Arc<RwLock<DataFile>>,
+        bytes_write: Arc<AtomicUsize>,
+    ) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || {
+            let (lock, cond) = &*thread_stop;
+            loop {
+                let stopped = {
+                    let mut stopped = lock.lock();
+                    if !*stopped {
+                        cond.wait_for(&mut stopped, interval);
+                    }
+                    *stopped
+                };
+
+                // 距离上一次持久化有新的写入才需要 sync，退出前再持久化一次
+                if bytes_write.swap(0, Ordering::SeqCst) > 0
+                    && let Err(e) = active_file.read().sync()
+                {
+                    error!("failed to sync active file in background: {}", e);
+                }
+                if stopped {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        let (lock, cond) = &*self.stop;
+        *lock.lock() = true;
+        cond.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
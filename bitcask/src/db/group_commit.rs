@@ -0,0 +1,129 @@
+use std::time::Duration;
+use parking_lot::{Condvar, Mutex};
+use crate::errors::AppResult;
+
+/// 组提交，并发的同步写入在追加到活跃文件之后共享同一次 sync
+pub(crate) struct GroupCommit {
+    state: Mutex<GroupState>,
+    cond: Condvar,
+    max_wait: Duration, // leader 执行 sync 之前等待更多写入加入的最长时间
+}
+
+struct GroupState {
+    written_seq: u64, // 已经写入活跃文件的最新写入序号
+    synced_seq: u64,  // 已经持久化的最新写入序号
+    syncing: bool,    // 是否已经有 leader 在等待或者执行 sync
+}
+
+impl GroupCommit {
+    pub(crate) fn new(max_wait: Duration) -> Self {
+        Self {
+            state: Mutex::new(GroupState {
+                written_seq: 0,
+                synced_seq: 0,
+                syncing: false,
+            }),
+            cond: Condvar::new(),
+            max_wait,
+        }
+    }
+
+    /// 为刚写入的数据分配写入序号，需要在持有活跃文件写锁时调用，保证序号和写入顺序一致
+    pub(crate) fn next_seq(&self) -> u64 {
+        let mut state = self.state.lock();
+        state.written_seq += 1;
+        state.written_seq
+    }
+
+    /// 阻塞直到序号 seq 之前的写入都已经持久化
+    /// 没有正在进行的 sync 时当前线程成为 leader，等待 max_wait 后执行一次 sync，
+    /// 期间到达的写入都由这一次 sync 持久化
+    pub(crate) fn wait_synced<F>(&self, seq: u64, sync: F) -> AppResult<()>
+    where
+        F: Fn() -> AppResult<()>,
+    {
+        let mut state = self.state.lock();
+        loop {
+            if state.synced_seq >= seq {
+                return Ok(());
+            }
+
+            if state.syncing {
+                // 已经有 leader，等待其 sync 完成
+                self.cond.wait(&mut state);
+                continue;
+            }
+
+            state.syncing = true;
+            if !self.max_wait.is_zero() {
+                self.cond.wait_for(&mut state, self.max_wait);
+            }
+            // 在此之前写入的数据都会被本次 sync 持久化
+            let target = state.written_seq;
+            let res = parking_lot::MutexGuard::unlocked(&mut state, &sync);
+            state.syncing = false;
+            if res.is_ok() && target > state.synced_seq {
+                state.synced_seq = target;
+            }
+            // 唤醒等待的写入，sync 失败时由它们重新发起 sync
+            self.cond.notify_all();
+            res?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use crate::errors::AppErrors;
+
+    #[test]
+    fn test_group_commit_share_sync() {
+        let gc = Arc::new(GroupCommit::new(Duration::from_millis(2)));
+        let sync_count = Arc::new(AtomicUsize::new(0));
+
+        let mut handles = vec![];
+        for _ in 0..8 {
+            let gc = gc.clone();
+            let sync_count = sync_count.clone();
+            handles.push(thread::spawn(move || {
+                for _ in 0..100 {
+                    let seq = gc.next_seq();
+                    let res = gc.wait_synced(seq, || {
+                        sync_count.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(1));
+                        Ok(())
+                    });
+                    assert!(res.is_ok());
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 800 次写入共享了更少的 sync
+        let count = sync_count.load(Ordering::SeqCst);
+        assert!(count > 0);
+        assert!(count < 800);
+    }
+
+    #[test]
+    fn test_group_commit_sync_error() {
+        let gc = GroupCommit::new(Duration::ZERO);
+        let seq1 = gc.next_seq();
+        let res1 = gc.wait_synced(seq1, || Err(AppErrors::FailedSyncDataFile));
+        assert_eq!(AppErrors::FailedSyncDataFile, res1.err().unwrap());
+
+        // 失败之后重新 sync
+        let res2 = gc.wait_synced(seq1, || Ok(()));
+        assert!(res2.is_ok());
+
+        // 已经持久化的序号不会再次 sync
+        let res3 = gc.wait_synced(seq1, || Err(AppErrors::FailedSyncDataFile));
+        assert!(res3.is_ok());
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use log::{error, warn};
+use parking_lot::{Condvar, Mutex, RwLock};
+use crate::batch::{parse_log_record_key, NON_TRANSACTION_SEQ_NO};
+use crate::data::data_files_mod::data_file::DataFile;
+use crate::data::data_files_mod::utils::MERGE_FINISHED_FILE_NAME;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::data::log_record_mod::log_record_type::LogRecordType;
+use crate::errors::{AppErrors, AppResult};
+use crate::index::indexer::Indexer;
+use crate::merge::load_index_from_hint_file;
+use crate::options::io_type::IOType;
+use crate::options::options::Options;
+use super::index_snapshot::{decode_snapshot_entries, read_index_snapshot, IndexSnapshot};
+use super::utils::{data_file_io_type, sealed_file_io_type};
+
+/// 启动时加载索引需要的数据库状态，可以转移到后台线程中执行
+///
+/// 按照文件 id 从新到旧重放数据文件，最后加载快照或者 hint 文件。每个 key 第一次出现的数据就是最新的数据，
+/// 之后出现的都是被覆盖的旧数据，直接作为可以回收的空间。已经加载到索引中的 key 不会再被修改，
+/// 后台加载时可以直接读取；已经确定被删除的 key 记录在 ready 中，加载完成之后释放
+pub(crate) struct IndexLoader {
+    pub(crate) options: Arc<Options>,
+    pub(crate) index: Arc<dyn Indexer>,
+    pub(crate) ready: Arc<IndexReadiness>,
+    pub(crate) active_file: Arc<RwLock<DataFile>>,
+    pub(crate) older_files: Arc<RwLock<HashMap<u32, DataFile>>>,
+    pub(crate) file_ids: Vec<u32>,
+    pub(crate) seq_no: Arc<AtomicUsize>,
+    pub(crate) reclaim_size: Arc<AtomicUsize>,
+}
+
+impl IndexLoader {
+    /// 加载索引，更新事务序列号，并设置活跃文件的写偏移
+    pub(crate) fn load(&self) -> AppResult<()> {
+        // 快照有效时不需要再加载 hint 文件和快照之前的数据
+        let snapshot = read_index_snapshot(&self.options.dir_path).filter(|snapshot| {
+            let fresh = self.index_snapshot_is_fresh(snapshot);
+            if !fresh {
+                warn!("index snapshot is stale, rebuild index from data files");
+            }
+            fresh
+        });
+        let start = snapshot.as_ref().map(|snapshot| (snapshot.file_id, snapshot.offset));
+
+        // 数据文件中的数据比快照和 hint 文件中的新，先加载
+        let mut seq_no = NON_TRANSACTION_SEQ_NO;
+        let max_seq_no = self.load_index_from_data_files(start)?;
+        match snapshot {
+            Some(snapshot) => {
+                decode_snapshot_entries(&snapshot.entries, |key, pos| {
+                    self.apply_record(key, LogRecordType::NORMAL, pos)
+                })?;
+                self.reclaim_size.fetch_add(snapshot.reclaim_size, Ordering::SeqCst);
+                seq_no = snapshot.seq_no;
+            }
+            None => load_index_from_hint_file(self.options.dir_path.clone(), |key, pos| {
+                self.apply_record(key, LogRecordType::NORMAL, pos)
+            })?,
+        }
+        self.ready.clear_deleted();
+
+        // 更新当前事务序列号
+        if max_seq_no > 0 {
+            seq_no = seq_no.max(max_seq_no + 1);
+        }
+        if seq_no > 0 {
+            self.seq_no.store(seq_no, Ordering::SeqCst);
+        }
+
+        // 重置 IO 类型
+        if self.options.mmap_at_startup {
+            self.reset_io_type()?;
+        }
+        Ok(())
+    }
+
+    // 从数据文件中加载索引，start 为索引快照覆盖到的位置，只需要重放之后写入的数据
+    // 返回数据文件中最大的事务序列号
+    fn load_index_from_data_files(&self, start: Option<(u32, u64)>) -> AppResult<usize> {
+        let mut current_seq_no = NON_TRANSACTION_SEQ_NO;
+        // 数据文件为空，直接返回
+        if self.file_ids.is_empty() {
+            return Ok(current_seq_no);
+        }
+
+        // 拿到最近未参与 merge 的文件 id
+        let mut has_merge = false;
+        let mut non_merge_fid = 0;
+        let merge_fin_file = self.options.dir_path.join(MERGE_FINISHED_FILE_NAME);
+        if merge_fin_file.is_file() {
+            let merge_fin_file = DataFile::new_merge_fin_file(self.options.dir_path.clone())?;
+            let merge_fin_record = merge_fin_file.read_log_record(0)?;
+            let v = String::from_utf8(merge_fin_record.record.value).unwrap();
+            non_merge_fid = v.parse::<u32>().unwrap();
+            has_merge = true;
+        }
+
+        // 加载使用单独打开的数据文件，不持有引擎中数据文件的锁，后台加载期间可以正常读取和切换 IO 类型
+        let io_type = match self.options.mmap_at_startup {
+            true => IOType::MemoryMap,
+            false => IOType::StandardFIO,
+        };
+        let active_fid = *self.file_ids.last().unwrap();
+        let mut data_files: Vec<(DataFile, u64)> = Vec::new();
+        for file_id in self.file_ids.iter().rev() {
+            // 如果比最近未参与 merge 的文件 id 更小，则已经从 hint 文件中加载索引了
+            if has_merge && *file_id < non_merge_fid {
+                continue;
+            }
+
+            // 快照之前的数据已经在索引中了
+            let mut offset = 0;
+            if let Some((start_fid, start_offset)) = start {
+                if *file_id < start_fid {
+                    continue;
+                }
+                if *file_id == start_fid {
+                    offset = start_offset;
+                }
+            }
+            data_files.push((DataFile::new(self.options.dir_path.clone(), *file_id, io_type)?, offset));
+        }
+        let files: Vec<(&DataFile, u64)> = data_files.iter().map(|(data_file, offset)| (data_file, *offset)).collect();
+
+        // 已经提交的事务，标识事务完成的数据在事务中所有数据的后面，从新到旧重放时最先读到
+        let mut committed: HashSet<usize> = HashSet::new();
+        let total_files = files.len();
+        let threads = self.options.index_load_threads;
+
+        // 数据文件可以并发解码，但是必须按照文件 id 从新到旧的顺序更新索引
+        replay_data_files(&files, threads, |i, replay_file| {
+            for record in replay_file.records.into_iter().rev() {
+                // 更新当前事务序列号
+                if record.seq_no > current_seq_no {
+                    current_seq_no = record.seq_no;
+                }
+
+                if record.rec_type == LogRecordType::TXNFINISHED {
+                    committed.insert(record.seq_no);
+                    continue;
+                }
+                // 没有提交的事务中的数据不生效
+                if record.seq_no != NON_TRANSACTION_SEQ_NO && !committed.contains(&record.seq_no) {
+                    continue;
+                }
+                self.apply_record(record.key, record.rec_type, record.pos)?;
+            }
+
+            // 设置活跃文件的 offset
+            if files[i].0.get_file_id() == active_fid {
+                self.active_file.read().set_write_off(replay_file.end_offset);
+            }
+            if let Some(progress) = self.options.index_load_progress.as_ref() {
+                progress.on_file_loaded(i + 1, total_files);
+            }
+            Ok(())
+        })?;
+        Ok(current_seq_no)
+    }
+
+    // 从新到旧重放时只有 key 第一次出现的数据生效，之后的数据都是被覆盖的旧数据，累计为可以 merge 回收的数据量
+    fn apply_record(&self, key: Vec<u8>, rec_type: LogRecordType, pos: LogRecordPos) -> AppResult<()> {
+        if self.ready.is_deleted(&key) || self.index.get(key.clone())?.is_some() {
+            self.reclaim_size.fetch_add(pos.size as usize, Ordering::SeqCst);
+            return Ok(());
+        }
+        if rec_type == LogRecordType::NORMAL {
+            self.index.put(key, pos)?;
+        } else if rec_type == LogRecordType::DELETED {
+            self.reclaim_size.fetch_add(pos.size as usize, Ordering::SeqCst);
+            self.ready.mark_deleted(key);
+        }
+        Ok(())
+    }
+
+    // 快照覆盖的数据文件必须仍然存在，并且数据没有被截断
+    fn index_snapshot_is_fresh(&self, snapshot: &IndexSnapshot) -> bool {
+        if !self.file_ids.contains(&snapshot.file_id) {
+            return false;
+        }
+        let active_file = self.active_file.read();
+        let file_size = match active_file.get_file_id() == snapshot.file_id {
+            true => active_file.file_size(),
+            false => match self.older_files.read().get(&snapshot.file_id) {
+                Some(data_file) => data_file.file_size(),
+                None => return false,
+            },
+        };
+        snapshot.offset <= file_size
+    }
+
+    // 启动时通过 mmap 加载完索引之后，将数据文件切换为配置的 IO 类型
+    pub(crate) fn reset_io_type(&self) -> AppResult<()> {
+        let mut active_file = self.active_file.write();
+        active_file.set_io_manager(self.options.dir_path.clone(), data_file_io_type(&self.options))?;
+        let mut older_files = self.older_files.write();
+        let io_type = sealed_file_io_type(&self.options);
+        if io_type == IOType::MemoryMap {
+            return Ok(());
+        }
+        for (_, file) in older_files.iter_mut() {
+            file.set_io_manager(self.options.dir_path.clone(), io_type)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum IndexLoadState {
+    Loading,
+    Ready,
+    Failed,
+}
+
+/// 索引的加载状态，后台加载索引时用于等待加载完成
+pub(crate) struct IndexReadiness {
+    state: Mutex<IndexLoadState>,
+    cond: Condvar,
+    deleted: Mutex<HashSet<Vec<u8>>>, // 加载期间已经确定被删除的 key
+}
+
+impl IndexReadiness {
+    pub(crate) fn ready() -> Self {
+        Self {
+            state: Mutex::new(IndexLoadState::Ready),
+            cond: Condvar::new(),
+            deleted: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub(crate) fn loading() -> Self {
+        Self {
+            state: Mutex::new(IndexLoadState::Loading),
+            cond: Condvar::new(),
+            deleted: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub(crate) fn mark_deleted(&self, key: Vec<u8>) {
+        self.deleted.lock().insert(key);
+    }
+
+    /// key 是否已经确定被删除，只在加载期间有效
+    pub(crate) fn is_deleted(&self, key: &[u8]) -> bool {
+        self.deleted.lock().contains(key)
+    }
+
+    pub(crate) fn clear_deleted(&self) {
+        *self.deleted.lock() = HashSet::new();
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        *self.state.lock() == IndexLoadState::Ready
+    }
+
+    /// 等待索引加载完成，加载失败时返回错误
+    pub(crate) fn wait(&self) -> AppResult<()> {
+        let mut state = self.state.lock();
+        while *state == IndexLoadState::Loading {
+            self.cond.wait(&mut state);
+        }
+        match *state {
+            IndexLoadState::Ready => Ok(()),
+            _ => Err(AppErrors::FailedToLoadIndex),
+        }
+    }
+
+    /// 记录后台加载的结果，并唤醒所有等待的线程
+    pub(crate) fn finish(&self, res: AppResult<()>) {
+        let mut state = self.state.lock();
+        *state = match res {
+            Ok(()) => IndexLoadState::Ready,
+            Err(e) => {
+                error!("failed to load index in background: {}", e);
+                IndexLoadState::Failed
+            }
+        };
+        self.cond.notify_all();
+    }
+}
+
+/// 从数据文件中解码出的一条需要重放到索引中的数据
+pub(crate) struct ReplayRecord {
+    pub(crate) key: Vec<u8>,            // 实际的 key
+    pub(crate) seq_no: usize,           // 事务序列号
+    pub(crate) rec_type: LogRecordType, // 数据类型
+    pub(crate) pos: LogRecordPos,       // 数据的位置信息
+}
+
+/// 一个数据文件的解码结果，以及读取到的文件末尾
+pub(crate) struct ReplayFile {
+    pub(crate) records: Vec<ReplayRecord>,
+    pub(crate) end_offset: u64,
+}
+
+// 从 offset 开始读取数据文件中的所有数据
+fn read_replay_file(data_file: &DataFile, offset: u64) -> AppResult<ReplayFile> {
+    let file_id = data_file.get_file_id();
+    let mut offset = offset;
+    let mut records = Vec::new();
+    loop {
+        let (log_record, size) = match data_file.read_log_record(offset) {
+            Ok(result) => (result.record, result.size),
+            Err(AppErrors::ReadDataFileEOF) => break,
+            Err(e) => return Err(e),
+        };
+
+        // 解析 key，拿到实际的 key 和 seq no
+        let (key, seq_no) = parse_log_record_key(log_record.key);
+        records.push(ReplayRecord {
+            key,
+            seq_no,
+            rec_type: log_record.rec_type,
+            pos: LogRecordPos {
+                file_id,
+                offset,
+                size: size as u32,
+            },
+        });
+        offset += size as u64;
+    }
+    Ok(ReplayFile {
+        records,
+        end_offset: offset,
+    })
+}
+
+/// 依次解码 files 中的数据文件，并按照 files 的顺序交给 apply 处理
+/// threads 大于 1 时多个线程并发解码，解码的结果在当前线程中按顺序应用，
+/// 最多领先 threads * 2 个文件解码，避免乱序完成的结果占用过多的内存
+pub(crate) fn replay_data_files<F>(files: &[(&DataFile, u64)], threads: usize, mut apply: F) -> AppResult<()>
+where
+    F: FnMut(usize, ReplayFile) -> AppResult<()>,
+{
+    if threads <= 1 || files.len() <= 1 {
+        for (i, (data_file, offset)) in files.iter().enumerate() {
+            apply(i, read_replay_file(data_file, *offset)?)?;
+        }
+        return Ok(());
+    }
+
+    let window = threads * 2;
+    let next = AtomicUsize::new(0); // 下一个需要解码的文件下标
+    let applied = Mutex::new(0usize); // 已经应用的文件数量
+    let cond = Condvar::new();
+    let stopped = AtomicBool::new(false); // 出错之后通知解码线程退出
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0..threads.min(files.len()) {
+            let sender = sender.clone();
+            let (next, applied, cond, stopped) = (&next, &applied, &cond, &stopped);
+            s.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= files.len() {
+                    return;
+                }
+                {
+                    let mut applied = applied.lock();
+                    while i >= *applied + window && !stopped.load(Ordering::SeqCst) {
+                        cond.wait(&mut applied);
+                    }
+                }
+                if stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                let (data_file, offset) = files[i];
+                if sender.send((i, read_replay_file(data_file, offset))).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(sender);
+
+        let res = apply_in_order(files.len(), &receiver, &applied, &cond, &mut apply);
+        if res.is_err() {
+            stopped.store(true, Ordering::SeqCst);
+            let _guard = applied.lock();
+            cond.notify_all();
+        }
+        res
+    })
+}
+
+// 按照文件的顺序应用解码线程返回的结果，每应用完一个文件唤醒等待的解码线程
+fn apply_in_order<F>(
+    total: usize,
+    receiver: &mpsc::Receiver<(usize, AppResult<ReplayFile>)>,
+    applied: &Mutex<usize>,
+    cond: &Condvar,
+    apply: &mut F,
+) -> AppResult<()>
+where
+    F: FnMut(usize, ReplayFile) -> AppResult<()>,
+{
+    let mut pending = HashMap::new();
+    for i in 0..total {
+        let replay_file = loop {
+            if let Some(result) = pending.remove(&i) {
+                break result;
+            }
+            let (j, result) = receiver.recv().unwrap();
+            pending.insert(j, result);
+        };
+        apply(i, replay_file?)?;
+        *applied.lock() += 1;
+        cond.notify_all();
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use bytes::{Buf, BufMut, BytesMut};
+use log::{error, warn};
+use prost::encoding::{decode_varint, encode_varint};
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::errors::{AppErrors, AppResult};
+use crate::index::indexer::Indexer;
+use crate::options::iterator_options::IteratorOptions;
+use super::engine::Engine;
+
+pub(crate) const INDEX_SNAPSHOT_FILE_NAME: &str = "index-snapshot";
+const INDEX_SNAPSHOT_TMP_FILE_NAME: &str = "index-snapshot.tmp";
+
+// 索引快照文件的格式，key 和位置信息一直存放到 crc 校验值之前
+//
+//	+---------+--------+--------+--------------+----------------------------------+-----------+
+//	| file_id | offset | seq_no | reclaim_size |  (key 长度, key, 位置信息) * N     | crc 校验值 |
+//	+---------+--------+--------+--------------+----------------------------------+-----------+
+//	   4字节     8字节     8字节       8字节                   变长                    4字节
+const SNAPSHOT_HEADER_SIZE: usize = 28;
+
+/// 索引快照覆盖的数据范围
+/// 快照中的索引包含了 file_id 之前的所有数据文件，以及 file_id 数据文件中 offset 之前的数据
+pub(crate) struct IndexSnapshot {
+    pub(crate) file_id: u32,        // 快照覆盖到的数据文件 id
+    pub(crate) offset: u64,         // 快照覆盖到的数据文件偏移
+    pub(crate) seq_no: usize,       // 写入快照时的事务序列号
+    pub(crate) reclaim_size: usize, // 写入快照时可以 merge 回收的数据量
+    pub(crate) entries: Vec<u8>,    // 编码之后的 key 和位置信息
+}
+
+impl Engine {
+    /// 将内存索引写入快照文件，下次打开数据库时只需要重放快照之后写入的数据
+    /// 先写入临时文件再重命名，写入过程中崩溃不会留下不完整的快照
+    /// 写入期间持有提交锁和写锁，快照中的索引和记录的活跃文件偏移保持一致
+    pub(crate) fn write_index_snapshot(&self) -> AppResult<()> {
+        // 存在已经溢写但尚未提交的批次时，快照之前的数据文件中有未完成的事务，不写入快照
+        let _commit_guard = self.batch_commit_lock.lock();
+        let _write_guard = self.write_lock.write();
+        if self.spilled_batches.load(Ordering::SeqCst) > 0 {
+            warn!("skip index snapshot, there are uncommitted write batches");
+            return Ok(());
+        }
+
+        let dir_path = self.options.dir_path.clone();
+        let tmp_path = dir_path.join(INDEX_SNAPSHOT_TMP_FILE_NAME);
+        let active_file = self.active_file.read();
+        let snapshot = IndexSnapshot {
+            file_id: active_file.get_file_id(),
+            offset: active_file.get_write_off(),
+            seq_no: self.seq_no.load(Ordering::SeqCst),
+            reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
+            entries: Vec::new(),
+        };
+        if let Err(e) = encode_index_snapshot(&tmp_path, &snapshot, self.index.as_ref()) {
+            error!("failed to write index snapshot: {}", e);
+            let _ = fs::remove_file(&tmp_path);
+            return Err(AppErrors::FailedToWriteIndexSnapshot);
+        }
+        if let Err(e) = fs::rename(&tmp_path, dir_path.join(INDEX_SNAPSHOT_FILE_NAME)) {
+            error!("failed to rename index snapshot: {}", e);
+            return Err(AppErrors::FailedToWriteIndexSnapshot);
+        }
+        Ok(())
+    }
+}
+
+/// 读取并校验快照文件，快照不存在或者损坏时返回 None，此时需要完整地重放数据文件
+/// 快照中的索引数据只校验不加载，之后通过 decode_snapshot_entries 加载
+pub(crate) fn read_index_snapshot(dir_path: &Path) -> Option<IndexSnapshot> {
+    let path = dir_path.join(INDEX_SNAPSHOT_FILE_NAME);
+    if !path.is_file() {
+        return None;
+    }
+
+    let res = decode_index_snapshot(&path);
+    // 快照只在关闭时写入，打开之后的写入不会更新快照，加载之后删除，避免崩溃重启时使用过期的快照
+    if let Err(e) = fs::remove_file(&path) {
+        error!("failed to remove index snapshot: {}", e);
+    }
+    match res {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            warn!("index snapshot is corrupted, rebuild index from data files: {}", e);
+            None
+        }
+    }
+}
+
+// 校验并解码快照文件的头部，并检查所有的索引数据都可以被解码
+fn decode_index_snapshot(path: &Path) -> AppResult<IndexSnapshot> {
+    let mut data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("failed to read index snapshot: {}", e);
+            return Err(AppErrors::InvalidIndexSnapshot);
+        }
+    };
+    if data.len() < SNAPSHOT_HEADER_SIZE + 4 {
+        return Err(AppErrors::InvalidIndexSnapshot);
+    }
+    let (body, mut crc) = data.split_at(data.len() - 4);
+    if crc32fast::hash(body) != crc.get_u32() {
+        return Err(AppErrors::InvalidIndexSnapshot);
+    }
+
+    let mut header = &body[..SNAPSHOT_HEADER_SIZE];
+    let (file_id, offset) = (header.get_u32(), header.get_u64());
+    let (seq_no, reclaim_size) = (header.get_u64() as usize, header.get_u64() as usize);
+    data.truncate(data.len() - 4);
+    data.drain(..SNAPSHOT_HEADER_SIZE);
+    decode_snapshot_entries(&data, |_, _| Ok(()))?;
+    Ok(IndexSnapshot {
+        file_id,
+        offset,
+        seq_no,
+        reclaim_size,
+        entries: data,
+    })
+}
+
+/// 依次解码快照中的 key 和位置信息
+pub(crate) fn decode_snapshot_entries<F>(entries: &[u8], mut f: F) -> AppResult<()>
+where
+    F: FnMut(Vec<u8>, LogRecordPos) -> AppResult<()>,
+{
+    let mut buf = entries;
+    while buf.has_remaining() {
+        let key_len = decode_varint(&mut buf).map_err(|_| AppErrors::InvalidIndexSnapshot)? as usize;
+        if buf.remaining() < key_len {
+            return Err(AppErrors::InvalidIndexSnapshot);
+        }
+        let key = buf[..key_len].to_vec();
+        buf.advance(key_len);
+        let pos = decode_snapshot_pos(&mut buf)?;
+        f(key, pos)?;
+    }
+    Ok(())
+}
+
+// 编码快照并写入文件，持久化之后返回
+fn encode_index_snapshot(path: &Path, snapshot: &IndexSnapshot, index: &dyn Indexer) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+    let mut hasher = crc32fast::Hasher::new();
+
+    let mut buf = BytesMut::with_capacity(SNAPSHOT_HEADER_SIZE);
+    buf.put_u32(snapshot.file_id);
+    buf.put_u64(snapshot.offset);
+    buf.put_u64(snapshot.seq_no as u64);
+    buf.put_u64(snapshot.reclaim_size as u64);
+    hasher.update(&buf);
+    writer.write_all(&buf)?;
+
+    // 遍历索引，依次写入 key 和位置信息
+    let mut iter = index.iterator(IteratorOptions::default());
+    while let Some((key, pos)) = iter.next() {
+        buf.clear();
+        encode_varint(key.len() as u64, &mut buf);
+        buf.put_slice(key);
+        buf.put_slice(&pos.encode());
+        hasher.update(&buf);
+        writer.write_all(&buf)?;
+    }
+    // 索引读取失败时快照不完整，不能写入
+    if let Some(e) = iter.error() {
+        return Err(std::io::Error::other(e));
+    }
+
+    writer.write_all(&hasher.finalize().to_be_bytes())?;
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+fn decode_snapshot_pos(buf: &mut &[u8]) -> AppResult<LogRecordPos> {
+    let file_id = decode_varint(buf).map_err(|_| AppErrors::InvalidIndexSnapshot)?;
+    let offset = decode_varint(buf).map_err(|_| AppErrors::InvalidIndexSnapshot)?;
+    let size = decode_varint(buf).map_err(|_| AppErrors::InvalidIndexSnapshot)?;
+    Ok(LogRecordPos {
+        file_id: file_id as u32,
+        offset,
+        size: size as u32,
+    })
+}
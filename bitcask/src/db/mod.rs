@@ -3,3 +3,9 @@
 pub mod engine;
 pub mod stat;
 pub mod utils;
+pub(crate) mod group_commit;
+pub(crate) mod flusher;
+pub(crate) mod value_cache;
+pub(crate) mod index_snapshot;
+pub(crate) mod index_loader;
+pub(crate) mod disk_space;
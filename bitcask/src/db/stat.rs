@@ -9,4 +9,10 @@ pub struct Stat {
     pub reclaim_size: usize,
     // 数据目录占据的磁盘空间大小
     pub disk_size: u64,
+    // value 缓存命中次数
+    pub cache_hits: u64,
+    // value 缓存未命中次数
+    pub cache_misses: u64,
+    // 索引占用的内存大小
+    pub index_memory_usage: usize,
 }
\ No newline at end of file
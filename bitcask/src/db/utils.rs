@@ -5,9 +5,10 @@ use crate::data::data_files_mod::data_file::DataFile;
 use crate::data::data_files_mod::utils::DATA_FILE_NAME_SUFFIX;
 use crate::options::io_type::IOType;
 use crate::options::options::Options;
+use crate::options::sync_policy::SyncPolicy;
 
 // 从数据目录中加载数据文件
-pub fn load_data_files(dir_path: PathBuf, use_mmap: bool) -> AppResult<Vec<DataFile>> {
+pub fn load_data_files(dir_path: PathBuf, opts: &Options) -> AppResult<Vec<DataFile>> {
     // 读取数据目录
     let dir = fs::read_dir(dir_path.clone());
     if dir.is_err() {
@@ -16,23 +17,21 @@ pub fn load_data_files(dir_path: PathBuf, use_mmap: bool) -> AppResult<Vec<DataF
 
     let mut file_ids: Vec<u32> = Vec::new();
     let mut data_files: Vec<DataFile> = Vec::new();
-    for file in dir.unwrap() {
-        if let Ok(entry) = file {
-            // 拿到文件名
-            let file_os_str = entry.file_name();
-            let file_name = file_os_str.to_str().unwrap();
+    for entry in dir.unwrap().flatten() {
+        // 拿到文件名
+        let file_os_str = entry.file_name();
+        let file_name = file_os_str.to_str().unwrap();
 
-            // 判断文件名称是否是以 .data 结尾
-            if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
-                let split_names: Vec<&str> = file_name.split(".").collect();
-                let file_id = match split_names[0].parse::<u32>() {
-                    Ok(fid) => fid,
-                    Err(_) => {
-                        return Err(AppErrors::DataDirectoryCorrupted);
-                    }
-                };
-                file_ids.push(file_id);
-            }
+        // 判断文件名称是否是以 .data 结尾
+        if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
+            let split_names: Vec<&str> = file_name.split(".").collect();
+            let file_id = match split_names[0].parse::<u32>() {
+                Ok(fid) => fid,
+                Err(_) => {
+                    return Err(AppErrors::DataDirectoryCorrupted);
+                }
+            };
+            file_ids.push(file_id);
         }
     }
 
@@ -44,31 +43,76 @@ pub fn load_data_files(dir_path: PathBuf, use_mmap: bool) -> AppResult<Vec<DataF
     // 对文件 id 进行排序，从小到大进行加载
     file_ids.sort();
     // 遍历所有的文件id，依次打开对应的数据文件
+    let active_file_id = *file_ids.last().unwrap();
     for file_id in file_ids.iter() {
-        let mut io_type = IOType::StandardFIO;
-        if use_mmap {
-            io_type = IOType::MemoryMap;
+        let mut file_io_type = match *file_id == active_file_id {
+            true => data_file_io_type(opts),
+            false => sealed_file_io_type(opts),
+        };
+        if opts.mmap_at_startup {
+            file_io_type = IOType::MemoryMap;
         }
-        let data_file = DataFile::new(dir_path.clone(), *file_id, io_type)?;
+        let data_file = DataFile::new(dir_path.clone(), *file_id, file_io_type)?;
         data_files.push(data_file);
     }
 
     Ok(data_files)
 }
 
+// 根据配置获取数据文件读写使用的 IO 类型
+pub fn data_file_io_type(opts: &Options) -> IOType {
+    if opts.direct_io {
+        return IOType::DirectIO;
+    }
+    if opts.io_uring {
+        return IOType::IoUring;
+    }
+    IOType::StandardFIO
+}
+
+// 根据配置获取已经写满的旧数据文件读取使用的 IO 类型
+pub fn sealed_file_io_type(opts: &Options) -> IOType {
+    if opts.mmap_sealed_files {
+        return IOType::MemoryMap;
+    }
+    data_file_io_type(opts)
+}
+
+// 崩溃前预分配的活跃文件没有截断，截断到数据真实的末尾，之后的写入从这里追加
+pub(crate) fn prepare_active_file(active_file: &DataFile, opts: &Options) -> AppResult<()> {
+    active_file.truncate_to_write_off()?;
+    if opts.preallocate {
+        active_file.preallocate(opts.data_file_size)?;
+    }
+    Ok(())
+}
+
 pub fn check_options(opts: &Options) -> Option<AppErrors> {
     let dir_path = opts.dir_path.to_str();
-    if dir_path.is_none() || dir_path.unwrap().len() == 0 {
+    if dir_path.is_none() || dir_path.unwrap().is_empty() {
         return Some(AppErrors::DirPathIsEmpty);
     }
 
-    if opts.data_file_size <= 0u64 {
+    if opts.data_file_size == 0u64 {
         return Some(AppErrors::DataFileSizeTooSmall);
     }
 
+    match opts.sync_policy {
+        SyncPolicy::EveryBytes(0) => return Some(AppErrors::InvalidSyncPolicy),
+        SyncPolicy::EveryInterval(interval) if interval.is_zero() => {
+            return Some(AppErrors::InvalidSyncPolicy);
+        }
+        _ => {}
+    }
+
     if opts.data_file_merge_ratio < 0f32 || opts.data_file_merge_ratio > 1f32 {
         return Some(AppErrors::InvalidMergeRatio);
     }
 
+    // 数据文件只能使用一种 IO 方式
+    if opts.direct_io && opts.io_uring {
+        return Some(AppErrors::ConflictingIOType);
+    }
+
     None
 }
\ No newline at end of file
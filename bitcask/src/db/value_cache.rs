@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+// 缓存分片的数量，降低并发读取时的锁竞争
+const CACHE_SHARDS: usize = 16;
+
+type CacheKey = (u32, u64);
+
+// 每个缓存槽位除了 value 之外额外占用的字节数估算，包括槽位、哈希表中的映射和 Bytes 本身
+const CACHE_SLOT_OVERHEAD: usize = 96;
+
+/// 数据文件读取的 value 缓存，按照 (file_id, offset) 缓存解码后的 value
+/// 数据文件是追加写的，put/delete 写入的是新的位置，已经缓存的位置上的数据不会改变；
+/// merge 生成的数据文件在下次打开数据库时才替换原来的文件，那时缓存已经随引擎一起释放
+pub(crate) struct ValueCache {
+    shards: Vec<Mutex<CacheShard>>,
+    hits: AtomicU64,   // 缓存命中次数
+    misses: AtomicU64, // 缓存未命中次数
+}
+
+/// 使用 CLOCK 算法淘汰的缓存分片，容量按照 key、value 和槽位占用的字节数计算
+struct CacheShard {
+    capacity: usize,                 // 分片的容量
+    used: usize,                     // 已经使用的容量
+    index: HashMap<CacheKey, usize>, // key 到槽位的映射
+    slots: Vec<Option<CacheSlot>>,   // 缓存槽位，淘汰之后置为 None
+    free: Vec<usize>,                // 空闲的槽位
+    hand: usize,                     // CLOCK 指针
+}
+
+struct CacheSlot {
+    key: CacheKey,
+    value: Bytes,
+    referenced: bool, // 最近是否被访问过，CLOCK 指针经过时清除
+}
+
+impl ValueCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let shard_capacity = capacity.div_ceil(CACHE_SHARDS);
+        let shards = (0..CACHE_SHARDS)
+            .map(|_| {
+                Mutex::new(CacheShard {
+                    capacity: shard_capacity,
+                    used: 0,
+                    index: HashMap::new(),
+                    slots: Vec::new(),
+                    free: Vec::new(),
+                    hand: 0,
+                })
+            })
+            .collect();
+        Self {
+            shards,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// 获取缓存的 value
+    pub(crate) fn get(&self, file_id: u32, offset: u64) -> Option<Bytes> {
+        let mut shard = self.shard(file_id, offset).lock();
+        let value = shard.get(&(file_id, offset));
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    /// 缓存 value，容量不足时淘汰最近没有被访问的数据
+    pub(crate) fn insert(&self, file_id: u32, offset: u64, value: Bytes) {
+        let mut shard = self.shard(file_id, offset).lock();
+        shard.insert((file_id, offset), value);
+    }
+
+    /// 缓存命中次数
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// 缓存未命中次数
+    pub(crate) fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn shard(&self, file_id: u32, offset: u64) -> &Mutex<CacheShard> {
+        let hash = (offset ^ ((file_id as u64) << 40)).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        &self.shards[(hash >> 32) as usize % CACHE_SHARDS]
+    }
+}
+
+impl CacheShard {
+    // 缓存一个 value 占用的容量
+    fn charge(value: &Bytes) -> usize {
+        std::mem::size_of::<CacheKey>() + value.len() + CACHE_SLOT_OVERHEAD
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<Bytes> {
+        let slot_idx = *self.index.get(key)?;
+        let slot = self.slots[slot_idx].as_mut().unwrap();
+        slot.referenced = true;
+        Some(slot.value.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, value: Bytes) {
+        // 超过分片容量的 value 不缓存
+        let charge = Self::charge(&value);
+        if charge > self.capacity || self.index.contains_key(&key) {
+            return;
+        }
+        while self.used + charge > self.capacity {
+            self.evict();
+        }
+
+        self.used += charge;
+        let slot = Some(CacheSlot {
+            key,
+            value,
+            referenced: false,
+        });
+        let slot_idx = match self.free.pop() {
+            Some(slot_idx) => {
+                self.slots[slot_idx] = slot;
+                slot_idx
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(key, slot_idx);
+    }
+
+    // 移动 CLOCK 指针，淘汰一个最近没有被访问过的数据
+    fn evict(&mut self) {
+        loop {
+            if self.hand >= self.slots.len() {
+                self.hand = 0;
+            }
+            let slot_idx = self.hand;
+            self.hand += 1;
+
+            let slot = match self.slots[slot_idx].as_mut() {
+                Some(slot) => slot,
+                None => continue,
+            };
+            if slot.referenced {
+                slot.referenced = false;
+                continue;
+            }
+
+            let slot = self.slots[slot_idx].take().unwrap();
+            self.index.remove(&slot.key);
+            self.used -= Self::charge(&slot.value);
+            self.free.push(slot_idx);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_cache_get_and_insert() {
+        let cache = ValueCache::new(1024 * 1024);
+        assert!(cache.get(1, 100).is_none());
+
+        cache.insert(1, 100, Bytes::from("value-a"));
+        cache.insert(2, 100, Bytes::from("value-b"));
+        assert_eq!(cache.get(1, 100).unwrap(), Bytes::from("value-a"));
+        assert_eq!(cache.get(2, 100).unwrap(), Bytes::from("value-b"));
+        assert!(cache.get(1, 200).is_none());
+
+        assert_eq!(cache.hits(), 2);
+        assert_eq!(cache.misses(), 2);
+    }
+
+    #[test]
+    fn test_value_cache_evict() {
+        // 每个分片可以容纳 4 个 value
+        let value = Bytes::from(vec![0u8; 100]);
+        let charge = CacheShard::charge(&value);
+        let cache = ValueCache::new(CACHE_SHARDS * charge * 4);
+        for offset in 0..1000 {
+            cache.insert(0, offset, value.clone());
+        }
+
+        // 使用的容量不超过分片容量
+        for shard in cache.shards.iter() {
+            let shard = shard.lock();
+            assert!(shard.used <= shard.capacity);
+            assert_eq!(shard.used, shard.index.len() * charge);
+        }
+
+        // 超过分片容量的 value 不缓存
+        cache.insert(1, 0, Bytes::from(vec![0u8; charge * 4]));
+        assert!(cache.get(1, 0).is_none());
+    }
+
+    #[test]
+    fn test_value_cache_keep_referenced() {
+        let mut shard = CacheShard {
+            capacity: CacheShard::charge(&Bytes::from(vec![0u8; 100])) * 3,
+            used: 0,
+            index: HashMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            hand: 0,
+        };
+        let value = Bytes::from(vec![0u8; 100]);
+        shard.insert((0, 0), value.clone());
+        shard.insert((0, 1), value.clone());
+        shard.insert((0, 2), value.clone());
+
+        // 最近访问过的数据不会被优先淘汰
+        assert!(shard.get(&(0, 0)).is_some());
+        shard.insert((0, 3), value.clone());
+        assert!(shard.get(&(0, 0)).is_some());
+        assert!(shard.get(&(0, 1)).is_none());
+        assert!(shard.get(&(0, 3)).is_some());
+    }
+}
@@ -5,7 +5,7 @@ use thiserror::Error; // 配合 anyhow
 pub type AppResult<T> = result::Result<T, AppErrors>;
 
 /// 应用全局需要一个兜底的, 容纳所有错误的枚举!
-#[derive(Debug, PartialEq, Error)]
+#[derive(Debug, Clone, PartialEq, Error)]
 pub enum AppErrors {
     #[error("failed to read from data file")]
     FailedReadFromDataFile,
@@ -37,6 +37,9 @@ pub enum AppErrors {
     #[error("database data file size must be greater than 0")]
     DataFileSizeTooSmall,
 
+    #[error("sync bytes and sync interval must be greater than 0")]
+    InvalidSyncPolicy,
+
     #[error("failed to create the database directory")]
     FailedToCreateDatabaseDir,
 
@@ -55,6 +58,12 @@ pub enum AppErrors {
     #[error("exceed the max batch num")]
     ExceedMaxBatchNum,
 
+    #[error("exceed the max batch size")]
+    ExceedMaxBatchSize,
+
+    #[error("a write batch has spilled uncommitted data, try merge later")]
+    WriteBatchSpilling,
+
     #[error("merge is in progress, try again later")]
     MergeInProgress,
 
@@ -64,18 +73,60 @@ pub enum AppErrors {
     #[error("the database directory is used by another process")]
     DatabaseIsUsing,
 
+    #[error("failed to unlock the database directory")]
+    FailedToUnlockDatabase,
+
     #[error("invalid merge ratio, must between 0 and 1")]
     InvalidMergeRatio,
 
+    #[error("direct_io and io_uring can not be enabled at the same time")]
+    ConflictingIOType,
+
     #[error("do not reach the merge ratio")]
     MergeRatioUnreached,
 
+    #[error("merge is cancelled")]
+    MergeCancelled,
+
     #[error("disk space is not enough for merge")]
     MeregeNoEnoughSpace,
 
+    #[error("failed to remove the merge directory")]
+    FailedToRemoveMergeDir,
+
+    #[error("failed to move the merged files into the database directory")]
+    FailedToMoveMergeFiles,
+
+    #[error("disk space is below the reserved size")]
+    DiskSpaceLow,
+
+    #[error("failed to get the available disk space")]
+    FailedToGetDiskSpace,
+
     #[error("failed to copy the database directory")]
     FailedToCopyDirectory,
 
+    #[error("failed to write the index snapshot")]
+    FailedToWriteIndexSnapshot,
+
+    #[error("the index snapshot is corrupted")]
+    InvalidIndexSnapshot,
+
+    #[error("failed to load the index")]
+    FailedToLoadIndex,
+
+    #[error("failed to open the bptree index")]
+    FailedToOpenBPTreeIndex,
+
+    #[error("failed to read from the bptree index")]
+    FailedToReadBPTreeIndex,
+
+    #[error("failed to write to the bptree index")]
+    FailedToWriteBPTreeIndex,
+
+    #[error("failed to reset the stale hybrid index")]
+    FailedToResetHybridIndex,
+
     #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
     WrongTypeOperation,
 }
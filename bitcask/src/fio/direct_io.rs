@@ -0,0 +1,388 @@
+use std::{
+    alloc::{alloc_zeroed, dealloc, Layout},
+    fs::{File, OpenOptions},
+    ops::{Deref, DerefMut},
+    os::unix::prelude::{FileExt, OpenOptionsExt},
+    path::PathBuf,
+    ptr::NonNull,
+};
+use log::error;
+use parking_lot::Mutex;
+use super::io_manager::IOManager;
+use crate::errors::{AppErrors, AppResult};
+
+// O_DIRECT 要求内存地址、读写长度和文件偏移都按照块大小对齐
+const BLOCK_SIZE: usize = 4096;
+// 写缓冲区的大小，攒满之后一次写入磁盘
+const WRITE_BUF_SIZE: usize = 64 * BLOCK_SIZE;
+// 复用的读缓冲区的大小，更大的读取临时分配对齐的缓冲区
+const READ_BUF_SIZE: usize = 16 * BLOCK_SIZE;
+
+/// 按块大小对齐的内存缓冲区
+struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    fn new(len: usize) -> Self {
+        let layout = Layout::from_size_align(len, BLOCK_SIZE).expect("invalid aligned layout");
+        let ptr = unsafe { alloc_zeroed(layout) };
+        Self {
+            ptr: NonNull::new(ptr).expect("failed to allocate aligned buffer"),
+            len,
+        }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.len, BLOCK_SIZE).unwrap();
+        unsafe { dealloc(self.ptr.as_ptr(), layout) };
+    }
+}
+
+/// 写缓冲区，缓存文件末尾尚未按块写入磁盘的数据
+struct WriteBuffer {
+    buf: AlignedBuf, // 缓冲区内存
+    offset: u64,     // 缓冲区起始位置对应的文件偏移，按块对齐
+    len: usize,      // 缓冲区中的有效数据长度
+    file_len: u64,   // 文件应有的长度，即数据末尾和预分配的长度中较大的一个
+}
+
+/// DirectIO 使用 O_DIRECT 绕过内核页缓存的文件 IO
+/// 日志记录的边界不按块对齐，写入先进入对齐的写缓冲区，所有的写入都通过 O_DIRECT 按块进行，
+/// sync 时末尾不足一个块的数据补零写入整个块，再将文件长度截断回去，保证文件中不会留下填充的数据
+pub struct DirectIO {
+    fd: File,                      // 以 O_DIRECT 打开的文件描述符
+    write_buf: Mutex<WriteBuffer>, // 写缓冲区
+    read_buf: Mutex<AlignedBuf>,   // 复用的对齐的读缓冲区
+}
+
+impl DirectIO {
+    pub fn new(file_name: PathBuf) -> AppResult<Self> {
+        let mut direct_opts = OpenOptions::new();
+        direct_opts.create(true).read(true).write(true);
+        #[cfg(target_os = "linux")]
+        direct_opts.custom_flags(libc::O_DIRECT);
+
+        let fd = match direct_opts.open(file_name) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("failed to open data file with O_DIRECT: {}", e);
+                return Err(AppErrors::FailedToOpenDataFile);
+            }
+        };
+
+        // 打开时还不知道数据真实的末尾，先以文件长度作为逻辑大小，恢复之后再通过 truncate 修正
+        let size = match fd.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                error!("failed to get data file metadata: {}", e);
+                return Err(AppErrors::FailedToOpenDataFile);
+            }
+        };
+        let mut write_buf = WriteBuffer {
+            buf: AlignedBuf::new(WRITE_BUF_SIZE),
+            offset: 0,
+            len: 0,
+            file_len: size,
+        };
+        Self::load_tail(&fd, &mut write_buf, size)?;
+
+        Ok(Self {
+            fd,
+            write_buf: Mutex::new(write_buf),
+            read_buf: Mutex::new(AlignedBuf::new(READ_BUF_SIZE)),
+        })
+    }
+
+    // 将逻辑末尾所在的不完整的块加载到写缓冲区中，后续的写入接着追加
+    fn load_tail(fd: &File, write_buf: &mut WriteBuffer, size: u64) -> AppResult<()> {
+        write_buf.offset = size - size % BLOCK_SIZE as u64;
+        write_buf.len = (size - write_buf.offset) as usize;
+        if write_buf.len > 0
+            && let Err(e) = fd.read_at(&mut write_buf.buf[..BLOCK_SIZE], write_buf.offset)
+        {
+            error!("read from data file err: {}", e);
+            return Err(AppErrors::FailedReadFromDataFile);
+        }
+        Ok(())
+    }
+
+    // 将写缓冲区中完整的块通过 O_DIRECT 写入磁盘，剩余不足一个块的数据移动到缓冲区头部
+    fn flush_blocks(&self, write_buf: &mut WriteBuffer) -> AppResult<()> {
+        let full_len = write_buf.len - write_buf.len % BLOCK_SIZE;
+        if full_len == 0 {
+            return Ok(());
+        }
+        if let Err(e) = self.fd.write_all_at(&write_buf.buf[..full_len], write_buf.offset) {
+            error!("write to data file err: {}", e);
+            return Err(AppErrors::FailedWriteToDataFile);
+        }
+
+        let remain = write_buf.len - full_len;
+        write_buf.buf.copy_within(full_len..write_buf.len, 0);
+        write_buf.offset += full_len as u64;
+        write_buf.len = remain;
+        write_buf.file_len = write_buf.file_len.max(write_buf.offset + remain as u64);
+        Ok(())
+    }
+
+    // 将末尾不完整的块补零之后通过 O_DIRECT 写入整个块，该块仍然保留在写缓冲区中，
+    // 写入的块超出了文件应有的长度时，将文件截断回去，去掉填充的数据
+    fn flush_tail(&self, write_buf: &mut WriteBuffer) -> AppResult<()> {
+        if write_buf.len == 0 {
+            return Ok(());
+        }
+        let len = write_buf.len;
+        write_buf.buf[len..BLOCK_SIZE].fill(0);
+        if let Err(e) = self.fd.write_all_at(&write_buf.buf[..BLOCK_SIZE], write_buf.offset) {
+            error!("write to data file err: {}", e);
+            return Err(AppErrors::FailedWriteToDataFile);
+        }
+
+        write_buf.file_len = write_buf.file_len.max(write_buf.offset + len as u64);
+        if write_buf.offset + (BLOCK_SIZE as u64) > write_buf.file_len
+            && let Err(e) = self.fd.set_len(write_buf.file_len)
+        {
+            error!("failed to truncate data file: {}", e);
+            return Err(AppErrors::FailedWriteToDataFile);
+        }
+        Ok(())
+    }
+
+    // 通过 O_DIRECT 读取 [aligned_start, aligned_start + aligned_buf.len()) 范围的数据，返回读取到的字节数
+    fn read_aligned(&self, aligned_buf: &mut [u8], aligned_start: u64) -> AppResult<usize> {
+        let mut read = 0usize;
+        while read < aligned_buf.len() {
+            match self.fd.read_at(&mut aligned_buf[read..], aligned_start + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) => {
+                    error!("read from data file err: {}", e);
+                    return Err(AppErrors::FailedReadFromDataFile);
+                }
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl IOManager for DirectIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> AppResult<usize> {
+        // 持有写缓冲区的锁期间只拷贝位于写缓冲区中的数据，不进行磁盘 IO
+        let (end, disk_end) = {
+            let write_buf = self.write_buf.lock();
+            let size = write_buf.offset + write_buf.len as u64;
+            if offset >= size {
+                return Ok(0);
+            }
+            let end = size.min(offset + buf.len() as u64);
+            if end > write_buf.offset {
+                let mem_start = offset.max(write_buf.offset);
+                let src_start = (mem_start - write_buf.offset) as usize;
+                let len = (end - mem_start) as usize;
+                let dst_start = (mem_start - offset) as usize;
+                buf[dst_start..dst_start + len].copy_from_slice(&write_buf.buf[src_start..src_start + len]);
+            }
+            (end, end.min(write_buf.offset))
+        };
+
+        // 位于写缓冲区之前的数据已经按块写入磁盘且不会再被修改，释放锁之后按块对齐读取
+        if offset < disk_end {
+            let aligned_start = offset - offset % BLOCK_SIZE as u64;
+            let aligned_len = (disk_end - aligned_start).div_ceil(BLOCK_SIZE as u64) as usize * BLOCK_SIZE;
+            // 复用的读缓冲区被其他线程占用或者不够大时，临时分配对齐的缓冲区
+            let mut read_buf = self.read_buf.try_lock().filter(|_| aligned_len <= READ_BUF_SIZE);
+            let mut owned_buf;
+            let aligned_buf: &mut [u8] = match read_buf.as_deref_mut() {
+                Some(read_buf) => &mut read_buf[..aligned_len],
+                None => {
+                    owned_buf = AlignedBuf::new(aligned_len);
+                    &mut owned_buf
+                }
+            };
+            let read = self.read_aligned(aligned_buf, aligned_start)?;
+            let start = (offset - aligned_start) as usize;
+            let len = (disk_end - offset) as usize;
+            if read < start + len {
+                error!("read from data file err: unexpected eof");
+                return Err(AppErrors::FailedReadFromDataFile);
+            }
+            buf[..len].copy_from_slice(&aligned_buf[start..start + len]);
+        }
+        Ok((end - offset) as usize)
+    }
+
+    fn write(&self, buf: &[u8]) -> AppResult<usize> {
+        let mut write_buf = self.write_buf.lock();
+        let mut written = 0usize;
+        while written < buf.len() {
+            let n = (WRITE_BUF_SIZE - write_buf.len).min(buf.len() - written);
+            let start = write_buf.len;
+            write_buf.buf[start..start + n].copy_from_slice(&buf[written..written + n]);
+            write_buf.len += n;
+            written += n;
+            // 写缓冲区满了则写入磁盘
+            if write_buf.len == WRITE_BUF_SIZE {
+                self.flush_blocks(&mut write_buf)?;
+            }
+        }
+        Ok(written)
+    }
+
+    fn sync(&self) -> AppResult<()> {
+        let mut write_buf = self.write_buf.lock();
+        self.flush_blocks(&mut write_buf)?;
+        self.flush_tail(&mut write_buf)?;
+        if let Err(e) = self.fd.sync_all() {
+            error!("failed to sync data file: {}", e);
+            return Err(AppErrors::FailedSyncDataFile);
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        let write_buf = self.write_buf.lock();
+        write_buf.offset + write_buf.len as u64
+    }
+
+    fn preallocate(&self, size: u64) -> AppResult<()> {
+        let mut write_buf = self.write_buf.lock();
+        if let Err(e) = fs2::FileExt::allocate(&self.fd, size) {
+            error!("failed to preallocate data file: {}", e);
+            return Err(AppErrors::FailedWriteToDataFile);
+        }
+        write_buf.file_len = write_buf.file_len.max(size);
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> AppResult<()> {
+        let mut write_buf = self.write_buf.lock();
+        // 先将缓冲的数据写入文件，再截断并重新加载末尾的块
+        self.flush_blocks(&mut write_buf)?;
+        self.flush_tail(&mut write_buf)?;
+        if let Err(e) = self.fd.set_len(size) {
+            error!("failed to truncate data file: {}", e);
+            return Err(AppErrors::FailedWriteToDataFile);
+        }
+        write_buf.file_len = size;
+        Self::load_tail(&self.fd, &mut write_buf, size)
+    }
+}
+
+impl Drop for DirectIO {
+    // 关闭前将写缓冲区中的数据写入文件
+    fn drop(&mut self) {
+        let mut write_buf = self.write_buf.lock();
+        if let Err(e) = self.flush_blocks(&mut write_buf).and_then(|_| self.flush_tail(&mut write_buf)) {
+            error!("failed to flush direct io write buffer: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn test_direct_io_write_read() {
+        let path = PathBuf::from("/tmp/direct-io-a.data");
+        let dio_res = DirectIO::new(path.clone());
+        assert!(dio_res.is_ok());
+        let dio = dio_res.ok().unwrap();
+
+        // 写入不按块对齐的数据，跨越多个块和写缓冲区
+        let data: Vec<u8> = (0..WRITE_BUF_SIZE * 2 + 1234).map(|i| (i % 251) as u8).collect();
+        let mut written = 0;
+        for chunk in data.chunks(777) {
+            let res = dio.write(chunk);
+            assert!(res.is_ok());
+            written += res.unwrap();
+        }
+        assert_eq!(written, data.len());
+        assert_eq!(dio.size(), data.len() as u64);
+
+        // 读取跨越磁盘和写缓冲区的数据
+        let mut buf = vec![0u8; 10000];
+        let offset = WRITE_BUF_SIZE * 2 - 5000;
+        let read_res = dio.read(&mut buf, offset as u64);
+        assert_eq!(read_res.unwrap(), 6234);
+        assert_eq!(&buf[..6234], &data[offset..]);
+
+        // 读取未对齐位置的数据
+        let mut buf2 = vec![0u8; 100];
+        let read_res2 = dio.read(&mut buf2, 4000);
+        assert_eq!(read_res2.unwrap(), 100);
+        assert_eq!(&buf2[..], &data[4000..4100]);
+
+        let res = fs::remove_file(path.clone());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_direct_io_sync_and_reopen() {
+        let path = PathBuf::from("/tmp/direct-io-b.data");
+        let dio = DirectIO::new(path.clone()).unwrap();
+        assert!(dio.write(b"key-a").is_ok());
+        assert!(dio.write(b"value-a").is_ok());
+        assert!(dio.sync().is_ok());
+
+        // 文件中不会留下用于对齐的填充数据
+        assert_eq!(fs::metadata(path.clone()).unwrap().len(), 12);
+        std::mem::drop(dio);
+
+        // 重新打开后继续追加写
+        let dio2 = DirectIO::new(path.clone()).unwrap();
+        assert_eq!(dio2.size(), 12);
+        assert!(dio2.write(b"key-b").is_ok());
+        std::mem::drop(dio2);
+        assert_eq!(fs::read(path.clone()).unwrap(), b"key-avalue-akey-b");
+
+        let res = fs::remove_file(path.clone());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_direct_io_preallocate() {
+        let path = PathBuf::from("/tmp/direct-io-c.data");
+        let dio = DirectIO::new(path.clone()).unwrap();
+        assert!(dio.write(b"key-a").is_ok());
+        assert!(dio.preallocate(64 * 1024).is_ok());
+        assert!(dio.write(b"key-b").is_ok());
+        assert!(dio.sync().is_ok());
+        assert_eq!(fs::metadata(path.clone()).unwrap().len(), 64 * 1024);
+        std::mem::drop(dio);
+
+        // 重新打开时逻辑大小为文件长度，截断到数据真实的末尾之后继续追加写
+        let dio2 = DirectIO::new(path.clone()).unwrap();
+        assert_eq!(dio2.size(), 64 * 1024);
+        assert!(dio2.truncate(10).is_ok());
+        assert_eq!(dio2.size(), 10);
+        assert!(dio2.write(b"key-c").is_ok());
+        std::mem::drop(dio2);
+        assert_eq!(fs::read(path.clone()).unwrap(), b"key-akey-bkey-c");
+
+        let res = fs::remove_file(path.clone());
+        assert!(res.is_ok());
+    }
+}
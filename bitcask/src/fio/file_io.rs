@@ -1,9 +1,12 @@
 use std::{
     fs::{File, OpenOptions},
-    io::Write,
-    os::unix::prelude::FileExt,
+    io::{ErrorKind, IoSlice, Seek, SeekFrom, Write},
+    os::unix::prelude::{AsRawFd, FileExt},
     path::PathBuf,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 use parking_lot::RwLock;
 use log::error;
@@ -11,29 +14,39 @@ use super::io_manager::IOManager;
 use crate::errors::{AppErrors, AppResult};
 
 /// FileIO 标准系统文件 IO
+/// 以追加模式打开，预分配空间时不改变文件长度，写入总是从数据的末尾开始
 pub struct FileIO {
     fd: Arc<RwLock<File>>, // 系统文件描述符, Arc+RwLock 常见组合(原子读写锁)
+    size: AtomicU64,       // 文件的逻辑大小，即已经写入数据的末尾，不包含预分配的空间
 }
 
 impl FileIO {
     pub fn new(file_name: PathBuf) -> AppResult<Self> {
-        return match OpenOptions::new()
+        let mut file = match OpenOptions::new()
             .create(true)
             .read(true)
-            .write(true)
             .append(true)
             .open(file_name)
         {
-            Ok(file) => {
-                Ok(Self {
-                    fd: Arc::new(RwLock::new(file)),
-                })
-            }
+            Ok(file) => file,
             Err(e) => {
                 error!("failed to open data file: {}", e);
-                Err(AppErrors::FailedToOpenDataFile)
+                return Err(AppErrors::FailedToOpenDataFile);
             }
-        }
+        };
+
+        // 打开时以文件长度作为逻辑大小，崩溃时末尾可能有不完整的数据，恢复之后再通过 truncate 修正
+        let size = match file.seek(SeekFrom::End(0)) {
+            Ok(size) => size,
+            Err(e) => {
+                error!("failed to seek data file: {}", e);
+                return Err(AppErrors::FailedToOpenDataFile);
+            }
+        };
+        Ok(Self {
+            fd: Arc::new(RwLock::new(file)),
+            size: AtomicU64::new(size),
+        })
     }
 }
 
@@ -41,26 +54,57 @@ impl IOManager for FileIO {
     fn read(&self, buf: &mut [u8], offset: u64) -> AppResult<usize> {
         // RwLockReadGuard<RawRwLock, File>
         let read_guard = self.fd.read();
-        return match read_guard.read_at(buf, offset) {
+        match read_guard.read_at(buf, offset) {
             Ok(n) => Ok(n),
             Err(e) => {
                 error!("read from data file err: {}", e);
                 Err(AppErrors::FailedReadFromDataFile)
             }
-        };
+        }
     }
 
     fn write(&self, buf: &[u8]) -> AppResult<usize> {
         let mut write_guard = self.fd.write();
-        return match write_guard.write(buf) {
-            Ok(n) => Ok(n),
+        match write_guard.write(buf) {
+            Ok(n) => {
+                self.size.fetch_add(n as u64, Ordering::SeqCst);
+                Ok(n)
+            }
             Err(e) => {
                 error!("write to data file err: {}", e);
-                return Err(AppErrors::FailedWriteToDataFile);
+                Err(AppErrors::FailedWriteToDataFile)
             }
         }
     }
 
+    fn write_vectored(&self, bufs: &[&[u8]]) -> AppResult<usize> {
+        let mut slices: Vec<IoSlice> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+        let mut remaining: &mut [IoSlice] = &mut slices;
+        let mut n_bytes = 0;
+
+        let mut write_guard = self.fd.write();
+        // 通过 writev 一次写入，部分写入时继续写剩余的部分
+        while !remaining.is_empty() {
+            match write_guard.write_vectored(remaining) {
+                Ok(0) => {
+                    error!("write to data file err: write zero bytes");
+                    return Err(AppErrors::FailedWriteToDataFile);
+                }
+                Ok(n) => {
+                    n_bytes += n;
+                    self.size.fetch_add(n as u64, Ordering::SeqCst);
+                    IoSlice::advance_slices(&mut remaining, n);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("write to data file err: {}", e);
+                    return Err(AppErrors::FailedWriteToDataFile);
+                }
+            }
+        }
+        Ok(n_bytes)
+    }
+
     fn sync(&self) -> AppResult<()> {
         let read_guard = self.fd.read();
         if let Err(e) = read_guard.sync_all() {
@@ -71,15 +115,38 @@ impl IOManager for FileIO {
     }
 
     fn size(&self) -> u64 {
-        let read_guard = self.fd.read();
-        let metadata = read_guard.metadata().unwrap();
-        metadata.len()
+        self.size.load(Ordering::SeqCst)
+    }
+
+    // 通过 FALLOC_FL_KEEP_SIZE 预分配磁盘空间，文件长度不变，追加写仍然从数据的末尾开始
+    #[cfg(target_os = "linux")]
+    fn preallocate(&self, size: u64) -> AppResult<()> {
+        let write_guard = self.fd.write();
+        let res = unsafe {
+            libc::fallocate(write_guard.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, size as libc::off_t)
+        };
+        if res != 0 {
+            error!("failed to preallocate data file: {}", std::io::Error::last_os_error());
+            return Err(AppErrors::FailedWriteToDataFile);
+        }
+        Ok(())
+    }
+
+    fn truncate(&self, size: u64) -> AppResult<()> {
+        let write_guard = self.fd.write();
+        if let Err(e) = write_guard.set_len(size) {
+            error!("failed to truncate data file: {}", e);
+            return Err(AppErrors::FailedWriteToDataFile);
+        }
+        self.size.store(size, Ordering::SeqCst);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs;
+    use std::os::unix::fs::MetadataExt;
     use super::*;
 
     #[test]
@@ -130,6 +197,33 @@ mod tests {
         assert!(res3.is_ok());
     }
 
+    #[test]
+    fn test_file_io_write_vectored() {
+        let path = PathBuf::from("/tmp/e.data");
+        let fio_res = FileIO::new(path.clone());
+        assert!(fio_res.is_ok());
+        let fio = fio_res.ok().unwrap();
+
+        let res1 = fio.write_vectored(&["key-a".as_bytes(), "".as_bytes(), "value-a".as_bytes()]);
+        assert!(res1.is_ok());
+        assert_eq!(12, res1.ok().unwrap());
+
+        let bufs: Vec<Vec<u8>> = (0..2000).map(|i| format!("{:05}", i).into_bytes()).collect();
+        let slices: Vec<&[u8]> = bufs.iter().map(|b| b.as_slice()).collect();
+        let res2 = fio.write_vectored(&slices);
+        assert!(res2.is_ok());
+        assert_eq!(10000, res2.ok().unwrap());
+        assert_eq!(10012, fio.size());
+
+        let mut buf = [0u8; 5];
+        let read_res = fio.read(&mut buf, 12 + 1999 * 5);
+        assert!(read_res.is_ok());
+        assert_eq!(b"01999", &buf);
+
+        let res3 = fs::remove_file(path.clone());
+        assert!(res3.is_ok());
+    }
+
     #[test]
     fn test_file_io_sync() {
         let path = PathBuf::from("/tmp/c.data");
@@ -171,4 +265,41 @@ mod tests {
         let res3 = fs::remove_file(path.clone());
         assert!(res3.is_ok());
     }
+
+    #[test]
+    fn test_file_io_preallocate() {
+        let path = PathBuf::from("/tmp/f.data");
+        let fio = FileIO::new(path.clone()).unwrap();
+        assert!(fio.write("key-a".as_bytes()).is_ok());
+
+        // 预分配的空间不改变文件长度，写入从数据的末尾开始
+        let pre_res = fio.preallocate(4096);
+        assert!(pre_res.is_ok());
+        assert_eq!(fs::metadata(path.clone()).unwrap().len(), 5);
+        assert!(fs::metadata(path.clone()).unwrap().blocks() * 512 >= 4096);
+        assert_eq!(fio.size(), 5);
+        assert!(fio.write("key-b".as_bytes()).is_ok());
+        assert_eq!(fio.size(), 10);
+
+        let mut buf = [0u8; 10];
+        assert_eq!(10, fio.read(&mut buf, 0).unwrap());
+        assert_eq!(b"key-akey-b", &buf);
+
+        // 截断之后从截断的位置继续追加写
+        let trunc_res = fio.truncate(5);
+        assert!(trunc_res.is_ok());
+        assert_eq!(fs::metadata(path.clone()).unwrap().len(), 5);
+        assert!(fio.write("key-b".as_bytes()).is_ok());
+        assert_eq!(fio.size(), 10);
+        std::mem::drop(fio);
+
+        // 重新打开之后继续追加写
+        let fio2 = FileIO::new(path.clone()).unwrap();
+        assert_eq!(fio2.size(), 10);
+        assert!(fio2.write("key-c".as_bytes()).is_ok());
+        assert_eq!(fs::read(path.clone()).unwrap(), b"key-akey-bkey-c");
+
+        let res = fs::remove_file(path.clone());
+        assert!(res.is_ok());
+    }
 }
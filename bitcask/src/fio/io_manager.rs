@@ -1,7 +1,12 @@
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
+use bytes::Bytes;
 use crate::errors::AppResult;
+use crate::fio::direct_io::DirectIO;
 use crate::fio::file_io::FileIO;
 use crate::fio::mmap::MMapIO;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::fio::uring_io::UringIO;
 use crate::options::io_type::IOType;
 
 /// 抽象 IO 管理接口，可以接入不同的 IO 类型，目前支持标准文件 IO
@@ -10,17 +15,48 @@ pub trait IOManager: Sync + Send {
     fn read(&self, buf: &mut [u8], offset: u64) -> AppResult<usize>;
     /// 写入字节数组到文件中
     fn write(&self, buf: &[u8]) -> AppResult<usize>;
+    /// 一次写入多个字节数组到文件中，默认依次调用 write
+    fn write_vectored(&self, bufs: &[&[u8]]) -> AppResult<usize> {
+        let mut n_bytes = 0;
+        for buf in bufs {
+            n_bytes += self.write(buf)?;
+        }
+        Ok(n_bytes)
+    }
     /// 持久化数据
     fn sync(&self) -> AppResult<()>;
     /// 获取文件的大小
     fn size(&self) -> u64;
+    /// 预分配文件的磁盘空间，不改变文件的逻辑大小
+    fn preallocate(&self, _size: u64) -> AppResult<()> {
+        Ok(())
+    }
+    /// 将文件截断到给定的大小，去掉预分配但没有写入数据的空间
+    fn truncate(&self, _size: u64) -> AppResult<()> {
+        Ok(())
+    }
+    /// 零拷贝读取，返回直接引用文件内容的 Bytes，不支持时返回 None
+    fn read_view(&self, _offset: u64, _len: usize) -> Option<Bytes> {
+        None
+    }
+    /// 获取可以提交给 io_uring 批量读取的文件描述符，不支持时返回 None
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
 }
 
-/// 根据文件名称初始化 IOManager
+/// 根据文件名称初始化 IOManager，打开或者映射文件失败时返回错误
 /// Box<dyn Trait> 动态分发
-pub fn new_io_manager(file_name: PathBuf, io_type: IOType) -> Box<dyn IOManager> {
-    match io_type {
-        IOType::StandardFIO => Box::new(FileIO::new(file_name).unwrap()),
-        IOType::MemoryMap => Box::new(MMapIO::new(file_name).unwrap()),
-    }
+pub fn new_io_manager(file_name: PathBuf, io_type: IOType) -> AppResult<Box<dyn IOManager>> {
+    let io_manager: Box<dyn IOManager> = match io_type {
+        IOType::StandardFIO => Box::new(FileIO::new(file_name)?),
+        IOType::MemoryMap => Box::new(MMapIO::new(file_name)?),
+        IOType::DirectIO => Box::new(DirectIO::new(file_name)?),
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        IOType::IoUring => Box::new(UringIO::new(file_name)?),
+        // 未开启 io-uring feature 时退化为标准文件 IO
+        #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+        IOType::IoUring => Box::new(FileIO::new(file_name)?),
+    };
+    Ok(io_manager)
 }
\ No newline at end of file
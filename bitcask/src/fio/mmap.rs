@@ -1,30 +1,41 @@
 use std::{fs::OpenOptions, path::PathBuf, sync::Arc};
+use bytes::Bytes;
 use log::error;
 use memmap2::Mmap;
-use parking_lot::Mutex;
 use super::io_manager::IOManager;
 use crate::errors::{AppErrors, AppResult};
 
-/// 线程安全(原子锁) -> memmap2::Mmap
+/// 只读的内存映射，通过 Arc 共享给零拷贝读取返回的 Bytes
 pub struct MMapIO {
-    map: Arc<Mutex<Mmap>>,
+    map: Arc<Mmap>,
+}
+
+// 零拷贝读取返回的 Bytes 持有映射的引用计数，MMapIO 被释放之后映射仍然有效
+struct MmapOwner(Arc<Mmap>);
+
+impl AsRef<[u8]> for MmapOwner {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 impl MMapIO {
     pub fn new(file_name: PathBuf) -> AppResult<Self> {
         // 尝试打开该路径文件
-        return match OpenOptions::new()
+        match OpenOptions::new()
             .create(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(file_name)
         {
             Ok(file) => {
                 // TODO 为什么要用unsafe ?
-                let map: Mmap = unsafe { Mmap::map(&file).expect("failed to map the file") };
-                Ok(MMapIO {
-                    map: Arc::new(Mutex::new(map)),
-                })
+                let map: Mmap = unsafe { Mmap::map(&file) }.map_err(|e| {
+                    error!("failed to map data file: {}", e);
+                    AppErrors::FailedToOpenDataFile
+                })?;
+                Ok(MMapIO { map: Arc::new(map) })
             }
             Err(e) => {
                 error!("failed to open data file: {}", e);
@@ -36,15 +47,16 @@ impl MMapIO {
 
 impl IOManager for MMapIO {
     fn read(&self, buf: &mut [u8], offset: u64) -> AppResult<usize> {
-        let map_arr = self.map.lock();
-        let end: u64 = offset + buf.len() as u64;
-        if end > map_arr.len() as u64 {
+        let map_arr = &self.map;
+        if offset >= map_arr.len() as u64 {
             return Err(AppErrors::ReadDataFileEOF);
         }
+        // 读到文件末尾时只返回剩余的数据，与标准文件 IO 一致
+        let end: u64 = (offset + buf.len() as u64).min(map_arr.len() as u64);
         let val: &[u8] = &map_arr[offset as usize..end as usize];
 
         // 显示/隐式 展开解引用
-        (&mut *buf).copy_from_slice(val);
+        buf[..val.len()].copy_from_slice(val);
 
         Ok(val.len())
     }
@@ -58,8 +70,27 @@ impl IOManager for MMapIO {
     }
 
     fn size(&self) -> u64 {
-        let map_arr = self.map.lock();
-        map_arr.len() as u64
+        self.map.len() as u64
+    }
+
+    // 只读映射的文件不能预分配和截断，静默忽略会让调用方误以为操作成功
+    fn preallocate(&self, _size: u64) -> AppResult<()> {
+        error!("failed to preallocate data file: mmap io is read-only");
+        Err(AppErrors::FailedWriteToDataFile)
+    }
+
+    fn truncate(&self, _size: u64) -> AppResult<()> {
+        error!("failed to truncate data file: mmap io is read-only");
+        Err(AppErrors::FailedWriteToDataFile)
+    }
+
+    fn read_view(&self, offset: u64, len: usize) -> Option<Bytes> {
+        let end = offset.checked_add(len as u64)?;
+        if end > self.map.len() as u64 {
+            return None;
+        }
+        let view = Bytes::from_owner(MmapOwner(self.map.clone()));
+        Some(view.slice(offset as usize..end as usize))
     }
 }
 
@@ -97,7 +128,31 @@ mod tests {
         let read_res2 = mmap_io2.read(&mut buf2, 2);
         assert!(read_res2.is_ok());
 
+        // 读到文件末尾
+        let mut buf3 = [0u8; 10];
+        let read_res3 = mmap_io2.read(&mut buf3, 4);
+        assert_eq!(read_res3.ok().unwrap(), 2);
+        assert_eq!(&buf3[..2], b"cc");
+
+        let remove_res = fs::remove_file(path.clone());
+        assert!(remove_res.is_ok());
+    }
+
+    #[test]
+    fn test_mmap_read_view() {
+        let path: PathBuf = PathBuf::from("/tmp/mmap-test-view.data");
+        let fio: FileIO = FileIO::new(path.clone()).unwrap();
+        fio.write(b"key-avalue-a").unwrap();
+
+        let mmap_io = MMapIO::new(path.clone()).unwrap();
+        let view = mmap_io.read_view(5, 7);
+        assert_eq!(view.clone().unwrap(), Bytes::from("value-a"));
+        assert!(mmap_io.read_view(5, 8).is_none());
+
+        // 释放映射并删除文件之后，已经返回的 Bytes 仍然有效
+        std::mem::drop(mmap_io);
         let remove_res = fs::remove_file(path.clone());
         assert!(remove_res.is_ok());
+        assert_eq!(view.unwrap(), Bytes::from("value-a"));
     }
 }
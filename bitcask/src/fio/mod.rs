@@ -1,3 +1,6 @@
 pub mod mmap;
 pub mod file_io;
-pub mod io_manager;
\ No newline at end of file
+pub mod io_manager;
+pub mod direct_io;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring_io;
@@ -0,0 +1,422 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    os::unix::io::{AsRawFd, RawFd},
+    path::PathBuf,
+    sync::OnceLock,
+    time::Duration,
+};
+use io_uring::{opcode, types, IoUring};
+use log::error;
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use super::file_io::FileIO;
+use super::io_manager::IOManager;
+use crate::errors::{AppErrors, AppResult};
+
+// io_uring 提交队列的长度，一次批量提交的读请求超过该值时分多轮提交
+const RING_ENTRIES: u32 = 256;
+// io_uring_enter 等待完成事件的标识
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+// 进程内共享的 io_uring 读实例，内核不支持 io_uring 时为 None
+static URING_READER: OnceLock<Option<UringReader>> = OnceLock::new();
+
+/// 一次通过 io_uring 发起的读请求
+pub struct UringRead<'a> {
+    pub fd: RawFd,         // 读取的文件描述符
+    pub offset: u64,       // 读取的文件偏移
+    pub buf: &'a mut [u8], // 读取数据存放的缓冲区
+}
+
+// 排队等待提交的读请求，缓冲区由发起请求的线程持有，并在请求完成之前一直阻塞
+struct PendingRead {
+    ticket: u64,
+    fd: RawFd,
+    offset: u64,
+    ptr: *mut u8,
+    len: u32,
+}
+
+unsafe impl Send for PendingRead {}
+
+struct BatchState {
+    queue: Vec<PendingRead>,    // 尚未提交的读请求
+    results: HashMap<u64, i32>, // 已经完成的读请求结果，负数为 errno
+    submitting: bool,           // 是否已经有 leader 在提交读请求
+    next_ticket: u64,           // 下一个读请求的编号
+}
+
+struct RingState {
+    ring: IoUring,
+    // io_uring_enter 返回了无法恢复的错误，之后不再使用该 io_uring，读请求退化为 pread
+    // 提交队列中可能残留没有被内核取走的请求，因此不能再进入该 io_uring，也不能重建它
+    broken: bool,
+}
+
+/// 基于 io_uring 的批量读
+/// 并发的读请求先进入队列，没有正在提交的请求时当前线程成为 leader，
+/// 将队列中所有线程的读请求通过一次 io_uring_enter 提交，减少系统调用的次数
+pub struct UringReader {
+    ring: Mutex<RingState>,
+    state: Mutex<BatchState>,
+    cond: Condvar,
+}
+
+/// leader 提交期间持有，结束时（包括 panic 时）保存结果并清除提交标识，唤醒等待的线程，
+/// 没有结果的读请求返回 EIO，避免等待的线程永远阻塞
+struct SubmitGuard<'a> {
+    reader: &'a UringReader,
+    tickets: Vec<u64>,
+    results: Vec<(u64, i32)>,
+}
+
+impl Drop for SubmitGuard<'_> {
+    fn drop(&mut self) {
+        let done: HashSet<u64> = self.results.iter().map(|(t, _)| *t).collect();
+        let mut state = self.reader.state.lock();
+        for ticket in self.tickets.iter().filter(|t| !done.contains(t)) {
+            state.results.insert(*ticket, -libc::EIO);
+        }
+        state.results.extend(self.results.drain(..));
+        state.submitting = false;
+        self.reader.cond.notify_all();
+    }
+}
+
+impl UringReader {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            ring: Mutex::new(RingState {
+                ring: IoUring::new(RING_ENTRIES)?,
+                broken: false,
+            }),
+            state: Mutex::new(BatchState {
+                queue: Vec::new(),
+                results: HashMap::new(),
+                submitting: false,
+                next_ticket: 0,
+            }),
+            cond: Condvar::new(),
+        })
+    }
+
+    /// 获取进程内共享的 io_uring 读实例，内核不支持 io_uring 时返回 None
+    pub fn global() -> Option<&'static UringReader> {
+        URING_READER
+            .get_or_init(|| match UringReader::new() {
+                Ok(reader) => Some(reader),
+                Err(e) => {
+                    error!("failed to setup io_uring, fallback to pread: {}", e);
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    /// 批量读取，返回每个请求实际读取的字节数，读到文件末尾时小于缓冲区长度
+    pub fn read_batch(&self, reqs: &mut [UringRead]) -> AppResult<Vec<usize>> {
+        if reqs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut state = self.state.lock();
+        let first = state.next_ticket;
+        state.next_ticket += reqs.len() as u64;
+        for (i, req) in reqs.iter_mut().enumerate() {
+            state.queue.push(PendingRead {
+                ticket: first + i as u64,
+                fd: req.fd,
+                offset: req.offset,
+                ptr: req.buf.as_mut_ptr(),
+                len: req.buf.len() as u32,
+            });
+        }
+
+        let tickets = first..first + reqs.len() as u64;
+        loop {
+            if tickets.clone().all(|t| state.results.contains_key(&t)) {
+                break;
+            }
+
+            if state.submitting {
+                // 已经有 leader，等待其提交完成
+                self.cond.wait(&mut state);
+                continue;
+            }
+
+            // 成为 leader，提交队列中所有线程的读请求
+            state.submitting = true;
+            let batch = std::mem::take(&mut state.queue);
+            MutexGuard::unlocked(&mut state, || {
+                let mut guard = SubmitGuard {
+                    reader: self,
+                    tickets: batch.iter().map(|req| req.ticket).collect(),
+                    results: Vec::with_capacity(batch.len()),
+                };
+                self.submit(&batch, &mut guard.results);
+            });
+        }
+
+        let mut n_bytes = Vec::with_capacity(reqs.len());
+        let mut failed = None;
+        for ticket in tickets {
+            let res = state.results.remove(&ticket).unwrap();
+            if res < 0 {
+                failed = Some(res);
+            }
+            n_bytes.push(res.max(0) as usize);
+        }
+        if let Some(res) = failed {
+            error!("read from data file err: {}", std::io::Error::from_raw_os_error(-res));
+            return Err(AppErrors::FailedReadFromDataFile);
+        }
+        Ok(n_bytes)
+    }
+
+    // 将一批读请求提交到 io_uring 并等待全部完成，每个请求的编号和结果写入 results
+    // 内核在请求完成之前仍然可能写入调用方的缓冲区，返回之前必须收割所有被内核取走的请求
+    fn submit(&self, batch: &[PendingRead], results: &mut Vec<(u64, i32)>) {
+        let mut ring = self.ring.lock();
+        for (i, chunk) in batch.chunks(RING_ENTRIES as usize).enumerate() {
+            if ring.broken {
+                let rest = &batch[i * RING_ENTRIES as usize..];
+                results.extend(rest.iter().map(|req| (req.ticket, pread(req))));
+                return;
+            }
+
+            // 每一轮提交之前队列中的请求都已经完成，提交的请求数不超过队列长度，队列不会满
+            // 如果仍然放不下，已经放入队列的请求不能再提交，同样退化为 pread
+            let pushed = chunk.iter().all(|req| {
+                let entry = opcode::Read::new(types::Fd(req.fd), req.ptr, req.len)
+                    .offset(req.offset)
+                    .build()
+                    .user_data(req.ticket);
+                unsafe { ring.ring.submission().push(&entry) }.is_ok()
+            });
+            if !pushed {
+                error!("io_uring submission queue is full, fallback to pread");
+                ring.broken = true;
+                results.extend(chunk.iter().map(|req| (req.ticket, pread(req))));
+                continue;
+            }
+
+            let mut completed = 0;
+            while completed < chunk.len() {
+                if let Err(e) = ring.ring.submit_and_wait(chunk.len() - completed) {
+                    match e.raw_os_error() {
+                        // 被信号中断，或者完成队列已满、内核资源暂时不足，收割已经完成的请求之后重试
+                        Some(libc::EINTR | libc::EBUSY | libc::EAGAIN) => {}
+                        _ => {
+                            error!("failed to submit io_uring read, fallback to pread: {}", e);
+                            ring.broken = true;
+                            // 被内核取走的请求按提交的顺序排在前面，没有被取走的请求留在提交队列的末尾
+                            let unsubmitted = ring.ring.submission().len();
+                            let in_flight = chunk.len() - unsubmitted - completed;
+                            Self::wait_completions(&mut ring.ring, in_flight, results);
+                            let rest = &chunk[chunk.len() - unsubmitted..];
+                            results.extend(rest.iter().map(|req| (req.ticket, pread(req))));
+                            break;
+                        }
+                    }
+                }
+                for cqe in ring.ring.completion() {
+                    results.push((cqe.user_data(), cqe.result()));
+                    completed += 1;
+                }
+            }
+        }
+    }
+
+    // io_uring 不可用之后，等待已经被内核取走但尚未完成的 in_flight 个请求全部完成，
+    // 只等待完成而不再提交，提交队列中残留的请求永远不会被执行
+    fn wait_completions(ring: &mut IoUring, in_flight: usize, results: &mut Vec<(u64, i32)>) {
+        let mut reaped = 0;
+        loop {
+            for cqe in ring.completion() {
+                results.push((cqe.user_data(), cqe.result()));
+                reaped += 1;
+            }
+            if reaped >= in_flight {
+                return;
+            }
+            let res = unsafe {
+                ring.submitter()
+                    .enter::<libc::sigset_t>(0, (in_flight - reaped) as u32, IORING_ENTER_GETEVENTS, None)
+            };
+            // 缓冲区在请求完成之前不能释放，等待失败时稍后重试，不能直接返回
+            if let Err(e) = res
+                && e.raw_os_error() != Some(libc::EINTR)
+            {
+                error!("failed to wait io_uring completions: {}", e);
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+// 通过 pread 读取，返回读取的字节数，失败时返回负数的 errno
+fn pread(req: &PendingRead) -> i32 {
+    let res = unsafe { libc::pread(req.fd, req.ptr as *mut libc::c_void, req.len as usize, req.offset as libc::off_t) };
+    match res < 0 {
+        true => -std::io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO),
+        false => res as i32,
+    }
+}
+
+/// UringIO 写入和持久化与标准文件 IO 相同，读取通过 io_uring 批量提交
+pub struct UringIO {
+    file_io: FileIO,                      // 标准文件 IO，负责写入和持久化
+    read_fd: File,                        // 提交给 io_uring 读取的文件描述符
+    reader: Option<&'static UringReader>, // io_uring 读实例，不可用时退化为 pread
+}
+
+impl UringIO {
+    pub fn new(file_name: PathBuf) -> AppResult<Self> {
+        let file_io = FileIO::new(file_name.clone())?;
+        let read_fd = match OpenOptions::new().read(true).open(file_name) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("failed to open data file: {}", e);
+                return Err(AppErrors::FailedToOpenDataFile);
+            }
+        };
+        Ok(Self {
+            file_io,
+            read_fd,
+            reader: UringReader::global(),
+        })
+    }
+}
+
+impl IOManager for UringIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> AppResult<usize> {
+        let reader = match self.reader {
+            Some(reader) => reader,
+            None => return self.file_io.read(buf, offset),
+        };
+        let mut reqs = [UringRead {
+            fd: self.read_fd.as_raw_fd(),
+            offset,
+            buf,
+        }];
+        let n_bytes = reader.read_batch(&mut reqs)?;
+        Ok(n_bytes[0])
+    }
+
+    fn write(&self, buf: &[u8]) -> AppResult<usize> {
+        self.file_io.write(buf)
+    }
+
+    fn write_vectored(&self, bufs: &[&[u8]]) -> AppResult<usize> {
+        self.file_io.write_vectored(bufs)
+    }
+
+    fn sync(&self) -> AppResult<()> {
+        self.file_io.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.file_io.size()
+    }
+
+    fn preallocate(&self, size: u64) -> AppResult<()> {
+        self.file_io.preallocate(size)
+    }
+
+    fn truncate(&self, size: u64) -> AppResult<()> {
+        self.file_io.truncate(size)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.reader.map(|_| self.read_fd.as_raw_fd())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc, thread};
+    use super::*;
+
+    #[test]
+    fn test_uring_io_read() {
+        let path = PathBuf::from("/tmp/uring-io-a.data");
+        let uio_res = UringIO::new(path.clone());
+        assert!(uio_res.is_ok());
+        let uio = Arc::new(uio_res.ok().unwrap());
+
+        let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        assert!(uio.write(&data).is_ok());
+
+        // 并发的读请求合并提交
+        let mut handles = vec![];
+        for t in 0..8usize {
+            let uio = uio.clone();
+            let data = data.clone();
+            handles.push(thread::spawn(move || {
+                for i in 0..100usize {
+                    let offset = (t * 7919 + i * 613) % (data.len() - 100);
+                    let mut buf = vec![0u8; 100];
+                    let read_res = uio.read(&mut buf, offset as u64);
+                    assert_eq!(read_res.unwrap(), 100);
+                    assert_eq!(&buf[..], &data[offset..offset + 100]);
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 读到文件末尾
+        let mut buf = vec![0u8; 100];
+        let read_res = uio.read(&mut buf, data.len() as u64 - 10);
+        assert_eq!(read_res.unwrap(), 10);
+
+        let res = fs::remove_file(path.clone());
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_uring_reader_read_batch() {
+        let path1 = PathBuf::from("/tmp/uring-io-b.data");
+        let path2 = PathBuf::from("/tmp/uring-io-c.data");
+        let uio1 = UringIO::new(path1.clone()).unwrap();
+        let uio2 = UringIO::new(path2.clone()).unwrap();
+        assert!(uio1.write(b"key-a-value-a").is_ok());
+        assert!(uio2.write(b"key-b-value-b").is_ok());
+
+        // 一次提交读取多个文件中的数据
+        let reader = UringReader::global().unwrap();
+        let mut buf1 = vec![0u8; 7];
+        let mut buf2 = vec![0u8; 7];
+        let mut reqs = vec![
+            UringRead { fd: uio1.raw_fd().unwrap(), offset: 6, buf: &mut buf1 },
+            UringRead { fd: uio2.raw_fd().unwrap(), offset: 6, buf: &mut buf2 },
+        ];
+        let read_res = reader.read_batch(&mut reqs);
+        assert_eq!(read_res.unwrap(), vec![7, 7]);
+        assert_eq!(&buf1[..], b"value-a");
+        assert_eq!(&buf2[..], b"value-b");
+
+        assert!(fs::remove_file(path1).is_ok());
+        assert!(fs::remove_file(path2).is_ok());
+    }
+
+    #[test]
+    fn test_uring_reader_broken_fallback() {
+        let path = PathBuf::from("/tmp/uring-io-d.data");
+        let uio = UringIO::new(path.clone()).unwrap();
+        assert!(uio.write(b"key-a-value-a").is_ok());
+
+        // io_uring 不可用之后退化为 pread
+        let reader = UringReader::new().unwrap();
+        reader.ring.lock().broken = true;
+        let mut buf = vec![0u8; 7];
+        let mut reqs = vec![UringRead { fd: uio.raw_fd().unwrap(), offset: 6, buf: &mut buf }];
+        let read_res = reader.read_batch(&mut reqs);
+        assert_eq!(read_res.unwrap(), vec![7]);
+        assert_eq!(&buf[..], b"value-a");
+        assert!(!reader.state.lock().submitting);
+
+        assert!(fs::remove_file(path).is_ok());
+    }
+}
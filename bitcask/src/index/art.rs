@@ -0,0 +1,785 @@
+use std::cmp;
+use std::mem::size_of;
+use std::sync::Arc;
+use bytes::Bytes;
+use parking_lot::RwLock;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::errors::AppResult;
+use crate::index::btree_iterator::BTreeIterator;
+use crate::options::iterator_options::IteratorOptions;
+use super::index_iterator::IndexIterator;
+use super::indexer::Indexer;
+
+// Node16 最多容纳的子节点数量，超过之后扩展为 Node48
+const NODE16_MAX: usize = 16;
+// Node48 最多容纳的子节点数量，超过之后扩展为 Node256
+const NODE48_MAX: usize = 48;
+// 删除之后子节点数量低于该值时收缩，留出余量避免在边界上反复扩展和收缩
+const NODE48_SHRINK: usize = 12;
+const NODE256_SHRINK: usize = 36;
+
+/// 自适应基数树（Adaptive Radix Tree）索引
+/// 共同前缀只在内部节点中存储一次，叶子节点只存储 key 剩余的部分，适合有大量公共前缀的 key
+pub struct Art {
+    tree: Arc<RwLock<ArtTree>>,
+}
+
+struct ArtTree {
+    root: Option<Node>,
+    len: usize,  // key 的数量
+    size: usize, // 所有节点占用的堆内存大小，在插入和删除时增量维护
+}
+
+enum Node {
+    Leaf {
+        suffix: Box<[u8]>, // key 除去路径上前缀之后剩余的部分
+        pos: LogRecordPos,
+    },
+    Inner(Box<Inner>),
+}
+
+struct Inner {
+    prefix: Box<[u8]>,           // 压缩的公共前缀
+    value: Option<LogRecordPos>, // key 正好在当前节点结束时的位置信息
+    children: Children,
+}
+
+/// 子节点按照数量选择不同的存储结构
+enum Children {
+    // 有序数组，容量从 4 开始增长到 16
+    Node16 { keys: Vec<u8>, nodes: Vec<Node> },
+    // 256 个字节的下标数组，指向子节点所在的位置，0 表示不存在
+    Node48 { index: Box<[u8; 256]>, nodes: Vec<Node> },
+    // 按照字节直接寻址
+    Node256 { nodes: Box<[Option<Node>]>, len: usize },
+}
+
+impl Default for Art {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Art {
+    pub fn new() -> Self {
+        Self {
+            tree: Arc::new(RwLock::new(ArtTree { root: None, len: 0, size: 0 })),
+        }
+    }
+}
+
+impl Indexer for Art {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>> {
+        let mut write_guard = self.tree.write();
+        let mut delta = 0;
+        let old = match write_guard.root.as_mut() {
+            Some(root) => root.insert(&key, pos, &mut delta),
+            None => {
+                write_guard.root = Some(Node::leaf(&key, pos));
+                delta = key.len() as isize;
+                None
+            }
+        };
+        if old.is_none() {
+            write_guard.len += 1;
+        }
+        write_guard.size = write_guard.size.saturating_add_signed(delta);
+        Ok(old)
+    }
+
+    fn get(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let read_guard = self.tree.read();
+        Ok(read_guard.root.as_ref().and_then(|root| root.get(&key)))
+    }
+
+    fn delete(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let mut write_guard = self.tree.write();
+        let mut delta = 0;
+        let (old, empty) = match write_guard.root.as_mut() {
+            Some(root) => root.remove(&key, &mut delta),
+            None => return Ok(None),
+        };
+        if empty {
+            write_guard.root = None;
+        }
+        if old.is_some() {
+            write_guard.len -= 1;
+        }
+        write_guard.size = write_guard.size.saturating_add_signed(delta);
+        Ok(old)
+    }
+
+    fn list_keys(&self) -> AppResult<Vec<Bytes>> {
+        let read_guard = self.tree.read();
+        let mut keys = Vec::with_capacity(read_guard.len);
+        if let Some(root) = read_guard.root.as_ref() {
+            root.walk(&mut Vec::new(), &[], &mut |key, _| {
+                keys.push(Bytes::copy_from_slice(key));
+                true
+            });
+        }
+        Ok(keys)
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        let read_guard = self.tree.read();
+        let mut items = Vec::new();
+        // 从迭代范围的起点开始遍历，跳过比起点小的子树，越过上界或者前缀之后停止，遍历的结果是有序的
+        if let Some(root) = read_guard.root.as_ref() {
+            root.walk(&mut Vec::new(), &options.start_key(), &mut |key, pos| {
+                if options.is_past(key) {
+                    return false;
+                }
+                if options.contains(key) {
+                    items.push((key.to_vec(), *pos));
+                }
+                true
+            });
+        }
+        Box::new(BTreeIterator::new(items, options))
+    }
+
+    fn memory_usage(&self) -> usize {
+        size_of::<ArtTree>() + self.tree.read().size
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+impl Node {
+    fn leaf(suffix: &[u8], pos: LogRecordPos) -> Self {
+        Node::Leaf {
+            suffix: suffix.into(),
+            pos,
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<LogRecordPos> {
+        let mut node = self;
+        let mut key = key;
+        loop {
+            match node {
+                Node::Leaf { suffix, pos } => {
+                    return match **suffix == *key {
+                        true => Some(*pos),
+                        false => None,
+                    };
+                }
+                Node::Inner(inner) => {
+                    key = key.strip_prefix(&inner.prefix[..])?;
+                    let (byte, rest) = match key.split_first() {
+                        Some(v) => v,
+                        None => return inner.value,
+                    };
+                    node = inner.children.get(*byte)?;
+                    key = rest;
+                }
+            }
+        }
+    }
+
+    // 插入 key 剩余的部分，返回旧的位置信息，delta 累加占用内存的变化量
+    fn insert(&mut self, key: &[u8], pos: LogRecordPos, delta: &mut isize) -> Option<LogRecordPos> {
+        match self {
+            Node::Leaf { suffix, pos: old } => {
+                if **suffix == *key {
+                    return Some(std::mem::replace(old, pos));
+                }
+                // 叶子节点分裂为内部节点，公共部分作为前缀
+                let before = suffix.len();
+                let p = common_prefix(suffix, key);
+                let mut inner = Inner::new(&key[..p]);
+                inner.add(&suffix[p..], *old);
+                inner.add(&key[p..], pos);
+                *self = Node::Inner(Box::new(inner));
+                *delta += self.shallow_size() as isize - before as isize;
+                None
+            }
+            Node::Inner(inner) => {
+                let p = common_prefix(&inner.prefix, key);
+                if p == inner.prefix.len() {
+                    let (byte, rest) = match key[p..].split_first() {
+                        Some(v) => v,
+                        None => return inner.value.replace(pos),
+                    };
+                    if let Some(child) = inner.children.get_mut(*byte) {
+                        return child.insert(rest, pos, delta);
+                    }
+                    let before = inner.children.own_size();
+                    inner.children.insert(*byte, Node::leaf(rest, pos));
+                    *delta += (inner.children.own_size() + rest.len()) as isize - before as isize;
+                    return None;
+                }
+
+                // 前缀不匹配，在分叉的位置拆分前缀
+                let before = inner.own_size();
+                let byte = inner.prefix[p];
+                inner.prefix = inner.prefix[p + 1..].into();
+                let mut parent = Inner::new(&key[..p]);
+                parent.add(&key[p..], pos);
+                let old = std::mem::replace(self, Node::Inner(Box::new(parent)));
+                if let Node::Inner(parent) = self {
+                    parent.children.insert(byte, old);
+                }
+                *delta += self.shallow_size() as isize - before as isize;
+                None
+            }
+        }
+    }
+
+    // 删除 key 剩余的部分，返回旧的位置信息，以及删除之后当前节点是否为空
+    // 为空的节点由调用方移除，其占用的内存已经从 delta 中减去
+    fn remove(&mut self, key: &[u8], delta: &mut isize) -> (Option<LogRecordPos>, bool) {
+        let inner = match self {
+            Node::Leaf { suffix, pos } => {
+                if **suffix != *key {
+                    return (None, false);
+                }
+                *delta -= suffix.len() as isize;
+                return (Some(*pos), true);
+            }
+            Node::Inner(inner) => inner,
+        };
+        let before = inner.own_size();
+
+        let key = match key.strip_prefix(&inner.prefix[..]) {
+            Some(key) => key,
+            None => return (None, false),
+        };
+        let old = match key.split_first() {
+            None => inner.value.take(),
+            Some((byte, rest)) => {
+                let (old, empty) = match inner.children.get_mut(*byte) {
+                    Some(child) => child.remove(rest, delta),
+                    None => return (None, false),
+                };
+                if empty {
+                    inner.children.remove(*byte);
+                }
+                old
+            }
+        };
+        if old.is_none() {
+            return (None, false);
+        }
+        *delta += inner.own_size() as isize - before as isize;
+        (old, self.compact(delta))
+    }
+
+    // 删除之后合并只剩下一个分支的内部节点，返回当前节点是否为空
+    fn compact(&mut self, delta: &mut isize) -> bool {
+        let inner = match self {
+            Node::Inner(inner) => inner,
+            Node::Leaf { .. } => return false,
+        };
+        let before = inner.own_size();
+        match (inner.value, inner.children.len()) {
+            (None, 0) => {
+                *delta -= before as isize;
+                true
+            }
+            (Some(pos), 0) => {
+                *self = Node::Leaf {
+                    suffix: std::mem::take(&mut inner.prefix),
+                    pos,
+                };
+                *delta += self.own_size() as isize - before as isize;
+                false
+            }
+            (None, 1) => {
+                let byte = inner.children.first_byte().unwrap();
+                let child = inner.children.remove(byte).unwrap();
+                let child_size = child.own_size();
+                let mut prefix = Vec::with_capacity(inner.prefix.len() + 1);
+                prefix.extend_from_slice(&inner.prefix);
+                prefix.push(byte);
+                *self = match child {
+                    Node::Leaf { suffix, pos } => {
+                        prefix.extend_from_slice(&suffix);
+                        Node::Leaf {
+                            suffix: prefix.into(),
+                            pos,
+                        }
+                    }
+                    Node::Inner(mut child) => {
+                        prefix.extend_from_slice(&child.prefix);
+                        child.prefix = prefix.into();
+                        Node::Inner(child)
+                    }
+                };
+                // 内部节点和唯一的子节点合并为一个节点
+                *delta += self.own_size() as isize - (before + child_size) as isize;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    // 按照 key 的顺序遍历子树中不小于 start 的 key，path 为到达当前节点的路径
+    // f 返回 false 时停止遍历，返回值表示是否需要继续遍历
+    fn walk<F: FnMut(&[u8], &LogRecordPos) -> bool>(&self, path: &mut Vec<u8>, start: &[u8], f: &mut F) -> bool {
+        let len = path.len();
+        let go_on = match self {
+            Node::Leaf { suffix, pos } => {
+                path.extend_from_slice(suffix);
+                path.as_slice() < start || f(path, pos)
+            }
+            Node::Inner(inner) => {
+                path.extend_from_slice(&inner.prefix);
+                inner.walk(path, start, f)
+            }
+        };
+        path.truncate(len);
+        go_on
+    }
+
+    // 节点自身占用的堆内存大小，不包括子节点
+    fn own_size(&self) -> usize {
+        match self {
+            Node::Leaf { suffix, .. } => suffix.len(),
+            Node::Inner(inner) => inner.own_size(),
+        }
+    }
+
+    // 节点和直接子节点占用的堆内存大小，用于计算分裂之后新增的内存
+    fn shallow_size(&self) -> usize {
+        let mut size = self.own_size();
+        if let Node::Inner(inner) = self {
+            size += inner.children.iter().map(|(_, child)| child.own_size()).sum::<usize>();
+        }
+        size
+    }
+
+    // 整个子树占用的堆内存大小，用于校验增量维护的结果
+    #[cfg(test)]
+    fn heap_size(&self) -> usize {
+        let mut size = self.own_size();
+        if let Node::Inner(inner) = self {
+            size += inner.children.iter().map(|(_, child)| child.heap_size()).sum::<usize>();
+        }
+        size
+    }
+}
+
+impl Inner {
+    // 遍历当前节点的值和子节点中不小于 start 的 key，path 为当前节点对应的 key
+    fn walk<F: FnMut(&[u8], &LogRecordPos) -> bool>(&self, path: &mut Vec<u8>, start: &[u8], f: &mut F) -> bool {
+        // 子树中的 key 都以 path 开头，比较相同长度的部分确定整个子树和 start 的大小关系
+        let n = path.len().min(start.len());
+        let start = match path[..n].cmp(&start[..n]) {
+            // 整个子树都比 start 小
+            cmp::Ordering::Less => return true,
+            // path 是 start 的前缀，只有 start 的下一个字节对应的子节点需要继续比较
+            cmp::Ordering::Equal if n < start.len() => start,
+            // 整个子树都不小于 start
+            _ => &[],
+        };
+        if start.is_empty() && let Some(pos) = self.value.as_ref() && !f(path, pos) {
+            return false;
+        }
+
+        let next = start.get(path.len()).copied();
+        for (byte, child) in self.children.iter() {
+            let child_start = match next {
+                Some(next) if byte < next => continue,
+                Some(next) if byte == next => start,
+                _ => &[],
+            };
+            path.push(byte);
+            let go_on = child.walk(path, child_start, f);
+            path.pop();
+            if !go_on {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn new(prefix: &[u8]) -> Self {
+        Self {
+            prefix: prefix.into(),
+            value: None,
+            children: Children::Node16 {
+                keys: Vec::with_capacity(4),
+                nodes: Vec::with_capacity(4),
+            },
+        }
+    }
+
+    fn own_size(&self) -> usize {
+        size_of::<Inner>() + self.prefix.len() + self.children.own_size()
+    }
+
+    // 添加 key 剩余的部分，调用方保证不会和已有的子节点冲突
+    fn add(&mut self, key: &[u8], pos: LogRecordPos) {
+        match key.split_first() {
+            Some((byte, rest)) => self.children.insert(*byte, Node::leaf(rest, pos)),
+            None => self.value = Some(pos),
+        }
+    }
+}
+
+impl Children {
+    fn len(&self) -> usize {
+        match self {
+            Children::Node16 { keys, .. } => keys.len(),
+            Children::Node48 { nodes, .. } => nodes.len(),
+            Children::Node256 { len, .. } => *len,
+        }
+    }
+
+    fn get(&self, byte: u8) -> Option<&Node> {
+        match self {
+            Children::Node16 { keys, nodes } => {
+                let idx = keys.iter().position(|k| *k == byte)?;
+                Some(&nodes[idx])
+            }
+            Children::Node48 { index, nodes } => match index[byte as usize] {
+                0 => None,
+                idx => Some(&nodes[idx as usize - 1]),
+            },
+            Children::Node256 { nodes, .. } => nodes[byte as usize].as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self, byte: u8) -> Option<&mut Node> {
+        match self {
+            Children::Node16 { keys, nodes } => {
+                let idx = keys.iter().position(|k| *k == byte)?;
+                Some(&mut nodes[idx])
+            }
+            Children::Node48 { index, nodes } => match index[byte as usize] {
+                0 => None,
+                idx => Some(&mut nodes[idx as usize - 1]),
+            },
+            Children::Node256 { nodes, .. } => nodes[byte as usize].as_mut(),
+        }
+    }
+
+    fn first_byte(&self) -> Option<u8> {
+        match self {
+            Children::Node16 { keys, .. } => keys.first().copied(),
+            Children::Node48 { index, .. } => (0..=255u8).find(|b| index[*b as usize] != 0),
+            Children::Node256 { nodes, .. } => (0..=255u8).find(|b| nodes[*b as usize].is_some()),
+        }
+    }
+
+    // 插入子节点，调用方保证 byte 对应的子节点不存在
+    fn insert(&mut self, byte: u8, node: Node) {
+        self.grow();
+        match self {
+            Children::Node16 { keys, nodes } => {
+                let idx = keys.partition_point(|k| *k < byte);
+                keys.insert(idx, byte);
+                nodes.insert(idx, node);
+            }
+            Children::Node48 { index, nodes } => {
+                nodes.push(node);
+                index[byte as usize] = nodes.len() as u8;
+            }
+            Children::Node256 { nodes, len } => {
+                nodes[byte as usize] = Some(node);
+                *len += 1;
+            }
+        }
+    }
+
+    fn remove(&mut self, byte: u8) -> Option<Node> {
+        let node = match self {
+            Children::Node16 { keys, nodes } => {
+                let idx = keys.iter().position(|k| *k == byte)?;
+                keys.remove(idx);
+                Some(nodes.remove(idx))
+            }
+            Children::Node48 { index, nodes } => {
+                let idx = match index[byte as usize] {
+                    0 => return None,
+                    idx => idx as usize - 1,
+                };
+                index[byte as usize] = 0;
+                // 将最后一个子节点移动到删除的位置上
+                let last = nodes.len() as u8;
+                if let Some(moved) = index.iter_mut().find(|i| **i == last) {
+                    *moved = idx as u8 + 1;
+                }
+                Some(nodes.swap_remove(idx))
+            }
+            Children::Node256 { nodes, len } => {
+                let node = nodes[byte as usize].take()?;
+                *len -= 1;
+                Some(node)
+            }
+        };
+        self.shrink();
+        node
+    }
+
+    // 子节点已满时扩展为更大的节点
+    fn grow(&mut self) {
+        match self {
+            Children::Node16 { keys, nodes } if keys.len() == NODE16_MAX => {
+                let mut index = Box::new([0u8; 256]);
+                for (i, byte) in keys.iter().enumerate() {
+                    index[*byte as usize] = i as u8 + 1;
+                }
+                let mut new_nodes = Vec::with_capacity(NODE48_MAX);
+                new_nodes.append(nodes);
+                *self = Children::Node48 {
+                    index,
+                    nodes: new_nodes,
+                };
+            }
+            Children::Node48 { index, nodes } if nodes.len() == NODE48_MAX => {
+                let mut new_nodes: Vec<Option<Node>> = (0..256).map(|_| None).collect();
+                let mut slots: Vec<Option<Node>> = nodes.drain(..).map(Some).collect();
+                for byte in 0..256 {
+                    if index[byte] != 0 {
+                        new_nodes[byte] = slots[index[byte] as usize - 1].take();
+                    }
+                }
+                *self = Children::Node256 {
+                    nodes: new_nodes.into_boxed_slice(),
+                    len: NODE48_MAX,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    // 子节点数量较少时收缩为更小的节点
+    fn shrink(&mut self) {
+        match self {
+            Children::Node48 { index, nodes } if nodes.len() <= NODE48_SHRINK => {
+                let mut slots: Vec<Option<Node>> = nodes.drain(..).map(Some).collect();
+                let mut new_keys = Vec::with_capacity(NODE16_MAX);
+                let mut new_nodes = Vec::with_capacity(NODE16_MAX);
+                for byte in 0..256 {
+                    if index[byte] != 0 {
+                        new_keys.push(byte as u8);
+                        new_nodes.push(slots[index[byte] as usize - 1].take().unwrap());
+                    }
+                }
+                *self = Children::Node16 {
+                    keys: new_keys,
+                    nodes: new_nodes,
+                };
+            }
+            Children::Node256 { nodes, len } if *len <= NODE256_SHRINK => {
+                let mut index = Box::new([0u8; 256]);
+                let mut new_nodes = Vec::with_capacity(NODE48_MAX);
+                for (byte, node) in nodes.iter_mut().enumerate() {
+                    if let Some(node) = node.take() {
+                        new_nodes.push(node);
+                        index[byte] = new_nodes.len() as u8;
+                    }
+                }
+                *self = Children::Node48 {
+                    index,
+                    nodes: new_nodes,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    // 按照字节的顺序遍历子节点
+    fn iter(&self) -> Box<dyn Iterator<Item = (u8, &Node)> + '_> {
+        match self {
+            Children::Node16 { keys, nodes } => Box::new(keys.iter().copied().zip(nodes.iter())),
+            Children::Node48 { index, nodes } => Box::new(
+                (0..256)
+                    .filter(|byte| index[*byte] != 0)
+                    .map(|byte| (byte as u8, &nodes[index[byte] as usize - 1])),
+            ),
+            Children::Node256 { nodes, .. } => Box::new(
+                nodes
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(byte, node)| node.as_ref().map(|node| (byte as u8, node))),
+            ),
+        }
+    }
+
+    // 存放子节点的结构占用的堆内存大小，不包括子节点指向的内容
+    fn own_size(&self) -> usize {
+        match self {
+            Children::Node16 { keys, nodes } => {
+                keys.capacity() + nodes.capacity() * size_of::<Node>()
+            }
+            Children::Node48 { nodes, .. } => 256 + nodes.capacity() * size_of::<Node>(),
+            Children::Node256 { nodes, .. } => nodes.len() * size_of::<Option<Node>>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::indexer::tests::check_iterator_range;
+    use crate::index::btree::BTree;
+
+    fn test_pos(offset: u64) -> LogRecordPos {
+        LogRecordPos {
+            file_id: 1,
+            offset,
+            size: 11,
+        }
+    }
+
+    // 增量维护的内存大小和遍历整棵树计算的结果一致
+    fn assert_memory_usage(art: &Art) {
+        let read_guard = art.tree.read();
+        let nodes = read_guard.root.as_ref().map_or(0, |root| root.heap_size());
+        assert_eq!(read_guard.size, nodes);
+    }
+
+    #[test]
+    fn test_art_put_get() {
+        let art = Art::new();
+        assert!(art.get("aa".as_bytes().to_vec()).unwrap().is_none());
+
+        assert!(art.put("".as_bytes().to_vec(), test_pos(1)).unwrap().is_none());
+        assert!(art.put("aa".as_bytes().to_vec(), test_pos(2)).unwrap().is_none());
+        assert!(art.put("aab".as_bytes().to_vec(), test_pos(3)).unwrap().is_none());
+        assert!(art.put("ab".as_bytes().to_vec(), test_pos(4)).unwrap().is_none());
+        assert!(art.put("a".as_bytes().to_vec(), test_pos(5)).unwrap().is_none());
+        assert_memory_usage(&art);
+
+        // 重复写入返回旧的位置
+        let res = art.put("aa".as_bytes().to_vec(), test_pos(6)).unwrap();
+        assert_eq!(res.unwrap().offset, 2);
+
+        assert_eq!(art.get("".as_bytes().to_vec()).unwrap().unwrap().offset, 1);
+        assert_eq!(art.get("aa".as_bytes().to_vec()).unwrap().unwrap().offset, 6);
+        assert_eq!(art.get("aab".as_bytes().to_vec()).unwrap().unwrap().offset, 3);
+        assert_eq!(art.get("ab".as_bytes().to_vec()).unwrap().unwrap().offset, 4);
+        assert_eq!(art.get("a".as_bytes().to_vec()).unwrap().unwrap().offset, 5);
+        assert!(art.get("aabb".as_bytes().to_vec()).unwrap().is_none());
+        assert!(art.get("b".as_bytes().to_vec()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_art_delete() {
+        let art = Art::new();
+        assert!(art.delete("aa".as_bytes().to_vec()).unwrap().is_none());
+
+        let keys = ["a", "aa", "aab", "ab", "tenant/1/user/1", "tenant/1/user/2"];
+        for (i, key) in keys.iter().enumerate() {
+            art.put(key.as_bytes().to_vec(), test_pos(i as u64)).unwrap();
+        }
+        assert!(art.delete("aac".as_bytes().to_vec()).unwrap().is_none());
+        assert!(art.delete("tenant/1".as_bytes().to_vec()).unwrap().is_none());
+
+        for (i, key) in keys.iter().enumerate() {
+            let res = art.delete(key.as_bytes().to_vec()).unwrap();
+            assert_eq!(res.unwrap().offset, i as u64);
+            assert_memory_usage(&art);
+            assert!(art.get(key.as_bytes().to_vec()).unwrap().is_none());
+            // 剩余的 key 不受影响
+            for (j, other) in keys.iter().enumerate().skip(i + 1) {
+                assert_eq!(art.get(other.as_bytes().to_vec()).unwrap().unwrap().offset, j as u64);
+            }
+        }
+        assert!(art.tree.read().root.is_none());
+        assert_eq!(art.tree.read().len, 0);
+        assert_eq!(art.tree.read().size, 0);
+    }
+
+    #[test]
+    fn test_art_grow_and_shrink() {
+        let art = Art::new();
+        // 同一个节点下有 256 个子节点，依次经过 Node16、Node48、Node256
+        for i in 0..=255u8 {
+            let key = vec![b'k', i, b'v'];
+            assert!(art.put(key, test_pos(i as u64)).unwrap().is_none());
+            assert_memory_usage(&art);
+        }
+        for i in 0..=255u8 {
+            assert_eq!(art.get(vec![b'k', i, b'v']).unwrap().unwrap().offset, i as u64);
+        }
+        let keys = art.list_keys().unwrap();
+        assert_eq!(keys.len(), 256);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        // 删除之后依次收缩
+        for i in (0..=255u8).rev().step_by(2) {
+            assert!(art.delete(vec![b'k', i, b'v']).unwrap().is_some());
+            assert_memory_usage(&art);
+        }
+        for i in 0..=255u8 {
+            let pos = art.get(vec![b'k', i, b'v']).unwrap();
+            assert_eq!(pos.is_some(), i % 2 == 0);
+        }
+        for i in (0..=255u8).step_by(2) {
+            assert!(art.delete(vec![b'k', i, b'v']).unwrap().is_some());
+            assert_memory_usage(&art);
+        }
+        assert!(art.list_keys().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_art_iterator() {
+        let art = Art::new();
+        let mut iter1 = art.iterator(IteratorOptions::default());
+        assert!(iter1.next().is_none());
+
+        for key in ["cadd", "bbed", "aaed", "cdea", "bbee", "bb"] {
+            art.put(key.as_bytes().to_vec(), test_pos(0)).unwrap();
+        }
+
+        // 迭代结果按照 key 排序
+        let mut iter2 = art.iterator(IteratorOptions::default());
+        let mut keys = Vec::new();
+        while let Some(item) = iter2.next() {
+            keys.push(String::from_utf8(item.0.clone()).unwrap());
+        }
+        assert_eq!(keys, vec!["aaed", "bb", "bbed", "bbee", "cadd", "cdea"]);
+
+        let mut iter3 = art.iterator(IteratorOptions::default());
+        iter3.seek("bbef".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &"cadd".as_bytes().to_vec());
+
+        // 反向迭代
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.reverse = true;
+        let mut iter4 = art.iterator(iter_opts);
+        iter4.seek("c".as_bytes().to_vec());
+        assert_eq!(iter4.next().unwrap().0, &"bbee".as_bytes().to_vec());
+
+        // 有前缀的情况
+        for (prefix, count) in [("bb", 3), ("bbe", 2), ("c", 2), ("cadd", 1), ("caddd", 0), ("d", 0)] {
+            let mut iter_opts = IteratorOptions::default();
+            iter_opts.prefix = prefix.as_bytes().to_vec();
+            let mut iter5 = art.iterator(iter_opts);
+            let mut n = 0;
+            while let Some(item) = iter5.next() {
+                assert!(item.0.starts_with(prefix.as_bytes()));
+                n += 1;
+            }
+            assert_eq!(n, count);
+        }
+    }
+
+    #[test]
+    fn test_art_memory_usage() {
+        let art = Art::new();
+        let bt = BTree::new();
+        assert!(art.memory_usage() < 1024);
+
+        // 有大量公共前缀的 key 占用的内存少于 BTree
+        for i in 0..10000 {
+            let key = format!("tenant/{}/user/{:08}", i % 10, i).into_bytes();
+            art.put(key.clone(), test_pos(i)).unwrap();
+            bt.put(key, test_pos(i)).unwrap();
+        }
+        assert!(art.memory_usage() > 0);
+        assert!(art.memory_usage() < bt.memory_usage());
+    }
+
+    #[test]
+    fn test_art_iterator_range() {
+        check_iterator_range(&Art::new());
+    }
+}
@@ -3,9 +3,10 @@
 use std::sync::Arc;
 use std::path::PathBuf;
 use bytes::Bytes;
-use jammdb::DB;
-use crate::errors::AppResult;
-use super::indexer::Indexer;
+use jammdb::{Bucket, DB};
+use log::error;
+use crate::errors::{AppErrors, AppResult};
+use super::indexer::{IndexOp, Indexer};
 use super::index_iterator::IndexIterator;
 use super::bptree_iterator::BPTreeIterator;
 use crate::options::iterator_options::IteratorOptions;
@@ -13,7 +14,7 @@ use crate::data::log_record_mod::log_record_pos::LogRecordPos;
 use crate::data::log_record_mod::decode_log_record_pos;
 
 const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
-const BPTREE_BUCKET_NAME: &str = "bitcask-index";
+pub(crate) const BPTREE_BUCKET_NAME: &str = "bitcask-index";
 
 // B+树索引
 pub struct BPlusTree {
@@ -22,68 +23,101 @@ pub struct BPlusTree {
 
 impl BPlusTree {
     // 实例化方法
-    pub fn new(dir_path: PathBuf) -> Self {
-        // 打开 B+ 树实例，并创建对应的 bucket
-        let bptree: DB =
-            DB::open(dir_path.join(BPTREE_INDEX_FILE_NAME)).expect("failed to open bptree");
-        let tree: Arc<DB> = Arc::new(bptree);
-        let tx = tree.tx(true).expect("failed to begin tx");
-        tx.get_or_create_bucket(BPTREE_BUCKET_NAME).unwrap();
-        tx.commit().unwrap();
+    pub fn new(dir_path: PathBuf) -> AppResult<Self> {
+        Self::open(dir_path.join(BPTREE_INDEX_FILE_NAME))
+    }
+
+    // 打开 path 对应的 B+ 树实例，并创建对应的 bucket
+    pub(crate) fn open(path: PathBuf) -> AppResult<Self> {
+        let bptree = DB::open(path).map_err(|e| {
+            error!("failed to open bptree index: {}", e);
+            AppErrors::FailedToOpenBPTreeIndex
+        })?;
+        let tree = Arc::new(bptree);
+        let tx = tree.tx(true).map_err(write_err)?;
+        tx.get_or_create_bucket(BPTREE_BUCKET_NAME).map_err(write_err)?;
+        tx.commit().map_err(write_err)?;
 
-        Self { tree: tree.clone() }
+        Ok(Self { tree })
+    }
+}
+
+// 记录 jammdb 读取时返回的错误
+pub(crate) fn read_err(e: jammdb::Error) -> AppErrors {
+    error!("failed to read bptree index: {}", e);
+    AppErrors::FailedToReadBPTreeIndex
+}
+
+// 记录 jammdb 写入时返回的错误
+fn write_err(e: jammdb::Error) -> AppErrors {
+    error!("failed to write bptree index: {}", e);
+    AppErrors::FailedToWriteBPTreeIndex
+}
+
+// 解码 B+ 树中存储的位置信息
+pub(crate) fn decode_pos(value: &[u8]) -> AppResult<LogRecordPos> {
+    decode_log_record_pos(value.to_vec()).map_err(|_| AppErrors::FailedToReadBPTreeIndex)
+}
+
+// 删除 key，key 不存在时返回 None
+fn delete_kv(bucket: &Bucket<'_, '_>, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+    match bucket.delete(key) {
+        Ok(kv) => decode_pos(kv.value()).map(Some),
+        Err(jammdb::Error::KeyValueMissing) => Ok(None),
+        Err(e) => Err(write_err(e)),
     }
 }
 
 impl Indexer for BPlusTree {
-    fn put(
-        &self,
-        key: Vec<u8>,
-        pos: LogRecordPos,
-    ) -> Option<LogRecordPos> {
-        let mut result: Option<LogRecordPos> = None;
-        let tx = self.tree.tx(true).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>> {
+        let tx = self.tree.tx(true).map_err(write_err)?;
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).map_err(write_err)?;
 
         // 先获取到旧的值
-        if let Some(kv) = bucket.get_kv(&key) {
-            let pos = decode_log_record_pos(kv.value().to_vec());
-            result = Some(pos);
-        }
+        let result = bucket.get_kv(&key).map(|kv| decode_pos(kv.value())).transpose()?;
 
         // put 新值
-        bucket
-            .put(key, pos.encode())
-            .expect("failed to put value in bptree");
-        tx.commit().unwrap();
+        bucket.put(key, pos.encode()).map_err(write_err)?;
+        tx.commit().map_err(write_err)?;
+        Ok(result)
+    }
 
-        result
+    fn get(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let tx = self.tree.tx(false).map_err(read_err)?;
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).map_err(read_err)?;
+        bucket.get_kv(key).map(|kv| decode_pos(kv.value())).transpose()
     }
 
-    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-        let tx = self.tree.tx(false).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-        if let Some(kv) = bucket.get_kv(key) {
-            return Some(decode_log_record_pos(kv.value().to_vec()));
-        }
-        None
+    fn delete(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let tx = self.tree.tx(true).map_err(write_err)?;
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).map_err(write_err)?;
+        let result = delete_kv(&bucket, key)?;
+        tx.commit().map_err(write_err)?;
+        Ok(result)
     }
 
-    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-        let mut result = None;
-        let tx = self.tree.tx(true).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-        if let Ok(kv) = bucket.delete(key) {
-            let pos = decode_log_record_pos(kv.value().to_vec());
-            result = Some(pos);
+    fn batch_update(&self, ops: Vec<IndexOp>) -> AppResult<Vec<Option<LogRecordPos>>> {
+        // 所有的操作在同一个事务中完成，只需要提交一次
+        let tx = self.tree.tx(true).map_err(write_err)?;
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).map_err(write_err)?;
+        let mut result = Vec::with_capacity(ops.len());
+        for op in ops {
+            match op {
+                IndexOp::Put(key, pos) => {
+                    let old = bucket.get_kv(&key).map(|kv| decode_pos(kv.value())).transpose()?;
+                    bucket.put(key, pos.encode()).map_err(write_err)?;
+                    result.push(old);
+                }
+                IndexOp::Delete(key) => result.push(delete_kv(&bucket, key)?),
+            }
         }
-        tx.commit().unwrap();
-        result
+        tx.commit().map_err(write_err)?;
+        Ok(result)
     }
 
     fn list_keys(&self) -> AppResult<Vec<Bytes>> {
-        let tx = self.tree.tx(false).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+        let tx = self.tree.tx(false).map_err(read_err)?;
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).map_err(read_err)?;
         let mut keys = Vec::new();
 
         for data in bucket.cursor() {
@@ -93,23 +127,239 @@ impl Indexer for BPlusTree {
     }
 
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-        let mut items: Vec<(Vec<u8>, LogRecordPos)> = Vec::new();
-        let tx = self.tree.tx(false).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+        Box::new(BPTreeIterator::new(self.tree.clone(), options))
+    }
 
-        for data in bucket.cursor() {
-            let key = data.key().to_vec();
-            let pos = decode_log_record_pos(data.kv().value().to_vec());
-            items.push((key, pos));
+    fn memory_usage(&self) -> usize {
+        // 索引存储在磁盘上，不占用额外的内存
+        0
+    }
+
+    fn is_persisted(&self) -> bool {
+        true
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::index::indexer::tests::check_iterator_range;
+
+    #[test]
+    fn test_bptree_iterator() {
+        let path = PathBuf::from("/tmp/bptree-iter");
+        fs::create_dir_all(path.clone()).unwrap();
+        let bpt = BPlusTree::new(path.clone()).unwrap();
+        let mut iter1 = bpt.iterator(IteratorOptions::default());
+        assert!(iter1.next().is_none());
+
+        // 数据量超过一个批次，迭代时需要分多次读取
+        let n = 3000u64;
+        for i in 0..n {
+            let pos = LogRecordPos {
+                file_id: 1,
+                offset: i,
+                size: 11,
+            };
+            bpt.put(format!("key-{:05}", i).into_bytes(), pos).unwrap();
         }
-        if options.reverse {
-            items.reverse();
+
+        let mut iter2 = bpt.iterator(IteratorOptions::default());
+        let mut expected = 0u64;
+        while let Some((key, pos)) = iter2.next() {
+            assert_eq!(key, &format!("key-{:05}", expected).into_bytes());
+            assert_eq!(pos.offset, expected);
+            expected += 1;
         }
+        assert_eq!(expected, n);
+        iter2.seek("key-02999".as_bytes().to_vec());
+        assert_eq!(iter2.next().unwrap().0, &"key-02999".as_bytes().to_vec());
+        assert!(iter2.next().is_none());
+        iter2.rewind();
+        assert_eq!(iter2.next().unwrap().0, &"key-00000".as_bytes().to_vec());
 
-        Box::new(BPTreeIterator {
-            items,
-            curr_index: 0usize,
-            options,
-        })
+        // 反向迭代
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.reverse = true;
+        let mut iter3 = bpt.iterator(iter_opts);
+        let mut expected = n;
+        while let Some((key, _)) = iter3.next() {
+            expected -= 1;
+            assert_eq!(key, &format!("key-{:05}", expected).into_bytes());
+        }
+        assert_eq!(expected, 0);
+        iter3.seek("key-01500a".as_bytes().to_vec());
+        let mut expected = 1501u64;
+        while let Some((key, _)) = iter3.next() {
+            expected -= 1;
+            assert_eq!(key, &format!("key-{:05}", expected).into_bytes());
+        }
+        assert_eq!(expected, 0);
+
+        // 分段的边界只在第一次扫描，之后写入的 key 也在某个分段中
+        let pos = LogRecordPos {
+            file_id: 2,
+            offset: 0,
+            size: 11,
+        };
+        bpt.put("a".as_bytes().to_vec(), pos).unwrap();
+        bpt.put("key-01500a".as_bytes().to_vec(), pos).unwrap();
+        iter3.rewind();
+        let mut keys = Vec::new();
+        while let Some((key, _)) = iter3.next() {
+            keys.push(key.clone());
+        }
+        assert_eq!(keys.len(), n as usize + 2);
+        assert!(keys.windows(2).all(|w| w[0] > w[1]));
+        assert_eq!(keys.last().unwrap(), &"a".as_bytes().to_vec());
+        iter3.seek("key-01500a".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &"key-01500a".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &"key-01500".as_bytes().to_vec());
+        bpt.delete("a".as_bytes().to_vec()).unwrap();
+        bpt.delete("key-01500a".as_bytes().to_vec()).unwrap();
+
+        // 有前缀的情况
+        for reverse in [false, true] {
+            let mut iter_opts = IteratorOptions::default();
+            iter_opts.prefix = "key-01".as_bytes().to_vec();
+            iter_opts.reverse = reverse;
+            let mut iter4 = bpt.iterator(iter_opts);
+            let mut count = 0;
+            while let Some(item) = iter4.next() {
+                assert!(item.0.starts_with("key-01".as_bytes()));
+                count += 1;
+            }
+            assert_eq!(count, 1000);
+        }
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_batch_update() {
+        let path = PathBuf::from("/tmp/bptree-batch-update");
+        fs::create_dir_all(path.clone()).unwrap();
+        let bpt = BPlusTree::new(path.clone()).unwrap();
+        let pos = |offset| LogRecordPos {
+            file_id: 1,
+            offset,
+            size: 11,
+        };
+        bpt.put("aa".as_bytes().to_vec(), pos(10)).unwrap();
+
+        let res = bpt.batch_update(vec![
+            IndexOp::Put("aa".as_bytes().to_vec(), pos(20)),
+            IndexOp::Put("bb".as_bytes().to_vec(), pos(30)),
+            IndexOp::Delete("bb".as_bytes().to_vec()),
+            IndexOp::Delete("cc".as_bytes().to_vec()),
+            IndexOp::Put("dd".as_bytes().to_vec(), pos(40)),
+        ]).unwrap();
+        let offsets: Vec<Option<u64>> = res.iter().map(|old| old.map(|p| p.offset)).collect();
+        assert_eq!(offsets, vec![Some(10), None, Some(30), None, None]);
+
+        assert_eq!(bpt.get("aa".as_bytes().to_vec()).unwrap().unwrap().offset, 20);
+        assert!(bpt.get("bb".as_bytes().to_vec()).unwrap().is_none());
+        assert_eq!(bpt.get("dd".as_bytes().to_vec()).unwrap().unwrap().offset, 40);
+        assert_eq!(bpt.list_keys().unwrap().len(), 2);
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_open_error() {
+        // 目录不存在时返回错误，而不是 panic
+        let res = BPlusTree::new(PathBuf::from("/tmp/bptree-not-exist/sub-dir"));
+        assert_eq!(AppErrors::FailedToOpenBPTreeIndex, res.err().unwrap());
+    }
+
+    #[test]
+    fn test_bptree_iterator_error() {
+        let path = PathBuf::from("/tmp/bptree-iter-error");
+        fs::create_dir_all(path.clone()).unwrap();
+        let bpt = BPlusTree::new(path.clone()).unwrap();
+        let pos = LogRecordPos {
+            file_id: 1,
+            offset: 10,
+            size: 11,
+        };
+        bpt.put("aa".as_bytes().to_vec(), pos).unwrap();
+
+        // bucket 被删除之后读取失败，迭代结束并返回错误
+        for reverse in [false, true] {
+            let mut iter = bpt.iterator(IteratorOptions {
+                reverse,
+                ..Default::default()
+            });
+            assert!(iter.error().is_none());
+            let tx = bpt.tree.tx(true).unwrap();
+            tx.delete_bucket(BPTREE_BUCKET_NAME).unwrap();
+            tx.commit().unwrap();
+            iter.rewind();
+            assert!(iter.next().is_none());
+            assert_eq!(Some(AppErrors::FailedToReadBPTreeIndex), iter.error());
+
+            // 恢复之后重新定位，错误被清除
+            let tx = bpt.tree.tx(true).unwrap();
+            tx.create_bucket(BPTREE_BUCKET_NAME).unwrap();
+            tx.commit().unwrap();
+            bpt.put("aa".as_bytes().to_vec(), pos).unwrap();
+            iter.rewind();
+            assert_eq!(iter.next().unwrap().0, &"aa".as_bytes().to_vec());
+            assert!(iter.error().is_none());
+        }
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_corrupted_pos() {
+        let path = PathBuf::from("/tmp/bptree-corrupted-pos");
+        fs::create_dir_all(path.clone()).unwrap();
+        let bpt = BPlusTree::new(path.clone()).unwrap();
+
+        // 写入一个不完整的 varint 作为位置信息
+        let tx = bpt.tree.tx(true).unwrap();
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+        bucket.put("aa".as_bytes().to_vec(), vec![0xff]).unwrap();
+        tx.commit().unwrap();
+
+        // 解码失败时返回错误，而不是 panic
+        let res = bpt.get("aa".as_bytes().to_vec());
+        assert_eq!(AppErrors::FailedToReadBPTreeIndex, res.err().unwrap());
+        let pos = LogRecordPos {
+            file_id: 1,
+            offset: 10,
+            size: 11,
+        };
+        let res = bpt.put("aa".as_bytes().to_vec(), pos);
+        assert_eq!(AppErrors::FailedToReadBPTreeIndex, res.err().unwrap());
+
+        for reverse in [false, true] {
+            let mut iter = bpt.iterator(IteratorOptions {
+                reverse,
+                ..Default::default()
+            });
+            assert!(iter.next().is_none());
+            assert_eq!(Some(AppErrors::FailedToReadBPTreeIndex), iter.error());
+
+            // 只需要 key 时不解码位置信息
+            let mut iter = bpt.iterator(IteratorOptions {
+                reverse,
+                keys_only: true,
+                ..Default::default()
+            });
+            assert_eq!(iter.next().unwrap().0, &"aa".as_bytes().to_vec());
+            assert!(iter.error().is_none());
+        }
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_iterator_range() {
+        let path = PathBuf::from("/tmp/bptree-iter-range");
+        fs::create_dir_all(path.clone()).unwrap();
+        check_iterator_range(&BPlusTree::new(path.clone()).unwrap());
+        fs::remove_dir_all(path).unwrap();
     }
-}
\ No newline at end of file
+}
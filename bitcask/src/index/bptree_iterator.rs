@@ -1,41 +1,314 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use jammdb::DB;
 use crate::data::log_record_mod::log_record_pos::LogRecordPos;
-use crate::options::iterator_options::IteratorOptions;
+use crate::options::iterator_options::{IterLimit, IteratorOptions};
+use crate::errors::{AppErrors, AppResult};
+use super::bptree::{decode_pos, read_err, BPTREE_BUCKET_NAME};
 use super::index_iterator::IndexIterator;
 
+// 每次从 B+ 树中读取的数据条数
+const BPTREE_ITERATOR_BATCH_SIZE: usize = 1024;
+
 /// B+ 树索引迭代器
+/// 每次开启一个只读事务读取一批数据，不需要把整个索引加载到内存中，也不会长时间持有事务
+/// 只从迭代范围的起点开始读取，越过上界或者前缀之后停止，只需要 key 时不解码位置信息
+///
+/// jammdb 的游标只能正向遍历，只读事务也不能跨线程持有，所以反向迭代时先正向扫描一遍需要的范围，
+/// 每 BPTREE_ITERATOR_BATCH_SIZE 个 key 记录一个分段的边界，然后从后往前逐个分段读取并倒序返回。
+/// 扫描在第一次调用 next 时才进行，seek 只扫描到 seek 的 key 为止，已经扫描过的边界之后的 rewind 和 seek 复用，
+/// 需要更大的范围时从最后一个分段继续扫描，反向迭代读取的 key 不超过范围内 key 数量的两倍再加一个分段
+///
+/// 一致性：每一批数据在同一个只读事务中读取，批次内部是一致的；不同批次之间可能看到迭代期间的写入。
+/// 长时间持有只读事务会阻塞 B+ 树文件的扩容，所以不在整个迭代期间持有同一个事务。
+/// 分段之间首尾相接并覆盖扫描过的范围，之后写入的 key 也会落在某个分段中
+/// 读取失败时结束迭代，错误通过 error 返回
 pub struct BPTreeIterator {
-    pub items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key+索引
-    pub curr_index: usize,                   // 当前遍历的位置下标
-    pub options: IteratorOptions,            // 配置项
+    tree: Arc<DB>,
+    options: IteratorOptions,
+    items: Vec<(Vec<u8>, LogRecordPos)>, // 当前批次的 key+索引
+    curr_index: usize,                   // 当前批次中遍历的位置下标
+    limit: IterLimit,                    // 数量限制
+    next_start: Option<(Vec<u8>, bool)>, // 正向迭代时下一批次的起始 key 以及是否包含该 key，None 表示迭代完毕
+    chunks: Vec<Vec<u8>>,                // 反向迭代时各个分段的起始 key，从小到大排列，第一个分段从迭代范围的起点开始
+    covered: Option<Vec<u8>>,            // 分段边界已经覆盖到的最大的 key（包含）
+    covered_all: bool,                   // 分段边界是否已经覆盖整个迭代范围
+    next_chunk: Option<usize>,           // 反向迭代时还没有读取的分段数量，None 表示还没有定位
+    upper: Option<Vec<u8>>,              // 反向迭代时最大的 key（包含），None 表示没有上界
+    keys_read: AtomicUsize,              // 从 B+ 树中读取过的 key 数量
+    err: Option<AppErrors>,              // 读取失败的错误，rewind 或 seek 之后清除
 }
 
-impl IndexIterator for BPTreeIterator {
-    fn rewind(&mut self) -> () {
-        self.curr_index = 0usize;
+impl BPTreeIterator {
+    pub fn new(tree: Arc<DB>, options: IteratorOptions) -> Self {
+        let mut iter = Self {
+            tree,
+            items: Vec::new(),
+            curr_index: 0,
+            limit: IterLimit::new(options.limit),
+            chunks: vec![options.start_key()],
+            options,
+            next_start: None,
+            covered: None,
+            covered_all: false,
+            next_chunk: None,
+            upper: None,
+            keys_read: AtomicUsize::new(0),
+            err: None,
+        };
+        iter.rewind();
+        iter
     }
 
-    fn seek(&mut self, key: Vec<u8>) -> () {
-        self.curr_index = self.items.binary_search_by(|(x, _)| {
-            if self.options.reverse {
-                x.cmp(&key).reverse()
-            } else {
-                x.cmp(&key)
+    // 从 start 开始正向遍历迭代范围内的数据，越过范围或者 f 返回 false 时停止，f 返回错误时结束遍历
+    fn scan<F>(&self, start: &[u8], mut f: F) -> AppResult<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> AppResult<bool>,
+    {
+        let tx = self.tree.tx(false).map_err(read_err)?;
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).map_err(read_err)?;
+        for data in bucket.range(start..) {
+            let kv = data.kv();
+            self.keys_read.fetch_add(1, Ordering::Relaxed);
+            if self.options.is_past(kv.key()) {
+                break;
+            }
+            if !self.options.contains(kv.key()) {
+                continue;
+            }
+            if !f(kv.key(), kv.value())? {
+                break;
             }
-        }).unwrap_or_else(|insert_val| insert_val);
+        }
+        Ok(())
+    }
+
+    // 只需要 key 时不解码位置信息，返回不能用来读取数据的位置
+    fn decode_pos(&self, value: &[u8]) -> AppResult<LogRecordPos> {
+        match self.options.keys_only {
+            true => Ok(LogRecordPos::UNRESOLVED),
+            false => decode_pos(value),
+        }
+    }
+
+    // 反向迭代时让分段边界覆盖到 upper（包含），None 表示覆盖整个迭代范围
+    // 从最后一个分段的起点继续正向扫描，每 BPTREE_ITERATOR_BATCH_SIZE 个 key 开始一个新的分段
+    fn cover(&mut self, upper: Option<&Vec<u8>>) -> AppResult<()> {
+        if self.covered_all {
+            return Ok(());
+        }
+        if let (Some(upper), Some(covered)) = (upper, self.covered.as_ref())
+            && covered >= upper
+        {
+            return Ok(());
+        }
+
+        let last = self.chunks.last().unwrap().clone();
+        let mut chunks = Vec::new();
+        let mut count = 0usize;
+        let mut reach_upper = false;
+        self.scan(&last, |key, _| {
+            if upper.is_some_and(|upper| key > upper.as_slice()) {
+                reach_upper = true;
+                return Ok(false);
+            }
+            if count > 0 && count.is_multiple_of(BPTREE_ITERATOR_BATCH_SIZE) {
+                chunks.push(key.to_vec());
+            }
+            count += 1;
+            Ok(true)
+        })?;
+
+        self.chunks.extend(chunks);
+        match reach_upper {
+            true => self.covered = upper.cloned(),
+            false => self.covered_all = true,
+        }
+        Ok(())
+    }
+
+    // 读取下一批数据，没有更多数据时返回 false
+    fn read_batch(&mut self) -> bool {
+        let mut items = Vec::new();
+        if self.options.reverse {
+            // 第一次读取时扫描分段边界，定位到起始 key 不大于 upper 的最后一个分段
+            let next_chunk = match self.next_chunk {
+                Some(next_chunk) => next_chunk,
+                None => {
+                    let upper = self.upper.clone();
+                    if let Err(e) = self.cover(upper.as_ref()) {
+                        self.err = Some(e);
+                        return false;
+                    }
+                    match upper {
+                        Some(upper) => self.chunks.partition_point(|start| *start <= upper),
+                        None => self.chunks.len(),
+                    }
+                }
+            };
+            if next_chunk == 0 {
+                self.next_chunk = Some(0);
+                return false;
+            }
+            self.next_chunk = Some(next_chunk - 1);
+            let (start, end) = (&self.chunks[next_chunk - 1], self.chunks.get(next_chunk));
+            let upper = self.upper.as_ref();
+            let res = self.scan(start, |key, value| {
+                let in_chunk = end.is_none_or(|end| key < end.as_slice())
+                    && upper.is_none_or(|upper| key <= upper.as_slice());
+                if in_chunk {
+                    items.push((key.to_vec(), self.decode_pos(value)?));
+                }
+                Ok(in_chunk)
+            });
+            if let Err(e) = res {
+                self.next_chunk = Some(0);
+                self.err = Some(e);
+                return false;
+            }
+            items.reverse();
+        } else {
+            let (start, inclusive) = match self.next_start.take() {
+                Some(next_start) => next_start,
+                None => return false,
+            };
+            let res = self.scan(&start, |key, value| {
+                if !inclusive && key == start.as_slice() {
+                    return Ok(true);
+                }
+                items.push((key.to_vec(), self.decode_pos(value)?));
+                Ok(items.len() < BPTREE_ITERATOR_BATCH_SIZE)
+            });
+            if let Err(e) = res {
+                self.err = Some(e);
+                return false;
+            }
+            // 读满一批说明后面可能还有数据，下一批从最后一个 key 之后开始
+            if items.len() == BPTREE_ITERATOR_BATCH_SIZE {
+                self.next_start = items.last().map(|(key, _)| (key.clone(), false));
+            }
+        }
+        self.items = items;
+        self.curr_index = 0;
+        true
+    }
+}
+
+impl IndexIterator for BPTreeIterator {
+    fn rewind(&mut self) {
+        self.items.clear();
+        self.curr_index = 0;
+        self.limit.reset();
+        self.err = None;
+        if self.options.reverse {
+            self.upper = None;
+            self.next_chunk = None;
+        } else {
+            self.next_start = Some((self.options.start_key(), true));
+        }
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        self.items.clear();
+        self.curr_index = 0;
+        self.limit.reset();
+        self.err = None;
+        if self.options.reverse {
+            // 第一次读取时从起始 key 不大于 key 的最后一个分段开始读取
+            self.next_chunk = None;
+            self.upper = Some(key);
+        } else {
+            // 比前缀或者下界小的 key 都不在迭代的范围内
+            let start = key.max(self.options.start_key());
+            self.next_start = Some((start, true));
+        }
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
+        if self.limit.reached() {
             return None;
         }
-
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
-            let prefix: &Vec<u8> = &self.options.prefix;
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
-                return Some((&item.0, &item.1));
+        while self.curr_index >= self.items.len() {
+            if !self.read_batch() {
+                return None;
             }
         }
-        None
+        self.curr_index += 1;
+        self.limit.add();
+        let item = &self.items[self.curr_index - 1];
+        Some((&item.0, &item.1))
+    }
+
+    fn error(&self) -> Option<AppErrors> {
+        self.err.clone()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use super::*;
+
+    // 写入 n 个 key 并返回对应的 B+ 树
+    fn open_tree(path: &PathBuf, n: usize) -> Arc<DB> {
+        let _ = fs::remove_dir_all(path);
+        fs::create_dir_all(path).unwrap();
+        let tree = Arc::new(DB::open(path.join("bptree-index")).unwrap());
+        let tx = tree.tx(true).unwrap();
+        let bucket = tx.get_or_create_bucket(BPTREE_BUCKET_NAME).unwrap();
+        for i in 0..n {
+            let pos = LogRecordPos {
+                file_id: 1,
+                offset: i as u64,
+                size: 11,
+            };
+            bucket.put(format!("key-{:05}", i).into_bytes(), pos.encode()).unwrap();
+        }
+        tx.commit().unwrap();
+        tree
+    }
+
+    #[test]
+    fn test_bptree_iterator_reverse_keys_read() {
+        let path = PathBuf::from("/tmp/bptree-iter-keys-read");
+        let n = 5000;
+        let tree = open_tree(&path, n);
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.reverse = true;
+
+        // 创建迭代器时不扫描
+        let mut iter = BPTreeIterator::new(tree.clone(), iter_opts.clone());
+        assert_eq!(iter.keys_read.load(Ordering::Relaxed), 0);
+
+        // 完整的反向迭代读取的 key 不超过两倍再加上每个分段多读的一个
+        let mut count = 0;
+        while iter.next().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, n);
+        let chunks = n.div_ceil(BPTREE_ITERATOR_BATCH_SIZE);
+        let full = iter.keys_read.load(Ordering::Relaxed);
+        assert!(full <= 2 * n + chunks + 1);
+
+        // rewind 复用已经扫描的分段边界，只需要再读取一遍数据
+        iter.rewind();
+        while iter.next().is_some() {}
+        assert!(iter.keys_read.load(Ordering::Relaxed) - full <= n + chunks);
+
+        // seek 到靠前的 key 只扫描到该 key 为止
+        let mut iter = BPTreeIterator::new(tree.clone(), iter_opts.clone());
+        iter.seek("key-00100".as_bytes().to_vec());
+        assert_eq!(iter.next().unwrap().0, &"key-00100".as_bytes().to_vec());
+        assert!(iter.keys_read.load(Ordering::Relaxed) <= 2 * 102);
+
+        // 只取一条数据时读取的 key 不超过一次扫描再加上最后一个分段
+        let mut iter = BPTreeIterator::new(tree.clone(), iter_opts);
+        assert_eq!(iter.next().unwrap().0, &format!("key-{:05}", n - 1).into_bytes());
+        assert!(iter.keys_read.load(Ordering::Relaxed) <= n + 1 + BPTREE_ITERATOR_BATCH_SIZE);
+
+        drop(iter);
+        drop(tree);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
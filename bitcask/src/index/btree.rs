@@ -1,4 +1,5 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, mem::size_of, sync::Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use bytes::Bytes;
 use parking_lot::RwLock;
 use crate::data::log_record_mod::log_record_pos::LogRecordPos;
@@ -11,62 +12,86 @@ use super::indexer::Indexer;
 // BTree 索引，主要封装了标准库中的 BTreeMap 结构
 pub struct BTree {
     tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+    key_size: AtomicUsize, // 所有 key 占用的堆内存大小，在写锁内增量维护
+}
+
+impl Default for BTree {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl BTree {
     pub fn new() -> Self {
         Self {
             tree: Arc::new(RwLock::new(BTreeMap::new())),
+            key_size: AtomicUsize::new(0),
         }
     }
 }
 
 impl Indexer for BTree {
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>> {
         let mut write_guard = self.tree.write();
-        write_guard.insert(key, pos)
+        // 已经存在的 key 只替换位置信息，保留原来的 key
+        let key_size = key.capacity();
+        let old = write_guard.insert(key, pos);
+        if old.is_none() {
+            self.key_size.fetch_add(key_size, Ordering::Relaxed);
+        }
+        Ok(old)
     }
 
-    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    fn get(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
         let read_guard = self.tree.read();
-        read_guard.get(&key).copied()
+        Ok(read_guard.get(&key).copied())
     }
 
-    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    fn delete(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
         let mut write_guard = self.tree.write();
-        write_guard.remove(&key)
+        let old = write_guard.remove_entry(&key).map(|(k, pos)| {
+            self.key_size.fetch_sub(k.capacity(), Ordering::Relaxed);
+            pos
+        });
+        Ok(old)
     }
 
     fn list_keys(&self) -> AppResult<Vec<Bytes>> {
         let read_guard = self.tree.read();
         let mut keys = Vec::with_capacity(read_guard.len());
         for (k, _) in read_guard.iter() {
-            keys.push(Bytes::copy_from_slice(&k));
+            keys.push(Bytes::copy_from_slice(k));
         }
         Ok(keys)
     }
 
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
         let read_guard = self.tree.read();
-        let mut items = Vec::with_capacity(read_guard.len());
-        // 将 BTree 中的数据存储到数组中
-        for (key, value) in read_guard.iter() {
-            items.push((key.clone(), value.clone()));
-        }
-        if options.reverse {
-            items.reverse();
+        let mut items = Vec::new();
+        // 将 BTree 中迭代范围内的数据存储到数组中，越过上界或者前缀之后停止
+        for (key, value) in read_guard.range(options.start_key()..) {
+            if options.is_past(key) {
+                break;
+            }
+            if options.contains(key) {
+                items.push((key.clone(), *value));
+            }
         }
-        Box::new(BTreeIterator {
-            items,
-            curr_index: 0usize,
-            options,
-        })
+        Box::new(BTreeIterator::new(items, options))
+    }
+
+    fn memory_usage(&self) -> usize {
+        let len = self.tree.read().len();
+        // BTreeMap 的节点平均填充率按照 2/3 估算
+        let entry_size = size_of::<Vec<u8>>() + size_of::<LogRecordPos>();
+        len * entry_size * 3 / 2 + self.key_size.load(Ordering::Relaxed)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::index::indexer::tests::check_iterator_range;
 
     #[test]
     fn test_btree_put() {
@@ -78,7 +103,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res1.is_none());
 
         let res2 = bt.put(
@@ -88,7 +113,7 @@ mod tests {
                 offset: 22,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res2.is_none());
 
         let res3 = bt.put(
@@ -98,7 +123,7 @@ mod tests {
                 offset: 22122,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res3.is_some());
         let v = res3.unwrap();
         assert_eq!(v.file_id, 11);
@@ -115,7 +140,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res1.is_none());
         let res2 = bt.put(
             "aa".as_bytes().to_vec(),
@@ -124,15 +149,15 @@ mod tests {
                 offset: 22,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res2.is_none());
 
-        let pos1 = bt.get("".as_bytes().to_vec());
+        let pos1 = bt.get("".as_bytes().to_vec()).unwrap();
         assert!(pos1.is_some());
         assert_eq!(pos1.unwrap().file_id, 1);
         assert_eq!(pos1.unwrap().offset, 10);
 
-        let pos2 = bt.get("aa".as_bytes().to_vec());
+        let pos2 = bt.get("aa".as_bytes().to_vec()).unwrap();
         assert!(pos2.is_some());
         assert_eq!(pos2.unwrap().file_id, 11);
         assert_eq!(pos2.unwrap().offset, 22);
@@ -148,7 +173,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res1.is_none());
         let res2 = bt.put(
             "aa".as_bytes().to_vec(),
@@ -157,22 +182,22 @@ mod tests {
                 offset: 22,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res2.is_none());
 
-        let del1 = bt.delete("".as_bytes().to_vec());
+        let del1 = bt.delete("".as_bytes().to_vec()).unwrap();
         assert!(del1.is_some());
         let v1 = del1.unwrap();
         assert_eq!(v1.file_id, 1);
         assert_eq!(v1.offset, 10);
 
-        let del2 = bt.delete("aa".as_bytes().to_vec());
+        let del2 = bt.delete("aa".as_bytes().to_vec()).unwrap();
         assert!(del2.is_some());
         let v2 = del2.unwrap();
         assert_eq!(v2.file_id, 11);
         assert_eq!(v2.offset, 22);
 
-        let del3 = bt.delete("not exist".as_bytes().to_vec());
+        let del3 = bt.delete("not exist".as_bytes().to_vec()).unwrap();
         assert!(del3.is_none());
     }
 
@@ -194,7 +219,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         let mut iter2 = bt.iterator(IteratorOptions::default());
         iter2.seek("aa".as_bytes().to_vec());
         let res2 = iter2.next();
@@ -213,7 +238,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         bt.put(
             "aaed".as_bytes().to_vec(),
             LogRecordPos {
@@ -221,7 +246,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         bt.put(
             "cadd".as_bytes().to_vec(),
             LogRecordPos {
@@ -229,18 +254,18 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
 
         let mut iter4 = bt.iterator(IteratorOptions::default());
         iter4.seek("b".as_bytes().to_vec());
         while let Some(item) = iter4.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
         }
 
         let mut iter5 = bt.iterator(IteratorOptions::default());
         iter5.seek("cadd".as_bytes().to_vec());
         while let Some(item) = iter5.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
             // println!("{:?}", String::from_utf8(item.0.to_vec()));
         }
 
@@ -255,7 +280,7 @@ mod tests {
         let mut iter7 = bt.iterator(iter_opts);
         iter7.seek("bb".as_bytes().to_vec());
         while let Some(item) = iter7.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
         }
     }
 
@@ -273,7 +298,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         let mut iter_opt1 = IteratorOptions::default();
         iter_opt1.reverse = true;
         let mut iter2 = bt.iterator(iter_opt1);
@@ -287,7 +312,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         bt.put(
             "aaed".as_bytes().to_vec(),
             LogRecordPos {
@@ -295,7 +320,7 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
         bt.put(
             "cdea".as_bytes().to_vec(),
             LogRecordPos {
@@ -303,13 +328,13 @@ mod tests {
                 offset: 10,
                 size: 11,
             },
-        );
+        ).unwrap();
 
         let mut iter_opt2 = IteratorOptions::default();
         iter_opt2.reverse = true;
         let mut iter3 = bt.iterator(iter_opt2);
         while let Some(item) = iter3.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
         }
 
         // 有前缀的情况
@@ -317,7 +342,12 @@ mod tests {
         iter_opt3.prefix = "bbed".as_bytes().to_vec();
         let mut iter4 = bt.iterator(iter_opt3);
         while let Some(item) = iter4.next() {
-            assert!(item.0.len() > 0);
+            assert!(!item.0.is_empty());
         }
     }
+
+    #[test]
+    fn test_btree_iterator_range() {
+        check_iterator_range(&BTree::new());
+    }
 }
@@ -1,18 +1,33 @@
-
 use crate::data::log_record_mod::log_record_pos::LogRecordPos;
-use crate::options::iterator_options::IteratorOptions;
+use crate::options::iterator_options::{IterLimit, IteratorOptions};
 use super::index_iterator::IndexIterator;
 
-/// BTree 索引迭代器
+/// 内存索引迭代器，创建时将迭代范围内的数据按照迭代方向拷贝到数组中
 pub struct BTreeIterator {
-    pub items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key+索引
-    pub curr_index: usize,                   // 当前遍历的位置下标
-    pub options: IteratorOptions,            // 配置项
+    items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key+索引
+    curr_index: usize,                   // 当前遍历的位置下标
+    limit: IterLimit,                    // 数量限制
+    options: IteratorOptions,            // 配置项
+}
+
+impl BTreeIterator {
+    pub fn new(mut items: Vec<(Vec<u8>, LogRecordPos)>, options: IteratorOptions) -> Self {
+        if options.reverse {
+            items.reverse();
+        }
+        Self {
+            items,
+            curr_index: 0,
+            limit: IterLimit::new(options.limit),
+            options,
+        }
+    }
 }
 
 impl IndexIterator for BTreeIterator {
     fn rewind(&mut self) {
         self.curr_index = 0;
+        self.limit.reset();
     }
 
     fn seek(&mut self, key: Vec<u8>) {
@@ -23,18 +38,25 @@ impl IndexIterator for BTreeIterator {
                 x.cmp(&key)
             }
         }).unwrap_or_else(|insert_val| insert_val);
+        self.limit.reset();
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
+        if self.curr_index >= self.items.len() || self.limit.reached() {
             return None;
         }
 
         while let Some(item) = self.items.get(self.curr_index) {
             self.curr_index += 1;
             let prefix = &self.options.prefix;
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
-                return Some((&item.0, &item.1));
+            if prefix.is_empty() || item.0.starts_with(prefix) {
+                self.limit.add();
+                // 只需要 key 时不返回位置信息，和磁盘上的索引保持一致
+                let pos = match self.options.keys_only {
+                    true => &LogRecordPos::UNRESOLVED,
+                    false => &item.1,
+                };
+                return Some((&item.0, pos));
             }
         }
         None
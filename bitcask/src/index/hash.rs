@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bytes::Bytes;
+use parking_lot::RwLock;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::errors::AppResult;
+use crate::index::btree_iterator::BTreeIterator;
+use crate::options::iterator_options::IteratorOptions;
+use super::index_iterator::IndexIterator;
+use super::indexer::Indexer;
+
+// 哈希索引的分片数量，降低并发写入时的锁竞争
+const HASH_SHARDS: usize = 32;
+
+/// 哈希索引，按照 key 的哈希值分片存储到多个 HashMap 中
+/// 只适合点查的场景，迭代时需要将所有的 key 拷贝出来并排序
+pub struct HashIndex {
+    shards: Vec<RwLock<HashMap<Vec<u8>, LogRecordPos>>>,
+    hasher: RandomState,   // 计算 key 所在的分片
+    key_size: AtomicUsize, // 所有 key 占用的堆内存大小，在分片的写锁内增量维护
+}
+
+impl Default for HashIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HashIndex {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..HASH_SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+            key_size: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, key: &[u8]) -> &RwLock<HashMap<Vec<u8>, LogRecordPos>> {
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash as usize) % HASH_SHARDS]
+    }
+}
+
+impl Indexer for HashIndex {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>> {
+        let mut write_guard = self.shard(&key).write();
+        // 已经存在的 key 只替换位置信息，保留原来的 key
+        let key_size = key.capacity();
+        let old = write_guard.insert(key, pos);
+        if old.is_none() {
+            self.key_size.fetch_add(key_size, Ordering::Relaxed);
+        }
+        Ok(old)
+    }
+
+    fn get(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let read_guard = self.shard(&key).read();
+        Ok(read_guard.get(&key).copied())
+    }
+
+    fn delete(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let mut write_guard = self.shard(&key).write();
+        let old = write_guard.remove_entry(&key).map(|(k, pos)| {
+            self.key_size.fetch_sub(k.capacity(), Ordering::Relaxed);
+            pos
+        });
+        Ok(old)
+    }
+
+    fn list_keys(&self) -> AppResult<Vec<Bytes>> {
+        let mut keys = Vec::new();
+        for shard in self.shards.iter() {
+            let read_guard = shard.read();
+            keys.reserve(read_guard.len());
+            for k in read_guard.keys() {
+                keys.push(Bytes::copy_from_slice(k));
+            }
+        }
+        // 和有序索引保持一致，按照 key 的顺序返回
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        let mut items = Vec::new();
+        // 将所有分片中的数据存储到数组中，再按照 key 排序
+        for shard in self.shards.iter() {
+            let read_guard = shard.read();
+            items.reserve(read_guard.len());
+            for (key, value) in read_guard.iter() {
+                if options.contains(key) {
+                    items.push((key.clone(), *value));
+                }
+            }
+        }
+        items.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Box::new(BTreeIterator::new(items, options))
+    }
+
+    fn memory_usage(&self) -> usize {
+        // 每个槽位除了 key 和 value 之外还有一个字节的控制位
+        let entry_size = size_of::<Vec<u8>>() + size_of::<LogRecordPos>() + 1;
+        let mut size = self.key_size.load(Ordering::Relaxed);
+        for shard in self.shards.iter() {
+            size += shard.read().capacity() * entry_size;
+        }
+        size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::indexer::tests::check_iterator_range;
+
+    #[test]
+    fn test_hash_index_put_get_delete() {
+        let hi = HashIndex::new();
+        let res1 = hi.put(
+            "".as_bytes().to_vec(),
+            LogRecordPos {
+                file_id: 1,
+                offset: 10,
+                size: 11,
+            },
+        ).unwrap();
+        assert!(res1.is_none());
+        let res2 = hi.put(
+            "aa".as_bytes().to_vec(),
+            LogRecordPos {
+                file_id: 11,
+                offset: 22,
+                size: 11,
+            },
+        ).unwrap();
+        assert!(res2.is_none());
+
+        // 重复写入返回旧的位置
+        let res3 = hi.put(
+            "aa".as_bytes().to_vec(),
+            LogRecordPos {
+                file_id: 1144,
+                offset: 22122,
+                size: 11,
+            },
+        ).unwrap();
+        assert!(res3.is_some());
+        assert_eq!(res3.unwrap().file_id, 11);
+        assert_eq!(res3.unwrap().offset, 22);
+
+        let pos1 = hi.get("".as_bytes().to_vec()).unwrap();
+        assert_eq!(pos1.unwrap().file_id, 1);
+        let pos2 = hi.get("aa".as_bytes().to_vec()).unwrap();
+        assert_eq!(pos2.unwrap().file_id, 1144);
+        assert!(hi.get("not exist".as_bytes().to_vec()).unwrap().is_none());
+
+        let del1 = hi.delete("aa".as_bytes().to_vec()).unwrap();
+        assert_eq!(del1.unwrap().offset, 22122);
+        assert!(hi.get("aa".as_bytes().to_vec()).unwrap().is_none());
+        assert!(hi.delete("aa".as_bytes().to_vec()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_hash_index_iterator() {
+        let hi = HashIndex::new();
+        let mut iter1 = hi.iterator(IteratorOptions::default());
+        assert!(iter1.next().is_none());
+
+        for key in ["cadd", "bbed", "aaed", "cdea", "bbee"] {
+            hi.put(
+                key.as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: 10,
+                    size: 11,
+                },
+            ).unwrap();
+        }
+
+        // 迭代结果按照 key 排序
+        let mut iter2 = hi.iterator(IteratorOptions::default());
+        let mut keys = Vec::new();
+        while let Some(item) = iter2.next() {
+            keys.push(String::from_utf8(item.0.clone()).unwrap());
+        }
+        assert_eq!(keys, vec!["aaed", "bbed", "bbee", "cadd", "cdea"]);
+
+        let list_keys = hi.list_keys().unwrap();
+        assert_eq!(list_keys.len(), 5);
+        assert_eq!(list_keys[0], Bytes::from("aaed"));
+
+        // 反向迭代
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.reverse = true;
+        let mut iter3 = hi.iterator(iter_opts);
+        iter3.seek("c".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &"bbee".as_bytes().to_vec());
+
+        // 有前缀的情况
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.prefix = "bb".as_bytes().to_vec();
+        let mut iter4 = hi.iterator(iter_opts);
+        let mut count = 0;
+        while let Some(item) = iter4.next() {
+            assert!(item.0.starts_with("bb".as_bytes()));
+            count += 1;
+        }
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_hash_iterator_range() {
+        check_iterator_range(&HashIndex::new());
+    }
+}
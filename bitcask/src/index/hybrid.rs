@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use bytes::Bytes;
+use log::error;
+use parking_lot::Mutex;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::errors::{AppErrors, AppResult};
+use crate::options::iterator_options::IteratorOptions;
+use super::bptree::BPlusTree;
+use super::hybrid_iterator::HybridIterator;
+use super::index_iterator::IndexIterator;
+use super::indexer::{IndexOp, Indexer};
+
+const HYBRID_INDEX_FILE_NAME: &str = "hybrid-index";
+// 正常关闭时写入的标识文件，存在时磁盘上的索引是完整的
+const HYBRID_INDEX_CLEAN_FILE_NAME: &str = "hybrid-index-clean";
+
+// 内存中每个 key 除了 key 本身之外额外占用的字节数估算，包括有序表和淘汰队列中的节点、位置信息和访问序号
+const HOT_ENTRY_OVERHEAD: usize = 64;
+
+// 内存中缓存的一个 key
+struct HotEntry {
+    pos: LogRecordPos,
+    dirty: bool, // 位置信息是否比磁盘上的新，淘汰时需要写入磁盘
+    tick: u64,   // 最近一次访问的序号
+}
+
+// 内存中缓存的 key，按照最近访问的顺序淘汰
+#[derive(Default)]
+struct HotKeys {
+    entries: BTreeMap<Vec<u8>, HotEntry>,
+    lru: BTreeMap<u64, Vec<u8>>, // 访问序号 -> key，序号最小的最久没有访问
+    tick: u64,
+    size: usize,  // 估算占用的字节数
+    version: u64, // 每次修改磁盘上的索引时递增
+    clean: bool,  // 正常关闭的标识文件是否存在，之后的第一次修改需要删除
+}
+
+impl HotKeys {
+    fn entry_size(key: &[u8]) -> usize {
+        key.len() * 2 + HOT_ENTRY_OVERHEAD
+    }
+
+    // 查找 key，并更新访问序号
+    fn get(&mut self, key: &[u8]) -> Option<LogRecordPos> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        let key = self.lru.remove(&entry.tick).unwrap();
+        entry.tick = self.tick;
+        self.lru.insert(self.tick, key);
+        Some(entry.pos)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, pos: LogRecordPos, dirty: bool) {
+        self.tick += 1;
+        let entry = HotEntry {
+            pos,
+            dirty,
+            tick: self.tick,
+        };
+        match self.entries.insert(key.clone(), entry) {
+            Some(old) => {
+                self.lru.remove(&old.tick);
+            }
+            None => self.size += Self::entry_size(&key),
+        }
+        self.lru.insert(self.tick, key);
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<HotEntry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.tick);
+        self.size -= Self::entry_size(key);
+        Some(entry)
+    }
+}
+
+/// 混合索引，最近访问的 key 缓存在内存中，内存占用不超过给定的字节数，其余的 key 存储在磁盘上的 B+ 树中
+///
+/// 写入时只更新内存，key 被淘汰时才将新的位置信息批量写入磁盘，频繁更新的 key 不需要每次都写 B+ 树，
+/// 内存中的位置信息比磁盘上的新，迭代时合并两边的数据。删除直接作用于内存和磁盘。
+///
+/// 关闭数据库时将内存中更新过的位置信息写入磁盘，并写入标识文件，下次打开时直接使用磁盘上的索引；
+/// 打开时和写入标识文件之后的第一次修改都会删除标识文件，没有正常关闭时磁盘上的索引不完整，删除之后从数据文件中重建
+pub struct HybridIndex {
+    hot: Mutex<HotKeys>,
+    disk: BPlusTree,
+    memory_size: usize,  // 内存中缓存的 key 最多占用的字节数
+    clean_path: PathBuf, // 正常关闭的标识文件
+    persisted: bool,     // 打开时磁盘上的索引是否完整
+}
+
+impl HybridIndex {
+    pub fn new(dir_path: PathBuf, memory_size: usize) -> AppResult<Self> {
+        let path = dir_path.join(HYBRID_INDEX_FILE_NAME);
+        let clean_path = dir_path.join(HYBRID_INDEX_CLEAN_FILE_NAME);
+        let persisted = clean_path.is_file() && path.is_file();
+        // 删除标识文件之后，再次正常关闭之前崩溃都需要重建索引
+        mark_hybrid_index_stale(&dir_path)?;
+        if !persisted && path.is_file() && let Err(e) = fs::remove_file(&path) {
+            error!("failed to remove hybrid index file: {}", e);
+            return Err(AppErrors::FailedToResetHybridIndex);
+        }
+        Ok(Self {
+            hot: Mutex::new(HotKeys::default()),
+            disk: BPlusTree::open(path)?,
+            memory_size,
+            clean_path,
+            persisted,
+        })
+    }
+
+    // 内存占用超过限制时，淘汰最久没有访问的 key，一直淘汰到限制的 3/4，避免每次写入都淘汰一次
+    // 被修改过的 key 在一个事务中写入磁盘之后再从内存中删除
+    fn evict(&self, hot: &mut HotKeys) -> AppResult<()> {
+        if hot.size <= self.memory_size {
+            return Ok(());
+        }
+        let target = self.memory_size / 4 * 3;
+        let mut size = hot.size;
+        let mut victims = Vec::new();
+        for key in hot.lru.values() {
+            if size <= target {
+                break;
+            }
+            size -= HotKeys::entry_size(key);
+            victims.push(key.clone());
+        }
+
+        let ops: Vec<IndexOp> = victims
+            .iter()
+            .filter_map(|key| {
+                let entry = &hot.entries[key];
+                entry.dirty.then(|| IndexOp::Put(key.clone(), entry.pos))
+            })
+            .collect();
+        if !ops.is_empty() {
+            self.disk.batch_update(ops)?;
+            hot.version += 1;
+        }
+        for key in victims {
+            hot.remove(&key);
+        }
+        Ok(())
+    }
+
+    // 写入标识文件之后索引再被修改，磁盘上的索引不再完整，删除标识文件
+    fn mark_stale(&self, hot: &mut HotKeys) -> AppResult<()> {
+        if hot.clean {
+            mark_hybrid_index_stale(self.clean_path.parent().unwrap())?;
+            hot.clean = false;
+        }
+        Ok(())
+    }
+
+    // 更新内存中的位置信息，返回旧的位置信息，内存中没有时从磁盘上读取
+    fn put_hot(&self, hot: &mut HotKeys, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>> {
+        let old = match hot.get(&key) {
+            Some(old) => Some(old),
+            None => self.disk.get(key.clone())?,
+        };
+        hot.insert(key, pos, true);
+        Ok(old)
+    }
+}
+
+impl Indexer for HybridIndex {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>> {
+        let mut hot = self.hot.lock();
+        self.mark_stale(&mut hot)?;
+        let old = self.put_hot(&mut hot, key, pos)?;
+        self.evict(&mut hot)?;
+        Ok(old)
+    }
+
+    fn get(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let version = {
+            let mut hot = self.hot.lock();
+            if let Some(pos) = hot.get(&key) {
+                return Ok(Some(pos));
+            }
+            hot.version
+        };
+
+        // 读取磁盘时不持有锁，期间磁盘上的索引被修改过时，读取到的可能是旧的数据，不放入内存中
+        let pos = self.disk.get(key.clone())?;
+        if let Some(pos) = pos {
+            let mut hot = self.hot.lock();
+            if hot.version == version && !hot.entries.contains_key(&key) {
+                hot.insert(key, pos, false);
+                self.evict(&mut hot)?;
+            }
+        }
+        Ok(pos)
+    }
+
+    fn delete(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let mut hot = self.hot.lock();
+        self.mark_stale(&mut hot)?;
+        let hot_old = hot.remove(&key).map(|entry| entry.pos);
+        let disk_old = self.disk.delete(key)?;
+        hot.version += 1;
+        Ok(hot_old.or(disk_old))
+    }
+
+    fn batch_update(&self, ops: Vec<IndexOp>) -> AppResult<Vec<Option<LogRecordPos>>> {
+        let mut hot = self.hot.lock();
+        self.mark_stale(&mut hot)?;
+        let mut result = Vec::with_capacity(ops.len());
+        // 删除操作在结果中的下标，以及需要写入磁盘的删除操作
+        let mut deletes = Vec::new();
+        let mut disk_ops = Vec::new();
+        for op in ops {
+            match op {
+                IndexOp::Put(key, pos) => result.push(self.put_hot(&mut hot, key, pos)?),
+                IndexOp::Delete(key) => {
+                    deletes.push(result.len());
+                    result.push(hot.remove(&key).map(|entry| entry.pos));
+                    disk_ops.push(IndexOp::Delete(key));
+                }
+            }
+        }
+
+        // 所有的删除在一个事务中写入磁盘，内存中没有的 key 以磁盘上的位置信息作为旧的位置信息
+        if !disk_ops.is_empty() {
+            let disk_olds = self.disk.batch_update(disk_ops)?;
+            hot.version += 1;
+            for (i, disk_old) in deletes.into_iter().zip(disk_olds) {
+                result[i] = result[i].or(disk_old);
+            }
+        }
+        self.evict(&mut hot)?;
+        Ok(result)
+    }
+
+    fn list_keys(&self) -> AppResult<Vec<Bytes>> {
+        let mut keys = Vec::new();
+        let mut iter = self.iterator(IteratorOptions {
+            keys_only: true,
+            ..Default::default()
+        });
+        while let Some((key, _)) = iter.next() {
+            keys.push(Bytes::copy_from_slice(key));
+        }
+        if let Some(e) = iter.error() {
+            return Err(e);
+        }
+        Ok(keys)
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        // 内存中的 key 数量受内存限制，直接拷贝出来，磁盘上的 key 由 B+ 树迭代器逐批读取
+        let mut items: Vec<(Vec<u8>, LogRecordPos)> = {
+            let hot = self.hot.lock();
+            hot.entries
+                .range(options.start_key()..)
+                .take_while(|(key, _)| !options.is_past(key))
+                .filter(|(key, _)| options.contains(key))
+                .map(|(key, entry)| (key.clone(), entry.pos))
+                .collect()
+        };
+        if options.reverse {
+            items.reverse();
+        }
+        // 磁盘上的数据可能被内存中的数据覆盖，数量限制只在合并之后生效
+        let disk = self.disk.iterator(IteratorOptions {
+            limit: None,
+            ..options.clone()
+        });
+        Box::new(HybridIterator::new(items, disk, options))
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.hot.lock().size
+    }
+
+    fn is_persisted(&self) -> bool {
+        self.persisted
+    }
+
+    fn flush(&self) -> AppResult<()> {
+        let mut hot = self.hot.lock();
+        let ops: Vec<IndexOp> = hot
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(key, entry)| IndexOp::Put(key.clone(), entry.pos))
+            .collect();
+        if !ops.is_empty() {
+            self.disk.batch_update(ops)?;
+            hot.version += 1;
+            hot.entries.values_mut().for_each(|entry| entry.dirty = false);
+        }
+        // 磁盘上的索引已经完整，写入标识文件
+        if let Err(e) = fs::File::create(&self.clean_path).and_then(|file| file.sync_all()) {
+            error!("failed to write hybrid index clean file: {}", e);
+            return Err(AppErrors::FailedToWriteBPTreeIndex);
+        }
+        hot.clean = true;
+        Ok(())
+    }
+}
+
+/// 删除正常关闭的标识文件，下次打开时从数据文件中重建磁盘上的混合索引
+/// merge 之后数据的位置发生了变化，也需要调用
+pub(crate) fn mark_hybrid_index_stale(dir_path: &Path) -> AppResult<()> {
+    let clean_path = dir_path.join(HYBRID_INDEX_CLEAN_FILE_NAME);
+    if clean_path.is_file() && let Err(e) = fs::remove_file(&clean_path) {
+        error!("failed to remove hybrid index clean file: {}", e);
+        return Err(AppErrors::FailedToResetHybridIndex);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::indexer::tests::check_iterator_range;
+
+    fn pos(offset: u64) -> LogRecordPos {
+        LogRecordPos {
+            file_id: 1,
+            offset,
+            size: 11,
+        }
+    }
+
+    #[test]
+    fn test_hybrid_put_get_delete() {
+        let path = PathBuf::from("/tmp/hybrid-put-get-delete");
+        fs::create_dir_all(path.clone()).unwrap();
+        // 内存只能放下很少的 key，写入的大部分 key 会被淘汰到磁盘上
+        let memory_size = 16 * (HOT_ENTRY_OVERHEAD + 32);
+        let index = HybridIndex::new(path.clone(), memory_size).unwrap();
+
+        let n = 500u64;
+        for i in 0..n {
+            let res = index.put(format!("key-{:05}", i).into_bytes(), pos(i)).unwrap();
+            assert!(res.is_none());
+            assert!(index.memory_usage() <= memory_size);
+        }
+        for i in 0..n {
+            let res = index.get(format!("key-{:05}", i).into_bytes()).unwrap();
+            assert_eq!(res.unwrap().offset, i);
+            assert!(index.memory_usage() <= memory_size);
+        }
+
+        // 更新已经淘汰到磁盘上的 key，返回磁盘上的旧位置信息
+        let res = index.put("key-00001".as_bytes().to_vec(), pos(1001)).unwrap();
+        assert_eq!(res.unwrap().offset, 1);
+        assert_eq!(index.get("key-00001".as_bytes().to_vec()).unwrap().unwrap().offset, 1001);
+
+        let res = index.delete("key-00001".as_bytes().to_vec()).unwrap();
+        assert_eq!(res.unwrap().offset, 1001);
+        assert!(index.get("key-00001".as_bytes().to_vec()).unwrap().is_none());
+        let res = index.delete("key-00002".as_bytes().to_vec()).unwrap();
+        assert_eq!(res.unwrap().offset, 2);
+        assert!(index.get("key-00002".as_bytes().to_vec()).unwrap().is_none());
+        assert!(index.delete("not-exist".as_bytes().to_vec()).unwrap().is_none());
+        assert_eq!(index.list_keys().unwrap().len(), n as usize - 2);
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_hybrid_batch_update() {
+        let path = PathBuf::from("/tmp/hybrid-batch-update");
+        fs::create_dir_all(path.clone()).unwrap();
+        let index = HybridIndex::new(path.clone(), 0).unwrap();
+        index.put("aa".as_bytes().to_vec(), pos(10)).unwrap();
+
+        let res = index.batch_update(vec![
+            IndexOp::Put("aa".as_bytes().to_vec(), pos(20)),
+            IndexOp::Put("bb".as_bytes().to_vec(), pos(30)),
+            IndexOp::Delete("bb".as_bytes().to_vec()),
+            IndexOp::Delete("cc".as_bytes().to_vec()),
+            IndexOp::Put("dd".as_bytes().to_vec(), pos(40)),
+        ]).unwrap();
+        let offsets: Vec<Option<u64>> = res.iter().map(|old| old.map(|p| p.offset)).collect();
+        assert_eq!(offsets, vec![Some(10), None, Some(30), None, None]);
+
+        assert_eq!(index.get("aa".as_bytes().to_vec()).unwrap().unwrap().offset, 20);
+        assert!(index.get("bb".as_bytes().to_vec()).unwrap().is_none());
+        assert_eq!(index.get("dd".as_bytes().to_vec()).unwrap().unwrap().offset, 40);
+        assert_eq!(index.list_keys().unwrap().len(), 2);
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_hybrid_flush_reopen() {
+        let path = PathBuf::from("/tmp/hybrid-flush-reopen");
+        fs::create_dir_all(path.clone()).unwrap();
+        let memory_size = 16 * (HOT_ENTRY_OVERHEAD + 32);
+        let index = HybridIndex::new(path.clone(), memory_size).unwrap();
+        assert!(!index.is_persisted());
+        for i in 0..100u64 {
+            index.put(format!("key-{:05}", i).into_bytes(), pos(i)).unwrap();
+        }
+        // 内存中的 key 写入磁盘之后，重新打开可以直接使用磁盘上的索引
+        index.flush().unwrap();
+        drop(index);
+
+        let index = HybridIndex::new(path.clone(), memory_size).unwrap();
+        assert!(index.is_persisted());
+        assert_eq!(index.list_keys().unwrap().len(), 100);
+        for i in 0..100u64 {
+            let res = index.get(format!("key-{:05}", i).into_bytes()).unwrap();
+            assert_eq!(res.unwrap().offset, i);
+        }
+        index.flush().unwrap();
+
+        // 写入标识文件之后又被修改，没有再次写入磁盘时标识文件已经被删除
+        index.put("key-00001".as_bytes().to_vec(), pos(1001)).unwrap();
+        assert!(!path.join(HYBRID_INDEX_CLEAN_FILE_NAME).is_file());
+        drop(index);
+
+        // 没有正常关闭时磁盘上的索引不完整，重新打开之后需要重建
+        let index = HybridIndex::new(path.clone(), memory_size).unwrap();
+        assert!(!index.is_persisted());
+        assert!(index.list_keys().unwrap().is_empty());
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_hybrid_iterator() {
+        let path = PathBuf::from("/tmp/hybrid-iter");
+        fs::create_dir_all(path.clone()).unwrap();
+        let memory_size = 64 * (HOT_ENTRY_OVERHEAD + 32);
+        let index = HybridIndex::new(path.clone(), memory_size).unwrap();
+        let mut iter1 = index.iterator(IteratorOptions::default());
+        assert!(iter1.next().is_none());
+
+        let n = 2000u64;
+        for i in 0..n {
+            index.put(format!("key-{:05}", i).into_bytes(), pos(i)).unwrap();
+        }
+        // 更新一部分 key，新的位置信息只在内存中，磁盘上是旧的位置信息
+        for i in (0..n).step_by(100) {
+            index.put(format!("key-{:05}", i).into_bytes(), pos(i + n)).unwrap();
+        }
+
+        let offset = |i: u64| if i.is_multiple_of(100) { i + n } else { i };
+        let mut iter2 = index.iterator(IteratorOptions::default());
+        let mut expected = 0u64;
+        while let Some((key, pos)) = iter2.next() {
+            assert_eq!(key, &format!("key-{:05}", expected).into_bytes());
+            assert_eq!(pos.offset, offset(expected));
+            expected += 1;
+        }
+        assert_eq!(expected, n);
+        iter2.seek("key-01000".as_bytes().to_vec());
+        assert_eq!(iter2.next().unwrap().1.offset, offset(1000));
+        iter2.rewind();
+        assert_eq!(iter2.next().unwrap().0, &"key-00000".as_bytes().to_vec());
+
+        // 反向迭代
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.reverse = true;
+        let mut iter3 = index.iterator(iter_opts);
+        let mut expected = n;
+        while let Some((key, pos)) = iter3.next() {
+            expected -= 1;
+            assert_eq!(key, &format!("key-{:05}", expected).into_bytes());
+            assert_eq!(pos.offset, offset(expected));
+        }
+        assert_eq!(expected, 0);
+        iter3.seek("key-01500a".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &"key-01500".as_bytes().to_vec());
+
+        // 有前缀的情况
+        for reverse in [false, true] {
+            let mut iter_opts = IteratorOptions::default();
+            iter_opts.prefix = "key-01".as_bytes().to_vec();
+            iter_opts.reverse = reverse;
+            let mut iter4 = index.iterator(iter_opts);
+            let mut count = 0;
+            while let Some(item) = iter4.next() {
+                assert!(item.0.starts_with("key-01".as_bytes()));
+                count += 1;
+            }
+            assert_eq!(count, 1000);
+        }
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_hybrid_iterator_range() {
+        let path = PathBuf::from("/tmp/hybrid-iter-range");
+        fs::create_dir_all(path.clone()).unwrap();
+        // 一部分 key 在内存中，一部分 key 被淘汰到磁盘上
+        check_iterator_range(&HybridIndex::new(path.clone(), 32 * (HOT_ENTRY_OVERHEAD + 16)).unwrap());
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use std::cmp::Ordering;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::errors::AppErrors;
+use crate::options::iterator_options::{IterLimit, IteratorOptions};
+use super::index_iterator::IndexIterator;
+
+/// 混合索引迭代器，按顺序合并内存中的数据和磁盘上的数据，相同的 key 以内存中的位置信息为准
+pub struct HybridIterator {
+    items: Vec<(Vec<u8>, LogRecordPos)>,          // 内存中的 key+索引
+    curr_index: usize,                            // 内存中的数据遍历的位置下标
+    limit: IterLimit,                             // 数量限制
+    disk: Box<dyn IndexIterator>,                 // 磁盘上的数据的迭代器
+    disk_head: Option<(Vec<u8>, LogRecordPos)>,   // 磁盘上下一个待返回的数据
+    current: Option<(Vec<u8>, LogRecordPos)>,     // 最近一次从磁盘上返回的数据
+    options: IteratorOptions,                     // 配置项
+}
+
+impl HybridIterator {
+    pub fn new(
+        items: Vec<(Vec<u8>, LogRecordPos)>,
+        disk: Box<dyn IndexIterator>,
+        options: IteratorOptions,
+    ) -> Self {
+        let mut iter = Self {
+            items,
+            curr_index: 0,
+            limit: IterLimit::new(options.limit),
+            disk,
+            disk_head: None,
+            current: None,
+            options,
+        };
+        iter.advance_disk();
+        iter
+    }
+
+    fn advance_disk(&mut self) {
+        self.disk_head = self.disk.next().map(|(key, pos)| (key.clone(), *pos));
+    }
+}
+
+impl IndexIterator for HybridIterator {
+    fn rewind(&mut self) {
+        self.curr_index = 0;
+        self.limit.reset();
+        self.disk.rewind();
+        self.advance_disk();
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        self.curr_index = self.items.binary_search_by(|(x, _)| {
+            if self.options.reverse {
+                x.cmp(&key).reverse()
+            } else {
+                x.cmp(&key)
+            }
+        }).unwrap_or_else(|insert_val| insert_val);
+        self.limit.reset();
+        self.disk.seek(key);
+        self.advance_disk();
+    }
+
+    fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+        if self.limit.reached() {
+            return None;
+        }
+        // 比较两边下一个 key 的顺序，内存中的 key 在前或者相同时返回内存中的数据
+        let ord = match (self.items.get(self.curr_index), self.disk_head.as_ref()) {
+            (None, None) => return None,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((mem_key, _)), Some((disk_key, _))) => match self.options.reverse {
+                true => disk_key.cmp(mem_key),
+                false => mem_key.cmp(disk_key),
+            },
+        };
+
+        self.limit.add();
+        if ord == Ordering::Greater {
+            self.current = self.disk_head.take();
+            self.advance_disk();
+            return self.current.as_ref().map(|(key, pos)| (key, pos));
+        }
+        // 磁盘上相同的 key 是淘汰之前写入的旧数据，直接跳过
+        if ord == Ordering::Equal {
+            self.advance_disk();
+        }
+        self.curr_index += 1;
+        let item = &self.items[self.curr_index - 1];
+        // 只需要 key 时不返回位置信息，和磁盘上的数据保持一致
+        let pos = match self.options.keys_only {
+            true => &LogRecordPos::UNRESOLVED,
+            false => &item.1,
+        };
+        Some((&item.0, pos))
+    }
+
+    fn error(&self) -> Option<AppErrors> {
+        self.disk.error()
+    }
+}
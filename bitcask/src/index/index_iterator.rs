@@ -1,4 +1,5 @@
 use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::errors::AppErrors;
 
 /// 抽象索引迭代器
 pub trait IndexIterator: Sync + Send {
@@ -8,6 +9,12 @@ pub trait IndexIterator: Sync + Send {
     /// Seek 根据传入的 key 查找到第一个大于（或小于）等于的目标 key，根据从这个 key 开始遍历
     fn seek(&mut self, key: Vec<u8>);
 
-    /// Next 跳转到下一个 key，返回 None 则说明迭代完毕
+    /// Next 跳转到下一个 key，返回 None 则说明迭代完毕或者读取失败
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
+
+    /// Error 返回迭代过程中读取索引失败的错误，next 返回 None 之后需要检查
+    /// 内存中的索引不会读取失败，默认返回 None
+    fn error(&self) -> Option<AppErrors> {
+        None
+    }
 }
\ No newline at end of file
@@ -4,20 +4,124 @@ use crate::options::iterator_options::IteratorOptions;
 use crate::data::log_record_mod::log_record_pos::LogRecordPos;
 use super::index_iterator::IndexIterator;
 
+/// 批量更新索引时的一条操作
+pub enum IndexOp {
+    Put(Vec<u8>, LogRecordPos), // 存储 key 对应的数据位置信息
+    Delete(Vec<u8>),            // 删除 key 对应的数据位置信息
+}
+
 /// Indexer 抽象索引接口，后续如果想要接入其他的数据结构，则直接实现这个接口即可
 pub trait Indexer: Sync + Send {
     /// 向索引中存储 key 对应的数据位置信息
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos>;
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>>;
 
     /// 根据 key 取出对应的索引位置信息
-    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos>;
+    fn get(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>>;
 
     /// 根据 key 删除对应的索引位置信息
-    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos>;
+    fn delete(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>>;
+
+    /// 批量更新索引，返回每个操作之前 key 对应的旧的位置信息
+    /// 默认依次执行每个操作，存储在磁盘上的索引可以在一个事务中完成所有的操作
+    fn batch_update(&self, ops: Vec<IndexOp>) -> AppResult<Vec<Option<LogRecordPos>>> {
+        ops.into_iter()
+            .map(|op| match op {
+                IndexOp::Put(key, pos) => self.put(key, pos),
+                IndexOp::Delete(key) => self.delete(key),
+            })
+            .collect()
+    }
 
     /// 获取索引存储的所有的 key
     fn list_keys(&self) -> AppResult<Vec<Bytes>>;
 
-    /// 返回索引迭代器
+    /// 返回索引迭代器，磁盘上的索引在迭代过程中读取失败时结束迭代，通过迭代器的 error 获取错误
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
-}
\ No newline at end of file
+
+    /// 估算索引占用的内存大小，单位为字节
+    fn memory_usage(&self) -> usize;
+
+    /// 磁盘上是否保存了完整的索引，是的话打开数据库时不需要从数据文件中加载索引
+    fn is_persisted(&self) -> bool {
+        false
+    }
+
+    /// 将只在内存中更新过的索引写入磁盘，关闭数据库时调用
+    fn flush(&self) -> AppResult<()> {
+        Ok(())
+    }
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::ops::Bound;
+    use super::*;
+
+    /// 写入 key-000 到 key-099，校验各种索引的迭代器对范围、方向、数量限制和 keys_only 的处理
+    pub(crate) fn check_iterator_range(index: &dyn Indexer) {
+        for i in 0..100u64 {
+            let pos = LogRecordPos {
+                file_id: 1,
+                offset: i,
+                size: 11,
+            };
+            index.put(format!("key-{:03}", i).into_bytes(), pos).unwrap();
+        }
+        let collect = |iter: &mut Box<dyn IndexIterator>| {
+            let mut keys = Vec::new();
+            while let Some((key, _)) = iter.next() {
+                keys.push(String::from_utf8(key.clone()).unwrap());
+            }
+            assert!(iter.error().is_none());
+            keys
+        };
+
+        // 包含下界，不包含上界
+        let mut iter1 = index.iterator(IteratorOptions {
+            lower_bound: Bound::Included("key-010".as_bytes().to_vec()),
+            upper_bound: Bound::Excluded("key-015".as_bytes().to_vec()),
+            ..Default::default()
+        });
+        assert_eq!(collect(&mut iter1), vec!["key-010", "key-011", "key-012", "key-013", "key-014"]);
+        iter1.rewind();
+        assert_eq!(iter1.next().unwrap().1.offset, 10);
+
+        // 不包含下界，包含上界，反向迭代
+        let mut iter2 = index.iterator(IteratorOptions {
+            reverse: true,
+            lower_bound: Bound::Excluded("key-010".as_bytes().to_vec()),
+            upper_bound: Bound::Included("key-013".as_bytes().to_vec()),
+            ..Default::default()
+        });
+        assert_eq!(collect(&mut iter2), vec!["key-013", "key-012", "key-011"]);
+        iter2.seek("key-012".as_bytes().to_vec());
+        assert_eq!(collect(&mut iter2), vec!["key-012", "key-011"]);
+
+        // 数量限制，rewind 和 seek 之后重新计数
+        let mut iter3 = index.iterator(IteratorOptions {
+            prefix: "key-05".as_bytes().to_vec(),
+            lower_bound: Bound::Included("key-000".as_bytes().to_vec()),
+            limit: Some(3),
+            keys_only: true,
+            ..Default::default()
+        });
+        assert_eq!(collect(&mut iter3), vec!["key-050", "key-051", "key-052"]);
+        iter3.seek("key-058".as_bytes().to_vec());
+        assert_eq!(collect(&mut iter3), vec!["key-058", "key-059"]);
+        iter3.rewind();
+        assert_eq!(collect(&mut iter3).len(), 3);
+
+        // 只需要 key 时返回的位置信息不能用来读取数据
+        iter3.rewind();
+        while let Some((_, pos)) = iter3.next() {
+            assert!(pos.is_unresolved());
+        }
+
+        // 空的范围
+        let mut iter4 = index.iterator(IteratorOptions {
+            lower_bound: Bound::Included("key-050".as_bytes().to_vec()),
+            upper_bound: Bound::Excluded("key-050".as_bytes().to_vec()),
+            ..Default::default()
+        });
+        assert!(iter4.next().is_none());
+    }
+}
@@ -1,8 +1,31 @@
 pub mod indexer;
 pub mod index_iterator;
+pub mod art;
 pub mod bptree;
 pub mod bptree_iterator;
 pub mod btree;
 pub mod btree_iterator;
+pub mod hash;
+pub mod hybrid;
+pub mod hybrid_iterator;
+pub mod sharded_btree;
 pub mod skiplist;
-pub mod skip_iterator;
\ No newline at end of file
+
+use crate::errors::AppResult;
+use crate::options::index_type::IndexType;
+use crate::options::options::Options;
+use self::indexer::Indexer;
+
+/// 根据索引类型创建索引
+pub fn new_indexer(options: &Options) -> AppResult<Box<dyn Indexer>> {
+    let indexer: Box<dyn Indexer> = match options.index_type {
+        IndexType::BTree => Box::new(btree::BTree::new()),
+        IndexType::SkipList => Box::new(skiplist::SkipList::new()),
+        IndexType::BPlusTree => Box::new(bptree::BPlusTree::new(options.dir_path.clone())?),
+        IndexType::Hash => Box::new(hash::HashIndex::new()),
+        IndexType::ART => Box::new(art::Art::new()),
+        IndexType::ShardedBTree => Box::new(sharded_btree::ShardedBTree::new()),
+        IndexType::Hybrid => Box::new(hybrid::HybridIndex::new(options.dir_path.clone(), options.hybrid_index_memory_size)?),
+    };
+    Ok(indexer)
+}
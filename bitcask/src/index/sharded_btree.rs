@@ -0,0 +1,234 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::hash::{BuildHasher, RandomState};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bytes::Bytes;
+use parking_lot::RwLock;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::errors::AppResult;
+use crate::index::btree_iterator::BTreeIterator;
+use crate::options::iterator_options::IteratorOptions;
+use super::index_iterator::IndexIterator;
+use super::indexer::Indexer;
+
+// 分片的数量，不同分片上的读写互不阻塞
+const BTREE_SHARDS: usize = 32;
+
+/// 分片的 BTree 索引，按照 key 的哈希值将数据分散到多个 BTreeMap 中，
+/// 每个分片有独立的读写锁，迭代时将各个分片的有序数据归并为整体有序
+pub struct ShardedBTree {
+    shards: Vec<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
+    hasher: RandomState,   // 计算 key 所在的分片
+    key_size: AtomicUsize, // 所有 key 占用的堆内存大小，在分片的写锁内增量维护
+}
+
+impl Default for ShardedBTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShardedBTree {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..BTREE_SHARDS).map(|_| RwLock::new(BTreeMap::new())).collect(),
+            hasher: RandomState::new(),
+            key_size: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, key: &[u8]) -> &RwLock<BTreeMap<Vec<u8>, LogRecordPos>> {
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash as usize) % BTREE_SHARDS]
+    }
+
+    // 取出每个分片中迭代范围内的数据，并归并为有序的数组
+    fn merge_shards(&self, options: &IteratorOptions) -> Vec<(Vec<u8>, LogRecordPos)> {
+        let mut runs: Vec<Vec<(Vec<u8>, LogRecordPos)>> = Vec::with_capacity(BTREE_SHARDS);
+        for shard in self.shards.iter() {
+            let read_guard = shard.read();
+            let run: Vec<_> = read_guard
+                .range(options.start_key()..)
+                .take_while(|(k, _)| !options.is_past(k))
+                .filter(|(k, _)| options.contains(k))
+                .map(|(k, v)| (k.clone(), *v))
+                .collect();
+            runs.push(run);
+        }
+
+        // 多路归并，同一个 key 只会存在于一个分片中
+        let total = runs.iter().map(|run| run.len()).sum();
+        let mut items = Vec::with_capacity(total);
+        let mut iters: Vec<_> = runs.into_iter().map(|run| run.into_iter()).collect();
+        // 堆中存放每个分片当前最小的 key，对应的位置信息存放在 heads 中
+        let mut heads = vec![None; iters.len()];
+        let mut heap = BinaryHeap::with_capacity(iters.len());
+        for (i, iter) in iters.iter_mut().enumerate() {
+            if let Some((k, v)) = iter.next() {
+                heads[i] = Some(v);
+                heap.push(Reverse((k, i)));
+            }
+        }
+        while let Some(Reverse((key, i))) = heap.pop() {
+            items.push((key, heads[i].take().unwrap()));
+            if let Some((k, v)) = iters[i].next() {
+                heads[i] = Some(v);
+                heap.push(Reverse((k, i)));
+            }
+        }
+        items
+    }
+}
+
+impl Indexer for ShardedBTree {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>> {
+        let mut write_guard = self.shard(&key).write();
+        // 已经存在的 key 只替换位置信息，保留原来的 key
+        let key_size = key.capacity();
+        let old = write_guard.insert(key, pos);
+        if old.is_none() {
+            self.key_size.fetch_add(key_size, Ordering::Relaxed);
+        }
+        Ok(old)
+    }
+
+    fn get(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let read_guard = self.shard(&key).read();
+        Ok(read_guard.get(&key).copied())
+    }
+
+    fn delete(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
+        let mut write_guard = self.shard(&key).write();
+        let old = write_guard.remove_entry(&key).map(|(k, pos)| {
+            self.key_size.fetch_sub(k.capacity(), Ordering::Relaxed);
+            pos
+        });
+        Ok(old)
+    }
+
+    fn list_keys(&self) -> AppResult<Vec<Bytes>> {
+        let keys = self
+            .merge_shards(&IteratorOptions::default())
+            .into_iter()
+            .map(|(k, _)| Bytes::from(k))
+            .collect();
+        Ok(keys)
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        let items = self.merge_shards(&options);
+        Box::new(BTreeIterator::new(items, options))
+    }
+
+    fn memory_usage(&self) -> usize {
+        // BTreeMap 的节点平均填充率按照 2/3 估算
+        let entry_size = size_of::<Vec<u8>>() + size_of::<LogRecordPos>();
+        let len: usize = self.shards.iter().map(|shard| shard.read().len()).sum();
+        len * entry_size * 3 / 2 + self.key_size.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use super::*;
+    use crate::index::indexer::tests::check_iterator_range;
+
+    fn test_pos(offset: u64) -> LogRecordPos {
+        LogRecordPos {
+            file_id: 1,
+            offset,
+            size: 11,
+        }
+    }
+
+    #[test]
+    fn test_sharded_btree_put_get_delete() {
+        let sbt = ShardedBTree::new();
+        assert!(sbt.put("".as_bytes().to_vec(), test_pos(10)).unwrap().is_none());
+        assert!(sbt.put("aa".as_bytes().to_vec(), test_pos(22)).unwrap().is_none());
+
+        let res = sbt.put("aa".as_bytes().to_vec(), test_pos(22122)).unwrap();
+        assert_eq!(res.unwrap().offset, 22);
+
+        assert_eq!(sbt.get("".as_bytes().to_vec()).unwrap().unwrap().offset, 10);
+        assert_eq!(sbt.get("aa".as_bytes().to_vec()).unwrap().unwrap().offset, 22122);
+        assert!(sbt.get("not exist".as_bytes().to_vec()).unwrap().is_none());
+
+        let del = sbt.delete("aa".as_bytes().to_vec()).unwrap();
+        assert_eq!(del.unwrap().offset, 22122);
+        assert!(sbt.get("aa".as_bytes().to_vec()).unwrap().is_none());
+        assert!(sbt.delete("aa".as_bytes().to_vec()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sharded_btree_iterator() {
+        let sbt = ShardedBTree::new();
+        let mut iter1 = sbt.iterator(IteratorOptions::default());
+        assert!(iter1.next().is_none());
+
+        for i in 0..1000u64 {
+            sbt.put(format!("key-{:04}", i).into_bytes(), test_pos(i)).unwrap();
+        }
+
+        // 各个分片的数据归并之后整体有序
+        let mut iter2 = sbt.iterator(IteratorOptions::default());
+        let mut expected = 0u64;
+        while let Some((key, pos)) = iter2.next() {
+            assert_eq!(key, &format!("key-{:04}", expected).into_bytes());
+            assert_eq!(pos.offset, expected);
+            expected += 1;
+        }
+        assert_eq!(expected, 1000);
+
+        let keys = sbt.list_keys().unwrap();
+        assert_eq!(keys.len(), 1000);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        // 反向迭代
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.reverse = true;
+        let mut iter3 = sbt.iterator(iter_opts);
+        iter3.seek("key-0500".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &"key-0500".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &"key-0499".as_bytes().to_vec());
+
+        // 有前缀的情况
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.prefix = "key-01".as_bytes().to_vec();
+        let mut iter4 = sbt.iterator(iter_opts);
+        let mut count = 0;
+        while let Some(item) = iter4.next() {
+            assert!(item.0.starts_with("key-01".as_bytes()));
+            count += 1;
+        }
+        assert_eq!(count, 100);
+    }
+
+    #[test]
+    fn test_sharded_btree_concurrent_put() {
+        let sbt = Arc::new(ShardedBTree::new());
+        let mut handles = vec![];
+        for t in 0..8u64 {
+            let sbt = sbt.clone();
+            handles.push(thread::spawn(move || {
+                for i in 0..1000u64 {
+                    let key = format!("key-{}-{:04}", t, i).into_bytes();
+                    assert!(sbt.put(key.clone(), test_pos(i)).unwrap().is_none());
+                    assert_eq!(sbt.get(key).unwrap().unwrap().offset, i);
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(sbt.list_keys().unwrap().len(), 8000);
+    }
+
+    #[test]
+    fn test_sharded_btree_iterator_range() {
+        check_iterator_range(&ShardedBTree::new());
+    }
+}
@@ -1,9 +1,11 @@
 use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
+use std::mem::size_of;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use super::index_iterator::IndexIterator;
 use super::indexer::Indexer;
-use super::skip_iterator::SkipListIterator;
+use super::btree_iterator::BTreeIterator;
 use crate::data::log_record_mod::log_record_pos::LogRecordPos;
 use crate::options::iterator_options::IteratorOptions;
 use crate::errors::AppResult;
@@ -11,38 +13,52 @@ use crate::errors::AppResult;
 // 跳表索引
 pub struct SkipList {
     skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
+    // 所有 key 占用的堆内存大小，在写入和删除时增量维护
+    // 跳表没有全局的写锁，并发写入同一个 key 时可能有少量偏差，只用于估算
+    key_size: AtomicUsize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SkipList {
     pub fn new() -> Self {
         Self {
             skl: Arc::new(SkipMap::new()),
+            key_size: AtomicUsize::new(0),
         }
     }
 }
 
 impl Indexer for SkipList {
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<Option<LogRecordPos>> {
         let mut result = None;
+        // 跳表会用新的 key 替换已经存在的节点
+        self.key_size.fetch_add(key.capacity(), Ordering::Relaxed);
         if let Some(entry) = self.skl.get(&key) {
             result = Some(*entry.value());
+            self.key_size.fetch_sub(entry.key().capacity(), Ordering::Relaxed);
         }
         self.skl.insert(key, pos);
-        result
+        Ok(result)
     }
 
-    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    fn get(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
         if let Some(entry) = self.skl.get(&key) {
-            return Some(*entry.value());
+            return Ok(Some(*entry.value()));
         }
-        None
+        Ok(None)
     }
 
-    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+    fn delete(&self, key: Vec<u8>) -> AppResult<Option<LogRecordPos>> {
         if let Some(entry) = self.skl.remove(&key) {
-            return Some(*entry.value());
+            self.key_size.fetch_sub(entry.key().capacity(), Ordering::Relaxed);
+            return Ok(Some(*entry.value()));
         }
-        None
+        Ok(None)
     }
 
     fn list_keys(&self) -> AppResult<Vec<Bytes>> {
@@ -54,25 +70,30 @@ impl Indexer for SkipList {
     }
 
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-        let mut items = Vec::with_capacity(self.skl.len());
-        // 将 SkipList 中的数据存储到数组中
-        for entry in self.skl.iter() {
-            items.push((entry.key().clone(), *entry.value()));
+        let mut items = Vec::new();
+        // 将 SkipList 中迭代范围内的数据存储到数组中，越过上界或者前缀之后停止
+        for entry in self.skl.range(options.start_key()..) {
+            if options.is_past(entry.key()) {
+                break;
+            }
+            if options.contains(entry.key()) {
+                items.push((entry.key().clone(), *entry.value()));
+            }
         }
-        if options.reverse {
-            items.reverse();
-        }
-        Box::new(SkipListIterator {
-            items,
-            curr_index: 0usize,
-            options,
-        })
+        Box::new(BTreeIterator::new(items, options))
+    }
+
+    fn memory_usage(&self) -> usize {
+        // 每个跳表节点除了 key 和 value 之外，还有引用计数、高度和平均两层的指针
+        let entry_size = size_of::<Vec<u8>>() + size_of::<LogRecordPos>() + 32;
+        self.skl.len() * entry_size + self.key_size.load(Ordering::Relaxed)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::index::indexer::tests::check_iterator_range;
 
     #[test]
     fn test_skl_put() {
@@ -84,7 +105,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res1.is_none());
         let res2 = skl.put(
             "acdd".as_bytes().to_vec(),
@@ -93,7 +114,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res2.is_none());
         let res3 = skl.put(
             "bbae".as_bytes().to_vec(),
@@ -102,7 +123,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res3.is_none());
         let res4 = skl.put(
             "ddee".as_bytes().to_vec(),
@@ -111,7 +132,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res4.is_none());
 
         let res5 = skl.put(
@@ -121,7 +142,7 @@ mod tests {
                 offset: 22,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res5.is_some());
         let v = res5.unwrap();
         assert_eq!(v.file_id, 1123);
@@ -132,7 +153,7 @@ mod tests {
     fn test_skl_get() {
         let skl = SkipList::new();
 
-        let v1 = skl.get(b"not exists".to_vec());
+        let v1 = skl.get(b"not exists".to_vec()).unwrap();
         assert!(v1.is_none());
 
         let res1 = skl.put(
@@ -142,9 +163,9 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res1.is_none());
-        let v2 = skl.get(b"aacd".to_vec());
+        let v2 = skl.get(b"aacd".to_vec()).unwrap();
         assert!(v2.is_some());
 
         let res2 = skl.put(
@@ -154,9 +175,9 @@ mod tests {
                 offset: 990,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res2.is_some());
-        let v3 = skl.get(b"aacd".to_vec());
+        let v3 = skl.get(b"aacd".to_vec()).unwrap();
         assert!(v3.is_some());
     }
 
@@ -164,7 +185,7 @@ mod tests {
     fn test_skl_delete() {
         let skl = SkipList::new();
 
-        let r1 = skl.delete(b"not exists".to_vec());
+        let r1 = skl.delete(b"not exists".to_vec()).unwrap();
         assert!(r1.is_none());
 
         let res1 = skl.put(
@@ -174,16 +195,16 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res1.is_none());
 
-        let r2 = skl.delete(b"aacd".to_vec());
+        let r2 = skl.delete(b"aacd".to_vec()).unwrap();
         assert!(r2.is_some());
         let v = r2.unwrap();
         assert_eq!(v.file_id, 1123);
         assert_eq!(v.offset, 1232);
 
-        let v2 = skl.get(b"aacd".to_vec());
+        let v2 = skl.get(b"aacd".to_vec()).unwrap();
         assert!(v2.is_none());
     }
 
@@ -201,7 +222,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res1.is_none());
         let res2 = skl.put(
             "acdd".as_bytes().to_vec(),
@@ -210,7 +231,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res2.is_none());
         let res3 = skl.put(
             "bbae".as_bytes().to_vec(),
@@ -219,7 +240,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res3.is_none());
         let res4 = skl.put(
             "ddee".as_bytes().to_vec(),
@@ -228,7 +249,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res4.is_none());
 
         let keys2 = skl.list_keys();
@@ -246,7 +267,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res1.is_none());
         let res2 = skl.put(
             "acdd".as_bytes().to_vec(),
@@ -255,7 +276,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res2.is_none());
         let res3 = skl.put(
             "bbae".as_bytes().to_vec(),
@@ -264,7 +285,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res3.is_none());
         let res4 = skl.put(
             "ddee".as_bytes().to_vec(),
@@ -273,7 +294,7 @@ mod tests {
                 offset: 1232,
                 size: 11,
             },
-        );
+        ).unwrap();
         assert!(res4.is_none());
 
         let mut opts = IteratorOptions::default();
@@ -284,4 +305,9 @@ mod tests {
             assert!(!key.is_empty());
         }
     }
+
+    #[test]
+    fn test_skl_iterator_range() {
+        check_iterator_range(&SkipList::new());
+    }
 }
@@ -1,3 +1,6 @@
+// 配置项统一先构造默认值再逐项修改
+#![allow(clippy::field_reassign_with_default)]
+
 pub mod errors;
 pub mod options;
 pub mod data;
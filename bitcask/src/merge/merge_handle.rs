@@ -0,0 +1,175 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::db::engine::Engine;
+use crate::errors::AppResult;
+
+/// merge 进度快照
+#[derive(Debug, Clone, Default)]
+pub struct MergeProgress {
+    // 需要 merge 的数据文件数量
+    pub total_files: usize,
+    // 已经处理完成的数据文件数量
+    pub files_processed: usize,
+    // 需要 merge 的数据总量
+    pub total_bytes: u64,
+    // 已经读取的数据量
+    pub bytes_read: u64,
+    // 重写到 merge 目录中的数据量（含 hint 文件）
+    pub bytes_written: u64,
+    // 保留的有效记录数量
+    pub records_kept: u64,
+    // 丢弃的无效记录数量
+    pub records_dropped: u64,
+    // merge 已经执行的时间
+    pub elapsed: Duration,
+}
+
+impl MergeProgress {
+    /// 根据已读取数据量的速度估算剩余时间，尚未开始读取时返回 None
+    pub fn eta(&self) -> Option<Duration> {
+        if self.bytes_read == 0 {
+            return None;
+        }
+        let remaining = self.total_bytes.saturating_sub(self.bytes_read);
+        let secs = self.elapsed.as_secs_f64() * remaining as f64 / self.bytes_read as f64;
+        Some(Duration::from_secs_f64(secs))
+    }
+}
+
+/// merge 任务的共享状态，由 merge 线程更新，由 MergeHandle 读取
+pub(crate) struct MergeState {
+    cancelled: AtomicBool,
+    started_at: Instant,
+    total_files: AtomicUsize,
+    files_processed: AtomicUsize,
+    total_bytes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    records_kept: AtomicU64,
+    records_dropped: AtomicU64,
+}
+
+impl MergeState {
+    pub(crate) fn new() -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+            started_at: Instant::now(),
+            total_files: AtomicUsize::new(0),
+            files_processed: AtomicUsize::new(0),
+            total_bytes: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            records_kept: AtomicU64::new(0),
+            records_dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_total(&self, files: usize, bytes: u64) {
+        self.total_files.store(files, Ordering::SeqCst);
+        self.total_bytes.store(bytes, Ordering::SeqCst);
+    }
+
+    pub(crate) fn file_processed(&self) {
+        self.files_processed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn record_kept(&self, read: usize, written: usize) {
+        self.bytes_read.fetch_add(read as u64, Ordering::SeqCst);
+        self.bytes_written.fetch_add(written as u64, Ordering::SeqCst);
+        self.records_kept.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn record_dropped(&self, read: usize) {
+        self.bytes_read.fetch_add(read as u64, Ordering::SeqCst);
+        self.records_dropped.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn snapshot(&self) -> MergeProgress {
+        MergeProgress {
+            total_files: self.total_files.load(Ordering::SeqCst),
+            files_processed: self.files_processed.load(Ordering::SeqCst),
+            total_bytes: self.total_bytes.load(Ordering::SeqCst),
+            bytes_read: self.bytes_read.load(Ordering::SeqCst),
+            bytes_written: self.bytes_written.load(Ordering::SeqCst),
+            records_kept: self.records_kept.load(Ordering::SeqCst),
+            records_dropped: self.records_dropped.load(Ordering::SeqCst),
+            elapsed: self.started_at.elapsed(),
+        }
+    }
+}
+
+/// 后台 merge 任务的句柄，可以查询进度或者取消 merge
+pub struct MergeHandle {
+    state: Arc<MergeState>,
+    worker: JoinHandle<AppResult<()>>,
+}
+
+impl MergeHandle {
+    /// 获取当前的 merge 进度
+    pub fn progress(&self) -> MergeProgress {
+        self.state.snapshot()
+    }
+
+    /// 估算 merge 剩余时间
+    pub fn eta(&self) -> Option<Duration> {
+        self.progress().eta()
+    }
+
+    /// 取消 merge，merge 线程会删除未完成的 merge 目录后退出
+    pub fn cancel(&self) {
+        self.state.cancel();
+    }
+
+    /// merge 线程是否已经结束
+    pub fn is_finished(&self) -> bool {
+        self.worker.is_finished()
+    }
+
+    /// 阻塞等待 merge 结束并返回结果，被取消时返回 MergeCancelled
+    pub fn wait(self) -> AppResult<()> {
+        match self.worker.join() {
+            Ok(res) => res,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+}
+
+impl Engine {
+    /// 在后台线程中执行 merge，返回可以查询进度和取消的句柄
+    pub fn start_merge(self: &Arc<Self>) -> MergeHandle {
+        let state = Arc::new(MergeState::new());
+        let engine = self.clone();
+        let merge_state = state.clone();
+        let worker = thread::spawn(move || engine.merge_with_state(&merge_state));
+        MergeHandle { state, worker }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_progress_eta() {
+        let mut progress = MergeProgress::default();
+        assert!(progress.eta().is_none());
+
+        progress.total_bytes = 300;
+        progress.bytes_read = 100;
+        progress.elapsed = Duration::from_secs(10);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(20)));
+
+        progress.bytes_read = 300;
+        assert_eq!(progress.eta(), Some(Duration::from_secs(0)));
+    }
+}
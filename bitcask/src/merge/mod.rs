@@ -1,32 +1,42 @@
 pub mod engine;
 pub mod utils;
+pub mod merge_handle;
 
 use std::{fs, path::PathBuf, sync::atomic::Ordering};
 use crate::options::options::Options;
 use crate::errors::{AppResult, AppErrors};
 use crate::db::engine::Engine;
+use crate::db::utils::{data_file_io_type, sealed_file_io_type};
+use crate::db::index_snapshot::INDEX_SNAPSHOT_FILE_NAME;
+use crate::index::hybrid::mark_hybrid_index_stale;
 use crate::data::data_files_mod::data_file::DataFile;
 use crate::data::data_files_mod::utils::{get_data_file_name, DATA_FILE_NAME_SUFFIX, HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME};
 use crate::data::log_record_mod::log_record::LogRecord;
+use crate::data::log_record_mod::log_record_pos::LogRecordPos;
+use crate::options::compaction_filter::CompactionDecision;
+use self::merge_handle::MergeState;
+use self::utils::{get_merge_path, MERGE_FIN_KEY};
 
 use log::error;
-use crate::{
-    batch::{utils, parse_log_record_key, NON_TRANSACTION_SEQ_NO},
-    data::{
-        log_record::{decode_log_record_pos, LogRecordType},
-    },
-    db::{Engine, FILE_LOCK_NAME},
-    options::{IOType, Options},
-    util,
-};
+use crate::batch::{log_record_key_with_seq, parse_log_record_key, NON_TRANSACTION_SEQ_NO};
+use crate::data::log_record_mod::decode_log_record_pos;
+use crate::data::log_record_mod::log_record_type::LogRecordType;
+use crate::db::engine::FILE_LOCK_NAME;
+use crate::utils::file::{available_disk_size, dir_disk_size};
 
 // TODO 逐步拆解,理解,梳理
-const MERGE_DIR_NAME: &str = "merge";
-const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
 
 impl Engine {
     // merge 数据目录，处理无效数据，并生成 hint 索引文件
     pub fn merge(&self) -> AppResult<()> {
+        self.merge_with_state(&MergeState::new())
+    }
+
+    // 执行 merge，并将进度记录到 state 中，state 被取消时清理 merge 目录后退出
+    pub(crate) fn merge_with_state(&self, state: &MergeState) -> AppResult<()> {
+        // 索引在后台加载时，需要根据完整的索引判断数据是否有效
+        self.wait_ready()?;
+
         // 如果是空的数据库则直接返回
         if self.is_empty_engine() {
             return Ok(());
@@ -40,21 +50,40 @@ impl Engine {
 
         // 判断是否达到了 merge 的比例阈值
         let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
-        let total_size = util::file::dir_disk_size(self.options.dir_path.clone());
+        let total_size = dir_disk_size(self.options.dir_path.clone());
         if (reclaim_size as f32 / total_size as f32) < self.options.data_file_merge_ratio {
             return Err(AppErrors::MergeRatioUnreached);
         }
 
-        // 判断磁盘剩余空间是否足够容纳 merge 之后的数据
-        let available_size = util::file::available_disk_size();
-        if total_size - reclaim_size as u64 >= available_size {
+        // 判断 merge 目录所在磁盘的剩余空间是否足够容纳 merge 之后的数据，并保留一定的空间
+        // merge 目录和数据目录同级，可能挂载在不同的磁盘上，目录不存在时按照其上级目录计算
+        let available_size = match available_disk_size(get_merge_path(self.options.dir_path.clone())) {
+            Ok(size) => size,
+            Err(e) => {
+                error!("failed to get available disk size: {}", e);
+                return Err(AppErrors::FailedToGetDiskSpace);
+            }
+        };
+        let merged_size = total_size.saturating_sub(reclaim_size as u64);
+        if merged_size + self.options.disk_reserve_size >= available_size {
             return Err(AppErrors::MeregeNoEnoughSpace);
         }
 
+        // 获取所有需要进行 merge 的数据文件
+        let merge_files = {
+            // 溢写但尚未提交的批次数据没有索引，merge 会将其丢弃，需要等待批次提交之后再 merge
+            // 检查和切换活跃文件期间持有写锁，之后溢写的数据只会写入新的活跃文件，不会参与本次 merge
+            let _write_guard = self.write_lock.write();
+            if self.spilled_batches.load(Ordering::SeqCst) > 0 {
+                return Err(AppErrors::WriteBatchSpilling);
+            }
+            self.rotate_merge_files()?
+        };
+
         let merge_path = get_merge_path(self.options.dir_path.clone());
         // 如果目录已经存在，则先删除
         if merge_path.is_dir() {
-            fs::remove_dir_all(merge_path.clone()).unwrap();
+            remove_merge_dir(merge_path.clone())?;
         }
         // 创建 merge 数据目录
         if let Err(e) = fs::create_dir_all(merge_path.clone()) {
@@ -62,13 +91,15 @@ impl Engine {
             return Err(AppErrors::FailedToCreateDatabaseDir);
         }
 
-        // 获取所有需要进行 merge 的数据文件
-        let merge_files = self.rotate_merge_files()?;
+        let total_bytes = merge_files.iter().map(|f| f.file_size()).sum();
+        state.set_total(merge_files.len(), total_bytes);
 
         // 打开临时用于 merge 的 bitcask 实例
         let mut merge_db_opts: Options = Options::default();
         merge_db_opts.dir_path = merge_path.clone();
         merge_db_opts.data_file_size = self.options.data_file_size;
+        merge_db_opts.disk_reserve_size = self.options.disk_reserve_size;
+        merge_db_opts.direct_io = self.options.direct_io;
         let merge_db = Engine::open(merge_db_opts)?;
 
         // 打开 hint 文件存储索引
@@ -77,6 +108,13 @@ impl Engine {
         for data_file in merge_files.iter() {
             let mut offset = 0;
             loop {
+                // merge 被取消，删除未完成的 merge 目录
+                if state.is_cancelled() {
+                    std::mem::drop(merge_db);
+                    std::mem::drop(hint_file);
+                    return cancel_merge(merge_path);
+                }
+
                 let (mut log_record, size) = match data_file.read_log_record(offset) {
                     Ok(result) => (result.record, result.size),
                     Err(e) => {
@@ -86,28 +124,62 @@ impl Engine {
                         return Err(e);
                     }
                 };
+                // 读取数据文件受后台 IO 限速控制，避免 merge 抢占前台请求的磁盘带宽
+                self.io_limiter.acquire(size);
 
                 // 解码拿到实际的 key
                 let (real_key, _) = parse_log_record_key(log_record.key.clone());
-                if let Some(index_pos) = self.index.get(real_key.clone()) {
+                if let Some(index_pos) = self.index.get(real_key.clone())? {
                     // 如果文件 id 和偏移 offset 均相等，则说明是一条有效的数据
                     if index_pos.file_id == data_file.get_file_id() && index_pos.offset == offset {
-                        // 去除事务的标识
-                        log_record.key =
-                            log_record_key_with_seq(real_key.clone(), NON_TRANSACTION_SEQ_NO);
-                        let log_record_pos = merge_db.append_log_record(&mut log_record)?;
-                        // 写 hint 索引
-                        hint_file.write_hint_record(real_key.clone(), log_record_pos)?;
+                        // 交给压缩过滤器决定保留、删除还是改写该数据
+                        let decision = match &self.options.compaction_filter {
+                            Some(filter) => filter.filter(&real_key, &log_record.value),
+                            None => CompactionDecision::Keep,
+                        };
+                        match decision {
+                            CompactionDecision::Keep => {
+                                // 去除事务的标识
+                                log_record.key =
+                                    log_record_key_with_seq(real_key.clone(), NON_TRANSACTION_SEQ_NO);
+                                self.io_limiter.acquire(size);
+                                let log_record_pos = merge_db.append_log_record(&mut log_record)?;
+                                // 写 hint 索引，hint 记录的大小近似为 key 加上位置信息的编码
+                                let hint_size = real_key.len() + log_record_pos.encode().len();
+                                self.io_limiter.acquire(hint_size);
+                                hint_file.write_hint_record(real_key.clone(), log_record_pos)?;
+                                state.record_kept(size, log_record_pos.size as usize + hint_size);
+                            }
+                            CompactionDecision::Remove => {
+                                self.compaction_remove(real_key, index_pos)?;
+                                state.record_dropped(size);
+                            }
+                            CompactionDecision::Replace(value) => {
+                                let written = self.compaction_replace(real_key, value, index_pos)?;
+                                state.record_kept(size, written);
+                            }
+                        }
+                        offset += size as u64;
+                        continue;
                     }
                 }
+                state.record_dropped(size);
                 offset += size as u64;
             }
+            state.file_processed();
         }
 
         // sync 保证持久化
         merge_db.sync()?;
         hint_file.sync()?;
 
+        // 写入 merge 完成标识之前再检查一次，保证取消后不会留下 merge-finished 文件
+        if state.is_cancelled() {
+            std::mem::drop(merge_db);
+            std::mem::drop(hint_file);
+            return cancel_merge(merge_path);
+        }
+
         // 拿到最近未参与 merge 的文件 id
         let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
         let merge_fin_file = DataFile::new_merge_fin_file(merge_path.clone())?;
@@ -123,10 +195,63 @@ impl Engine {
         Ok(())
     }
 
+    // 压缩过滤器删除的数据：在活跃文件中写入墓碑值并删除索引，
+    // 这样 merge 生效前后该 key 都不可见，且不会被未参与 merge 的旧数据恢复
+    // 检查索引、写入墓碑值和删除索引期间持有写锁，用户的写入不会插入其中而被覆盖
+    fn compaction_remove(&self, key: Vec<u8>, pos: LogRecordPos) -> AppResult<()> {
+        let _write_guard = self.write_lock.write();
+        // 索引已经被用户的新写入更新，则以用户的写入为准
+        if !self.index_points_to(&key, &pos)? {
+            return Ok(());
+        }
+
+        let mut record = LogRecord {
+            key: log_record_key_with_seq(key.clone(), NON_TRANSACTION_SEQ_NO),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+        };
+        let del_pos = self.append_log_record(&mut record)?;
+        self.reclaim_size
+            .fetch_add(del_pos.size as usize, Ordering::SeqCst);
+
+        if let Some(old_pos) = self.index.delete(key)? {
+            self.reclaim_size
+                .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    // 压缩过滤器改写的数据：将新值追加到活跃文件并更新索引，返回写入的字节数
+    // 和 compaction_remove 一样，整个过程持有写锁
+    fn compaction_replace(&self, key: Vec<u8>, value: Vec<u8>, pos: LogRecordPos) -> AppResult<usize> {
+        let _write_guard = self.write_lock.write();
+        if !self.index_points_to(&key, &pos)? {
+            return Ok(0);
+        }
+
+        let mut record = LogRecord {
+            key: log_record_key_with_seq(key.clone(), NON_TRANSACTION_SEQ_NO),
+            value,
+            rec_type: LogRecordType::NORMAL,
+        };
+        let new_pos = self.append_log_record(&mut record)?;
+        if let Some(old_pos) = self.index.put(key, new_pos)? {
+            self.reclaim_size
+                .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        }
+        Ok(new_pos.size as usize)
+    }
+
+    // 判断 key 的索引是否仍然指向 merge 读取到的位置
+    fn index_points_to(&self, key: &[u8], pos: &LogRecordPos) -> AppResult<bool> {
+        let index_pos = self.index.get(key.to_vec())?;
+        Ok(index_pos.is_some_and(|index_pos| index_pos.file_id == pos.file_id && index_pos.offset == pos.offset))
+    }
+
     fn is_empty_engine(&self) -> bool {
         let active_file = self.active_file.read();
         let older_files = self.older_files.read();
-        active_file.get_write_off() == 0 && older_files.len() == 0
+        active_file.get_write_off() == 0 && older_files.is_empty()
     }
 
     fn rotate_merge_files(&self) -> AppResult<Vec<DataFile>> {
@@ -139,21 +264,25 @@ impl Engine {
 
         // 设置一个新的活跃文件用于写入
         let mut active_file = self.active_file.write();
-        // sync 数据文件保证持久性
+        // 截断预分配的空间，sync 数据文件保证持久性
+        active_file.truncate_to_write_off()?;
         active_file.sync()?;
         let active_file_id = active_file.get_file_id();
         let new_active_file = DataFile::new(
             self.options.dir_path.clone(),
             active_file_id + 1,
-            IOType::StandardFIO,
+            data_file_io_type(&self.options),
         )?;
+        if self.options.preallocate {
+            new_active_file.preallocate(self.options.data_file_size)?;
+        }
         *active_file = new_active_file;
 
         // 加到旧的数据文件当中
         let old_file = DataFile::new(
             self.options.dir_path.clone(),
             active_file_id,
-            IOType::StandardFIO,
+            sealed_file_io_type(&self.options),
         )?;
         older_files.insert(active_file_id, old_file);
 
@@ -166,49 +295,60 @@ impl Engine {
         let mut merge_files = Vec::new();
         for file_id in merge_file_ids.iter() {
             let data_file =
-                DataFile::new(self.options.dir_path.clone(), *file_id, IOType::StandardFIO)?;
+                DataFile::new(self.options.dir_path.clone(), *file_id, data_file_io_type(&self.options))?;
             merge_files.push(data_file);
         }
         Ok(merge_files)
     }
+}
 
-    /// 从 hint 索引文件中加载索引
-    pub(crate) fn load_index_from_hint_file(&self) -> AppResult<()> {
-        let hint_file_name = self.options.dir_path.join(HINT_FILE_NAME);
-        // 如果 hint 文件不存在则返回
-        if !hint_file_name.is_file() {
-            return Ok(());
-        }
+/// 从 hint 索引文件中加载索引，依次将 key 和位置信息交给 f 处理
+pub(crate) fn load_index_from_hint_file<F>(dir_path: PathBuf, mut f: F) -> AppResult<()>
+where
+    F: FnMut(Vec<u8>, LogRecordPos) -> AppResult<()>,
+{
+    let hint_file_name = dir_path.join(HINT_FILE_NAME);
+    // 如果 hint 文件不存在则返回
+    if !hint_file_name.is_file() {
+        return Ok(());
+    }
 
-        let hint_file = DataFile::new_hint_file(self.options.dir_path.clone())?;
-        let mut offset = 0;
-        loop {
-            let (log_record, size) = match hint_file.read_log_record(offset) {
-                Ok(result) => (result.record, result.size),
-                Err(e) => {
-                    if e == AppErrors::ReadDataFileEOF {
-                        break;
-                    }
-                    return Err(e);
+    let hint_file = DataFile::new_hint_file(dir_path)?;
+    let mut offset = 0;
+    loop {
+        let (log_record, size) = match hint_file.read_log_record(offset) {
+            Ok(result) => (result.record, result.size),
+            Err(e) => {
+                if e == AppErrors::ReadDataFileEOF {
+                    break;
                 }
-            };
+                return Err(e);
+            }
+        };
 
-            // 解码 value，拿到位置索引信息
-            let log_record_pos = decode_log_record_pos(log_record.value);
-            // 存储到内存索引中
-            self.index.put(log_record.key, log_record_pos);
-            offset += size as u64;
-        }
-        Ok(())
+        // 解码 value，拿到位置索引信息
+        let log_record_pos = decode_log_record_pos(log_record.value)?;
+        f(log_record.key, log_record_pos)?;
+        offset += size as u64;
+    }
+    Ok(())
+}
+
+// 删除临时的 merge 目录
+fn remove_merge_dir(merge_path: PathBuf) -> AppResult<()> {
+    if let Err(e) = fs::remove_dir_all(merge_path) {
+        error!("failed to remove merge path {}", e);
+        return Err(AppErrors::FailedToRemoveMergeDir);
     }
+    Ok(())
 }
 
-// 获取临时的用于 merge 的数据目录
-fn get_merge_path(dir_path: PathBuf) -> PathBuf {
-    let file_name = dir_path.file_name().unwrap();
-    let merge_name = std::format!("{}-{}", file_name.to_str().unwrap(), MERGE_DIR_NAME);
-    let parent = dir_path.parent().unwrap();
-    parent.to_path_buf().join(merge_name)
+// 取消 merge，删除临时的 merge 目录
+fn cancel_merge(merge_path: PathBuf) -> AppResult<()> {
+    if let Err(e) = fs::remove_dir_all(merge_path) {
+        error!("failed to remove merge path {}", e);
+    }
+    Err(AppErrors::MergeCancelled)
 }
 
 // 加载 merge 数据目录
@@ -230,46 +370,55 @@ pub(crate) fn load_merge_files(dir_path: PathBuf) -> AppResult<()> {
     // 查找是否有标识 merge 完成的文件
     let mut merge_file_names = Vec::new();
     let mut merge_finished = false;
-    for file in dir {
-        if let Ok(entry) = file {
-            let file_os_str = entry.file_name();
-            let file_name = file_os_str.to_str().unwrap();
+    for entry in dir.flatten() {
+        let file_os_str = entry.file_name();
+        let file_name = file_os_str.to_str().unwrap();
 
-            if file_name.ends_with(MERGE_FINISHED_FILE_NAME) {
-                merge_finished = true;
-            }
-            if file_name.ends_with(SEQ_NO_FILE_NAME) {
-                continue;
-            }
-            if file_name.ends_with(FILE_LOCK_NAME) {
-                continue;
-            }
-            // 数据文件容量为空则跳过
-            let meta = entry.metadata().unwrap();
-            if file_name.ends_with(DATA_FILE_NAME_SUFFIX) && meta.len() == 0 {
-                continue;
+        if file_name.ends_with(MERGE_FINISHED_FILE_NAME) {
+            merge_finished = true;
+        }
+        if file_name.ends_with(SEQ_NO_FILE_NAME) {
+            continue;
+        }
+        if file_name.ends_with(FILE_LOCK_NAME) {
+            continue;
+        }
+        // 数据文件容量为空则跳过
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                error!("failed to read merge file metadata: {}", e);
+                return Err(AppErrors::FailedToReadDatabaseDir);
             }
-            merge_file_names.push(entry.file_name());
+        };
+        if file_name.ends_with(DATA_FILE_NAME_SUFFIX) && meta.len() == 0 {
+            continue;
         }
+        merge_file_names.push(entry.file_name());
     }
 
     // merge 没有完成，直接返回
     if !merge_finished {
-        fs::remove_dir_all(merge_path.clone()).unwrap();
-        return Ok(());
+        return remove_merge_dir(merge_path);
     }
 
     // 打开标识 merge 完成的文件，取出未参与 merge 的文件 id
     let merge_fin_file = DataFile::new_merge_fin_file(merge_path.clone())?;
     let merge_fin_record = merge_fin_file.read_log_record(0)?;
-    let v = String::from_utf8(merge_fin_record.record.value).unwrap();
-    let non_merge_fid = v.parse::<u32>().unwrap();
+    let non_merge_fid = match String::from_utf8(merge_fin_record.record.value)
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+    {
+        Some(fid) => fid,
+        None => return Err(AppErrors::DataDirectoryCorrupted),
+    };
 
     // 将旧的数据文件删除
     for file_id in 0..non_merge_fid {
         let file = get_data_file_name(dir_path.clone(), file_id);
-        if file.is_file() {
-            fs::remove_file(file).unwrap();
+        if file.is_file() && let Err(e) = fs::remove_file(file) {
+            error!("failed to remove merged data file: {}", e);
+            return Err(AppErrors::FailedToMoveMergeFiles);
         }
     }
 
@@ -277,18 +426,29 @@ pub(crate) fn load_merge_files(dir_path: PathBuf) -> AppResult<()> {
     for file_name in merge_file_names {
         let src_path = merge_path.join(file_name.clone());
         let dest_path = dir_path.join(file_name.clone());
-        fs::rename(src_path, dest_path).unwrap();
+        if let Err(e) = fs::rename(src_path, dest_path) {
+            error!("failed to move merge file: {}", e);
+            return Err(AppErrors::FailedToMoveMergeFiles);
+        }
+    }
+
+    // 数据文件被替换之后，索引快照中的位置信息已经失效
+    let snapshot_path = dir_path.join(INDEX_SNAPSHOT_FILE_NAME);
+    if snapshot_path.is_file() && let Err(e) = fs::remove_file(snapshot_path) {
+        error!("failed to remove index snapshot: {}", e);
+        return Err(AppErrors::FailedToMoveMergeFiles);
     }
+    mark_hybrid_index_stale(&dir_path)?;
 
     // 最后删除临时 merge 的目录
-    fs::remove_dir_all(merge_path.clone()).unwrap();
-    Ok(())
+    remove_merge_dir(merge_path)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::util::rand_kv::{get_test_key, get_test_value};
+    use crate::options::compaction_filter::CompactionFilter;
+    use crate::utils::rand_kv::{get_test_key, get_test_value};
     use bytes::Bytes;
     use std::{sync::Arc, thread};
 
@@ -333,7 +493,7 @@ mod tests {
 
         for i in 0..50000 {
             let get_res = engine2.get(get_test_key(i));
-            assert!(get_res.ok().unwrap().len() > 0);
+            assert!(!get_res.ok().unwrap().is_empty());
         }
 
         // 删除测试的文件夹
@@ -470,4 +630,146 @@ mod tests {
         // 删除测试的文件夹
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
+
+    #[test]
+    fn test_merge_handle_progress_and_cancel() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-6");
+        opts.data_file_size = 32 * 1024 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..50000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..10000 {
+            let del_res = engine.delete(get_test_key(i));
+            assert!(del_res.is_ok());
+        }
+        let eng = Arc::new(engine);
+
+        // 处理第一个数据文件之前就被取消，返回 MergeCancelled，并且 merge 目录被删除
+        let state1 = MergeState::new();
+        state1.cancel();
+        let res1 = eng.merge_with_state(&state1);
+        assert_eq!(AppErrors::MergeCancelled, res1.err().unwrap());
+        assert_eq!(state1.snapshot().files_processed, 0);
+        assert!(!get_merge_path(opts.dir_path.clone()).is_dir());
+        assert_eq!(eng.list_keys().unwrap().len(), 40000);
+
+        // 正常完成的 merge，进度与数据量一致
+        let handle2 = eng.start_merge();
+        while !handle2.is_finished() {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let progress = handle2.progress();
+        assert!(handle2.wait().is_ok());
+        assert_eq!(progress.files_processed, progress.total_files);
+        assert_eq!(progress.records_kept, 40000);
+        assert_eq!(progress.records_dropped, 20000);
+        assert_eq!(progress.bytes_read, progress.total_bytes);
+
+        // 重启校验
+        std::mem::drop(eng);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine2.list_keys().unwrap();
+        assert_eq!(keys.len(), 40000);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    // 以 0 结尾的 key 删除，以 1 结尾的 key 改写 value
+    struct TestFilter;
+
+    impl CompactionFilter for TestFilter {
+        fn filter(&self, key: &[u8], _value: &[u8]) -> CompactionDecision {
+            match key.last() {
+                Some(b'0') => CompactionDecision::Remove,
+                Some(b'1') => CompactionDecision::Replace(b"filtered value".to_vec()),
+                _ => CompactionDecision::Keep,
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_compaction_filter() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-7");
+        opts.data_file_size = 32 * 1024 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.compaction_filter = Some(Arc::new(TestFilter));
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..50000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+
+        // merge 之后立即生效
+        assert_eq!(engine.list_keys().unwrap().len(), 45000);
+        assert_eq!(AppErrors::KeyNotFound, engine.get(get_test_key(10)).err().unwrap());
+        assert_eq!(Bytes::from("filtered value"), engine.get(get_test_key(11)).unwrap());
+        assert_eq!(get_test_value(12), engine.get(get_test_key(12)).unwrap());
+
+        // 重启校验
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().unwrap().len(), 45000);
+        assert_eq!(AppErrors::KeyNotFound, engine2.get(get_test_key(10)).err().unwrap());
+        assert_eq!(Bytes::from("filtered value"), engine2.get(get_test_key(11)).unwrap());
+        assert_eq!(get_test_value(12), engine2.get(get_test_key(12)).unwrap());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_compaction_replace_race() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-replace-race");
+        opts.data_file_size = 32 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // compaction_replace 持有写锁期间，用户写入需要等待，不会在检查索引和更新索引之间写入
+        let key = get_test_key(1);
+        assert!(engine.put(key.clone(), get_test_value(0)).is_ok());
+        let pos = engine.index.get(key.to_vec()).unwrap().unwrap();
+        let write_guard = engine.write_lock.write();
+        thread::scope(|s| {
+            let handle = s.spawn(|| engine.put(key.clone(), get_test_value(1)));
+            thread::sleep(std::time::Duration::from_millis(100));
+            assert!(!handle.is_finished());
+            assert_eq!(engine.index.get(key.to_vec()).unwrap().unwrap().offset, pos.offset);
+            drop(write_guard);
+            assert!(handle.join().unwrap().is_ok());
+        });
+
+        // 压缩过滤器改写和用户写入同时进行，用户写入的较新的数据不能被改写覆盖
+        for i in 0..500 {
+            let pos = engine.index.get(key.to_vec()).unwrap().unwrap();
+            let barrier = std::sync::Barrier::new(2);
+            thread::scope(|s| {
+                s.spawn(|| {
+                    barrier.wait();
+                    engine
+                        .compaction_replace(key.to_vec(), b"filtered value".to_vec(), pos)
+                        .unwrap();
+                });
+                s.spawn(|| {
+                    barrier.wait();
+                    engine.put(key.clone(), get_test_value(i)).unwrap();
+                });
+            });
+            assert_eq!(get_test_value(i), engine.get(key.clone()).unwrap());
+        }
+
+        // 删除测试的文件夹
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
 }
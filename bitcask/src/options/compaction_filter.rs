@@ -0,0 +1,16 @@
+/// 压缩过滤器对一条有效记录的处理结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompactionDecision {
+    /// 保留原数据
+    Keep,
+    /// 删除该数据
+    Remove,
+    /// 使用新的 value 替换原数据
+    Replace(Vec<u8>),
+}
+
+/// 压缩过滤器，merge 时对每一条有效记录调用，可以按照业务规则删除或者改写数据
+pub trait CompactionFilter: Sync + Send {
+    /// 传入实际的 key 和 value，返回对该记录的处理方式
+    fn filter(&self, key: &[u8], value: &[u8]) -> CompactionDecision;
+}
@@ -0,0 +1,5 @@
+/// 启动时加载索引的进度回调，每重放完一个数据文件调用一次
+pub trait IndexLoadProgress: Sync + Send {
+    /// 传入已经加载完成的数据文件数量，以及需要加载的数据文件总数
+    fn on_file_loaded(&self, files_loaded: usize, total_files: usize);
+}
@@ -6,4 +6,12 @@ pub enum IndexType {
     SkipList,
     /// B+树索引，将索引存储到磁盘上
     BPlusTree,
+    /// 哈希索引，只适合点查，迭代时需要对所有的 key 排序
+    Hash,
+    /// 自适应基数树索引，压缩 key 的公共前缀以节省内存
+    ART,
+    /// 分片的 BTree 索引，每个分片有独立的锁，适合高并发写入
+    ShardedBTree,
+    /// 混合索引，最近访问的 key 缓存在内存中，完整的索引存储在磁盘上的 B+ 树中
+    Hybrid,
 }
@@ -4,4 +4,8 @@ pub enum IOType {
     StandardFIO,
     // 内存文件映射
     MemoryMap,
+    // 绕过页缓存的 O_DIRECT 文件 IO
+    DirectIO,
+    // 通过 io_uring 批量提交读请求的文件 IO，需要开启 io-uring feature
+    IoUring,
 }
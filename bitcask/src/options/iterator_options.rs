@@ -1,9 +1,15 @@
 use std::default::Default;
+use std::ops::Bound;
 
 /// 索引迭代器配置项
+#[derive(Clone)]
 pub struct IteratorOptions {
     pub prefix: Vec<u8>,
     pub reverse: bool,
+    pub lower_bound: Bound<Vec<u8>>, // 迭代范围的下界，和迭代方向无关，可以包含或者不包含该 key
+    pub upper_bound: Bound<Vec<u8>>, // 迭代范围的上界，和迭代方向无关，可以包含或者不包含该 key
+    pub limit: Option<usize>,        // 每次 rewind 或 seek 之后最多返回的数据条数，None 表示不限制
+    pub keys_only: bool,             // 只需要 key，磁盘上的索引不读取位置信息，返回的位置信息不能用来读取数据
 }
 
 /// Default::default()
@@ -12,6 +18,119 @@ impl Default for IteratorOptions {
         Self {
             prefix: Default::default(),
             reverse: false,
+            lower_bound: Bound::Unbounded,
+            upper_bound: Bound::Unbounded,
+            limit: None,
+            keys_only: false,
         }
     }
 }
+
+impl IteratorOptions {
+    /// 迭代范围内最小的 key 不会小于这个 key，即前缀和下界中较大的一个
+    pub(crate) fn start_key(&self) -> Vec<u8> {
+        match &self.lower_bound {
+            Bound::Included(key) | Bound::Excluded(key) if key > &self.prefix => key.clone(),
+            _ => self.prefix.clone(),
+        }
+    }
+
+    /// key 是否小于下界
+    pub(crate) fn below_lower(&self, key: &[u8]) -> bool {
+        match &self.lower_bound {
+            Bound::Included(lower) => key < lower.as_slice(),
+            Bound::Excluded(lower) => key <= lower.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// key 是否大于上界
+    pub(crate) fn above_upper(&self, key: &[u8]) -> bool {
+        match &self.upper_bound {
+            Bound::Included(upper) => key > upper.as_slice(),
+            Bound::Excluded(upper) => key >= upper.as_slice(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// 从小到大遍历到 key 时是否已经越过了迭代范围，之后的 key 都不在范围内
+    pub(crate) fn is_past(&self, key: &[u8]) -> bool {
+        // 前缀相同的 key 连续存放，比前缀大又不以前缀开头的 key 已经越过了前缀的范围
+        self.above_upper(key) || (!key.starts_with(&self.prefix) && key > self.prefix.as_slice())
+    }
+
+    /// key 是否在前缀和上下界的范围内
+    pub(crate) fn contains(&self, key: &[u8]) -> bool {
+        key.starts_with(&self.prefix) && !self.below_lower(key) && !self.above_upper(key)
+    }
+
+}
+
+/// 迭代器的数量限制，记录 rewind 或 seek 之后已经返回的数据条数
+pub(crate) struct IterLimit {
+    limit: Option<usize>,
+    count: usize,
+}
+
+impl IterLimit {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self { limit, count: 0 }
+    }
+
+    /// rewind 或 seek 之后重新计数
+    pub(crate) fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    /// 是否已经达到了数量限制
+    pub(crate) fn reached(&self) -> bool {
+        self.limit.is_some_and(|limit| self.count >= limit)
+    }
+
+    /// 记录返回了一条数据
+    pub(crate) fn add(&mut self) {
+        self.count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iterator_options_range() {
+        let opts = IteratorOptions {
+            prefix: "b".as_bytes().to_vec(),
+            lower_bound: Bound::Excluded("ba".as_bytes().to_vec()),
+            upper_bound: Bound::Included("bc".as_bytes().to_vec()),
+            ..Default::default()
+        };
+        assert_eq!(opts.start_key(), "ba".as_bytes().to_vec());
+        assert!(!opts.contains("a".as_bytes()));
+        assert!(!opts.contains("ba".as_bytes()));
+        assert!(opts.contains("bb".as_bytes()));
+        assert!(opts.contains("bc".as_bytes()));
+        assert!(!opts.contains("bca".as_bytes()));
+        assert!(!opts.is_past("bc".as_bytes()));
+        assert!(opts.is_past("bca".as_bytes()));
+
+        // 下界比前缀小时从前缀开始
+        let opts = IteratorOptions {
+            prefix: "b".as_bytes().to_vec(),
+            lower_bound: Bound::Included("a".as_bytes().to_vec()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(opts.start_key(), "b".as_bytes().to_vec());
+        assert!(opts.is_past("c".as_bytes()));
+        assert!(!opts.is_past("a".as_bytes()));
+
+        let mut limit = IterLimit::new(opts.limit);
+        limit.add();
+        assert!(!limit.reached());
+        limit.add();
+        assert!(limit.reached());
+        limit.reset();
+        assert!(!limit.reached());
+    }
+}
@@ -1,5 +1,9 @@
+#[allow(clippy::module_inception)]
 pub mod options;
 pub mod io_type;
 pub mod index_type;
 pub mod iterator_options;
 pub mod write_batch_options;
+pub mod compaction_filter;
+pub mod index_load_progress;
+pub mod sync_policy;
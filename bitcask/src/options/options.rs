@@ -1,6 +1,11 @@
 use std::path::PathBuf;
 use std::default::Default;
+use std::sync::Arc;
+use std::time::Duration;
 use super::index_type::IndexType;
+use super::sync_policy::SyncPolicy;
+use super::compaction_filter::CompactionFilter;
+use super::index_load_progress::IndexLoadProgress;
 
 #[derive(Clone)]
 pub struct Options {
@@ -8,16 +13,45 @@ pub struct Options {
     pub dir_path: PathBuf,
     // 数据文件大小
     pub data_file_size: u64,
-    // 是否每次写都持久化
-    pub sync_writes: bool,
-    // 累计写到多少字节后进行持久化
-    pub bytes_per_sync: usize,
+    // 数据文件的持久化策略
+    pub sync_policy: SyncPolicy,
+    // 持久化策略为 Always 时是否开启组提交，并发的写入共享同一次持久化
+    pub group_commit: bool,
+    // 组提交时等待更多写入加入同一次持久化的最长时间
+    pub group_commit_max_wait: Duration,
     // 索引类型
     pub index_type: IndexType,
+    // 混合索引在内存中缓存的 key 和位置信息最多占用的字节数
+    pub hybrid_index_memory_size: usize,
+    // 关闭数据库时是否写入索引快照，下次打开时只重放快照之后写入的数据，B+ 树索引不需要
+    pub index_snapshot: bool,
+    // 启动时并发解码数据文件的线程数，不大于 1 时在当前线程中依次加载
+    pub index_load_threads: usize,
+    // 启动时加载索引的进度回调
+    pub index_load_progress: Option<Arc<dyn IndexLoadProgress>>,
+    // 是否在后台加载索引，打开数据库时立即返回
+    // 加载期间已经加载到的 key 可以直接读取，其余的读取和所有的写入等待加载完成
+    pub lazy_open: bool,
     // 是否用 mmap 打开数据库
     pub mmap_at_startup: bool,
+    // 数据文件写满切换之后，是否将旧的数据文件通过 mmap 只读打开
+    pub mmap_sealed_files: bool,
+    // 是否在创建数据文件时预分配 data_file_size 大小的磁盘空间
+    pub preallocate: bool,
+    // 数据文件是否使用 O_DIRECT 读写，绕过内核页缓存
+    pub direct_io: bool,
+    // 数据文件是否通过 io_uring 读取，需要开启 io-uring feature
+    pub io_uring: bool,
+    // 数据文件读取的 value 缓存大小（字节），包括每条缓存的 key 和槽位的开销，0 表示不开启缓存
+    pub value_cache_size: usize,
     // 执行数据文件 merge 的阈值
     pub data_file_merge_ratio: f32,
+    // 后台任务（merge、hint 文件、备份）每秒最多读写的字节数，0 表示不限速
+    pub background_io_rate_limit: u64,
+    // 数据目录所在磁盘需要保留的空间，剩余空间低于该值时拒绝写入、merge 和备份，0 表示不检查写入
+    pub disk_reserve_size: u64,
+    // merge 时对有效记录进行过滤或改写
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
 }
 
 /// 默认配置(Default::default())
@@ -26,11 +60,25 @@ impl Default for Options {
         Self {
             dir_path: std::env::temp_dir().join("bitcask-rs"),
             data_file_size: 256 * 1024 * 1024u64, // 256MB,
-            sync_writes: false,
-            bytes_per_sync: 0usize,
+            sync_policy: SyncPolicy::Never,
+            group_commit: false,
+            group_commit_max_wait: Duration::from_micros(200),
             index_type: IndexType::BTree,
+            hybrid_index_memory_size: 64 * 1024 * 1024, // 64MB
+            index_snapshot: false,
+            index_load_threads: 1,
+            index_load_progress: None,
+            lazy_open: false,
             mmap_at_startup: true,
+            mmap_sealed_files: false,
+            preallocate: false,
+            direct_io: false,
+            io_uring: false,
+            value_cache_size: 0,
             data_file_merge_ratio: 0.5f32,
+            background_io_rate_limit: 0u64,
+            disk_reserve_size: 0u64,
+            compaction_filter: None,
         }
     }
 }
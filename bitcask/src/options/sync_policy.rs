@@ -0,0 +1,14 @@
+use std::time::Duration;
+
+/// 数据文件的持久化策略
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// 每次写入都进行持久化
+    Always,
+    /// 累计写入指定的字节数之后进行持久化
+    EveryBytes(usize),
+    /// 后台线程按照固定的时间间隔进行持久化
+    EveryInterval(Duration),
+    /// 不主动持久化，由操作系统决定何时刷盘
+    Never,
+}
@@ -3,6 +3,13 @@ pub struct WriteBatchOptions {
     // 一个批次当中的最大数据量
     pub max_batch_num: usize,
 
+    // 一个批次当中暂存的 key 和 value 的最大字节数
+    pub max_batch_size: usize,
+
+    // 暂存的数据达到该字节数时提前写入数据文件，提交时只写事务完成标识，0 表示不开启
+    // 开启后 max_batch_num 和 max_batch_size 只限制内存中暂存的数据
+    pub spill_threshold: usize,
+
     // 提交时候是否进行 sync 持久化
     pub sync_writes: bool,
 }
@@ -11,6 +18,8 @@ impl Default for WriteBatchOptions {
     fn default() -> Self {
         Self {
             max_batch_num: 10000usize,
+            max_batch_size: 64 * 1024 * 1024usize, // 64MB
+            spill_threshold: 0usize,
             sync_writes: true,
         }
     }
@@ -1,11 +1,23 @@
-use std::{fs, io, path::PathBuf};
+use std::{fs, io, path::{Path, PathBuf}};
+use std::fs::File;
+use std::io::{Read, Write};
+use super::rate_limiter::RateLimiter;
 
-// 获取磁盘剩余空间容量
-pub fn available_disk_size() -> u64 {
-    if let Ok(size) = fs2::available_space(PathBuf::from("/")) {
-        return size;
+// 拷贝文件时每次读写的缓冲区大小
+const COPY_BUF_SIZE: usize = 64 * 1024;
+
+// 获取 path 所在文件系统的剩余空间容量，path 不存在时使用最近的已存在的上级目录
+// 相对路径基于当前工作目录解析，找不到已存在的上级目录时返回错误
+pub fn available_disk_size(path: PathBuf) -> io::Result<u64> {
+    let path = std::path::absolute(path)?;
+    let mut target: &Path = path.as_path();
+    while !target.exists() {
+        target = match target.parent() {
+            Some(parent) => parent,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no existing ancestor directory")),
+        };
     }
-    0u64
+    fs2::available_space(target)
 }
 
 // 磁盘数据目录的大小
@@ -16,8 +28,8 @@ pub fn dir_disk_size(dir_path: PathBuf) -> u64 {
     0u64
 }
 
-// 拷贝数据目录
-pub fn copy_dir(src: PathBuf, dest: PathBuf, exclude: &[&str]) -> io::Result<()> {
+// 拷贝数据目录，读写的字节数受 limiter 限速
+pub fn copy_dir(src: PathBuf, dest: PathBuf, exclude: &[&str], limiter: &RateLimiter) -> io::Result<()> {
 
     if !dest.exists() {
         fs::create_dir_all(&dest)?;
@@ -33,16 +45,60 @@ pub fn copy_dir(src: PathBuf, dest: PathBuf, exclude: &[&str]) -> io::Result<()>
 
         let dest_path: PathBuf = dest.join(entry.file_name());
         if entry.file_type()?.is_dir() {
-            copy_dir(src_path, dest_path, exclude)?;
+            copy_dir(src_path, dest_path, exclude, limiter)?;
         } else {
-            fs::copy(src_path, dest_path)?;
+            copy_file(src_path, dest_path, limiter)?;
         }
     }
     Ok(())
 }
 
+// 分块拷贝单个文件，每块拷贝前先向 limiter 申请令牌
+fn copy_file(src: PathBuf, dest: PathBuf, limiter: &RateLimiter) -> io::Result<()> {
+    let mut reader: File = File::open(src)?;
+    let mut writer: File = File::create(dest)?;
+    let mut buf = vec![0u8; COPY_BUF_SIZE];
+    loop {
+        let n: usize = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        limiter.acquire(n);
+        writer.write_all(&buf[..n])?;
+    }
+    writer.sync_all()
+}
+
 #[test]
 fn test_available_disk_size() {
-    let size: u64 = available_disk_size();
+    let size: u64 = available_disk_size(std::env::temp_dir()).unwrap();
     assert!(size > 0u64);
+
+    // 目录不存在时，使用上级目录所在的文件系统
+    let size2: u64 = available_disk_size(std::env::temp_dir().join("bitcask-rs-not-exist/a/b")).unwrap();
+    assert!(size2 > 0u64);
+
+    // 相对路径基于当前工作目录解析
+    let size3: u64 = available_disk_size(PathBuf::from("bitcask-rs-not-exist/a")).unwrap();
+    assert!(size3 > 0u64);
+}
+
+#[test]
+fn test_copy_dir() {
+    let src: PathBuf = PathBuf::from("/tmp/bitcask-rs-copy-src");
+    let dest: PathBuf = PathBuf::from("/tmp/bitcask-rs-copy-dest");
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("000000000.data"), vec![1u8; 200 * 1024]).unwrap();
+    fs::write(src.join("sub").join("a.data"), b"bitcask-rs").unwrap();
+    fs::write(src.join("flock"), b"").unwrap();
+
+    let limiter = RateLimiter::new(0);
+    let res = copy_dir(src.clone(), dest.clone(), &["flock"], &limiter);
+    assert!(res.is_ok());
+    assert_eq!(fs::read(dest.join("000000000.data")).unwrap().len(), 200 * 1024);
+    assert_eq!(fs::read(dest.join("sub").join("a.data")).unwrap(), b"bitcask-rs");
+    assert!(!dest.join("flock").exists());
+
+    fs::remove_dir_all(src).unwrap();
+    fs::remove_dir_all(dest).unwrap();
 }
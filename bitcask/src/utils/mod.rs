@@ -1,2 +1,3 @@
 pub mod rand_kv;
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod rate_limiter;
\ No newline at end of file
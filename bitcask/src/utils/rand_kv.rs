@@ -16,7 +16,7 @@ pub fn get_test_value(i: usize) -> Bytes {
 #[test]
 fn test_get_test_key_value() {
     (0..=20).for_each(|i: usize| -> () {
-        assert!(get_test_key(i).len() > 0);
-        assert!(get_test_key(i).len() > 0);
+        assert!(!get_test_key(i).is_empty());
+        assert!(!get_test_key(i).is_empty());
     });
 }
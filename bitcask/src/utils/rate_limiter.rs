@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+
+// 单次睡眠的最长时间，保证运行时调整速率后能及时生效
+const MAX_WAIT_SLICE: Duration = Duration::from_millis(100);
+
+/// 令牌桶限速器，按照每秒字节数限制后台任务（merge、备份）的 IO
+pub struct RateLimiter {
+    rate: AtomicU64,          // 每秒允许的字节数，0 表示不限速
+    state: Mutex<BucketState>, // 令牌桶状态
+}
+
+struct BucketState {
+    tokens: f64,          // 当前可用的令牌数，允许为负数，表示欠账
+    last_refill: Instant, // 上一次补充令牌的时间
+}
+
+impl BucketState {
+    // 根据流逝的时间补充令牌，桶容量为一秒的流量
+    fn refill(&mut self, rate: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+    }
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            rate: AtomicU64::new(bytes_per_sec),
+            state: Mutex::new(BucketState {
+                tokens: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// 获取当前的限速值
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::SeqCst)
+    }
+
+    /// 运行时调整限速值，0 表示不限速
+    pub fn set_rate(&self, bytes_per_sec: u64) {
+        let mut state = self.state.lock();
+        state.refill(self.rate());
+        self.rate.store(bytes_per_sec, Ordering::SeqCst);
+        state.tokens = state.tokens.min(bytes_per_sec as f64);
+    }
+
+    /// 申请读写 bytes 个字节，令牌不足时阻塞等待
+    pub fn acquire(&self, bytes: usize) {
+        if bytes == 0 || self.rate() == 0 {
+            return;
+        }
+
+        // 先扣除令牌，不足的部分记为欠账，后续睡眠补齐
+        {
+            let mut state = self.state.lock();
+            state.refill(self.rate());
+            state.tokens -= bytes as f64;
+        }
+
+        loop {
+            let rate = self.rate();
+            let mut state = self.state.lock();
+            // 等待过程中取消了限速，清空欠账直接返回
+            if rate == 0 {
+                state.tokens = state.tokens.max(0f64);
+                return;
+            }
+            state.refill(rate);
+            if state.tokens >= 0f64 {
+                return;
+            }
+            let wait = Duration::from_secs_f64(-state.tokens / rate as f64).min(MAX_WAIT_SLICE);
+            drop(state);
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[test]
+fn test_rate_limiter_unlimited() {
+    let limiter = RateLimiter::new(0);
+    let start = Instant::now();
+    for _ in 0..1000 {
+        limiter.acquire(1024 * 1024);
+    }
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+#[test]
+fn test_rate_limiter_throttle() {
+    // 每秒 1MB，桶内初始有 1MB，再读 1MB 需要等待约 1 秒
+    let limiter = RateLimiter::new(1024 * 1024);
+    let start = Instant::now();
+    limiter.acquire(1024 * 1024);
+    limiter.acquire(512 * 1024);
+    limiter.acquire(512 * 1024);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(900));
+    assert!(elapsed < Duration::from_millis(1500));
+}
+
+#[test]
+fn test_rate_limiter_set_rate() {
+    let limiter = RateLimiter::new(1024);
+    limiter.acquire(1024);
+    limiter.set_rate(0);
+    assert_eq!(limiter.rate(), 0);
+
+    let start = Instant::now();
+    limiter.acquire(1024 * 1024);
+    assert!(start.elapsed() < Duration::from_millis(100));
+}